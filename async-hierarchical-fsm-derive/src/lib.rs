@@ -0,0 +1,95 @@
+//! Derive macro for `async-hierarchical-fsm` state enums
+//!
+//! Use it through the `derive` feature of `async-hierarchical-fsm`, which
+//! re-exports [`FsmState`](macro@FsmState).
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Ident, parse_macro_input};
+
+/// Derive `FsmState` for an enum of unit variants
+///
+/// Each variant may name its superstate, another variant of the same enum,
+/// with `#[superstate(Parent)]`. Variants without the attribute have no
+/// superstate.
+///
+/// ```rust,ignore
+/// #[derive(Debug, Clone, PartialEq, Eq, Hash, FsmState)]
+/// enum State {
+///     Root,
+///     #[superstate(Root)]
+///     Menu,
+///     #[superstate(Menu)]
+///     Settings,
+/// }
+/// ```
+#[proc_macro_derive(FsmState, attributes(superstate))]
+pub fn derive_fsm_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            input,
+            "FsmState can only be derived for enums",
+        ));
+    };
+
+    let mut variants = Vec::new();
+    let mut parents = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(
+                variant,
+                "FsmState variants must not have fields",
+            ));
+        }
+
+        let mut parent = None;
+        for attr in variant
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("superstate"))
+        {
+            if parent.is_some() {
+                return Err(Error::new_spanned(attr, "duplicate superstate attribute"));
+            }
+            parent = Some(attr.parse_args::<Ident>()?);
+        }
+
+        if let Some(parent) = parent {
+            if !data.variants.iter().any(|v| v.ident == parent) {
+                return Err(Error::new_spanned(
+                    &parent,
+                    format!("no variant named `{parent}` in this enum"),
+                ));
+            }
+            parents.push((variant.ident.clone(), parent));
+        }
+        variants.push(variant.ident.clone());
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let arms = parents.iter().map(|(variant, parent)| {
+        quote! { #name::#variant => ::core::option::Option::Some(#name::#parent), }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::async_hierarchical_fsm::FsmState for #name #ty_generics #where_clause {
+            const ALL: &'static [Self] = &[#(#name::#variants),*];
+
+            fn superstate(&self) -> ::core::option::Option<Self> {
+                match self {
+                    #(#arms)*
+                    _ => ::core::option::Option::None,
+                }
+            }
+        }
+    })
+}
@@ -1,12 +1,44 @@
 //! Builder pattern implementation for state machines
 
-use crate::fsm::SuperstateFn;
-use crate::{StateMachine, Stateful};
-use std::collections::HashMap;
+use crate::fsm::{
+    BoxedState, CopyableEvent, DEFAULT_MAX_QUEUED_EVENTS, EdgeGuard, Invariant,
+    SharedAsyncSuperstateFn, SharedSuperstateFn, StateAction,
+};
+use crate::history::{DEFAULT_HISTORY_CAPACITY, TransitionHistory};
+use crate::registry::{StateEntry, StateRegistry};
+use crate::table::{EventPredicate, apply_transition_table};
+use crate::{
+    BoxFuture, EventMiddleware, EventQueue, FsmError, Response, ResponseKind, SharedStateful,
+    StateMachine, Stateful,
+};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::sync::Arc;
+
+type ApplyTable<S, CTX, E> =
+    fn(&mut HashMap<S, StateEntry<S, CTX, E>>, HashMap<S, Vec<(EventPredicate<E>, S)>>);
+
+// Callbacks are kept as `Fn` behind an `Arc`, so clones of the builder can share them
+type SharedTransitionListener<S, CTX> = Arc<dyn Fn(&S, &S, &CTX) + Send + Sync>;
+type SharedEventObserver<S, E> = Arc<dyn Fn(&S, &E, &ResponseKind) + Send + Sync>;
+type SharedRejectionListener<S, E> = Arc<dyn Fn(&S, &E, &FsmError<S>) + Send + Sync>;
+type SharedGlobalHandler<S, CTX, E> =
+    Arc<dyn Fn(&E, &mut CTX) -> Option<Response<S>> + Send + Sync>;
+type SharedUnhandledHandler<S, CTX, E> = Arc<dyn Fn(&S, &E, &mut CTX) -> Response<S> + Send + Sync>;
+type SharedStateAction<CTX> = Arc<dyn Fn(&mut CTX) + Send + Sync>;
 
 /// Builder for constructing state machines
+///
+/// A builder can build more than one machine with
+/// [`build_with_context`](Self::build_with_context) or by building clones
+/// made with [`try_clone`](Self::try_clone). Handlers added with
+/// [`cloneable_state`](Self::cloneable_state) are copied into each machine,
+/// while those added with [`shared_state`](Self::shared_state) and all
+/// callbacks are shared between them. Shared handlers and callbacks are
+/// called from every machine without any locking, so they must be stateless
+/// or synchronize their own data. Handlers added with [`state`](Self::state)
+/// can be neither copied nor shared.
 pub struct StateMachineBuilder<S, CTX, E>
 where
     S: Hash + Eq + Clone + Send + Debug + 'static,
@@ -14,8 +46,37 @@ where
     CTX: Send + 'static,
 {
     context: CTX,
-    states: HashMap<S, Box<dyn Stateful<S, CTX, E> + Send + Sync>>,
-    superstate_fn: Option<SuperstateFn<S>>,
+    states: HashMap<S, StateEntry<S, CTX, E>>,
+    aliases: HashMap<S, S>,
+    initial: Option<S>,
+    // Shared so clones of the builder can reuse them
+    superstate_fn: Option<SharedSuperstateFn<S>>,
+    async_superstate_fn: Option<SharedAsyncSuperstateFn<S, CTX>>,
+    transition_listeners: Vec<SharedTransitionListener<S, CTX>>,
+    event_observers: Vec<SharedEventObserver<S, E>>,
+    rejection_listeners: Vec<SharedRejectionListener<S, E>>,
+    middleware: Vec<Box<dyn EventMiddleware<CTX, E> + Send + Sync>>,
+    global_handler: Option<SharedGlobalHandler<S, CTX, E>>,
+    unhandled_handler: Option<SharedUnhandledHandler<S, CTX, E>>,
+    edge_guards: HashMap<(S, S), EdgeGuard<CTX>>,
+    invariants: Vec<Invariant<S, CTX>>,
+    allowed_edges: HashSet<(S, S)>,
+    terminal_states: HashSet<S>,
+    error_recovery: HashMap<S, S>,
+    transition_table: HashMap<S, Vec<(EventPredicate<E>, S)>>,
+    // Set by `transition`, whose `S: Sync` bound table handlers need
+    apply_transition_table: Option<ApplyTable<S, CTX, E>>,
+    enter_actions: HashMap<S, Vec<SharedStateAction<CTX>>>,
+    exit_actions: HashMap<S, Vec<SharedStateAction<CTX>>>,
+    event_queue: Option<EventQueue<E>>,
+    max_queued_events: usize,
+    max_enter_chain: Option<usize>,
+    rng_seed: Option<u64>,
+    timeout_event: Option<CopyableEvent<E>>,
+    collect_metrics: bool,
+    history_capacity: Option<usize>,
+    #[cfg(feature = "tokio-integration")]
+    state_broadcast: Option<tokio::sync::broadcast::Sender<S>>,
 }
 
 impl<S, CTX, E> StateMachineBuilder<S, CTX, E>
@@ -29,16 +90,117 @@ where
         Self {
             context,
             states: HashMap::new(),
+            aliases: HashMap::new(),
+            initial: None,
             superstate_fn: None,
+            async_superstate_fn: None,
+            transition_listeners: Vec::new(),
+            event_observers: Vec::new(),
+            rejection_listeners: Vec::new(),
+            middleware: Vec::new(),
+            global_handler: None,
+            unhandled_handler: None,
+            edge_guards: HashMap::new(),
+            invariants: Vec::new(),
+            allowed_edges: HashSet::new(),
+            terminal_states: HashSet::new(),
+            error_recovery: HashMap::new(),
+            transition_table: HashMap::new(),
+            apply_transition_table: None,
+            enter_actions: HashMap::new(),
+            exit_actions: HashMap::new(),
+            event_queue: None,
+            max_queued_events: DEFAULT_MAX_QUEUED_EVENTS,
+            max_enter_chain: None,
+            rng_seed: None,
+            timeout_event: None,
+            collect_metrics: false,
+            history_capacity: None,
+            #[cfg(feature = "tokio-integration")]
+            state_broadcast: None,
         }
     }
 
     /// Add a state to the state machine
-    pub fn state<T>(mut self, state_id: S, state_impl: T) -> Self
+    pub fn state<T>(self, state_id: S, state_impl: T) -> Self
     where
         T: Stateful<S, CTX, E> + 'static,
     {
-        self.states.insert(state_id, Box::new(state_impl));
+        self.state_boxed(state_id, Box::new(state_impl))
+    }
+
+    /// Add a state whose handler is already boxed, such as one created by a
+    /// plugin loader
+    pub fn state_boxed(mut self, state_id: S, handler: BoxedState<S, CTX, E>) -> Self {
+        self.states.insert(state_id, StateEntry::Plain(handler));
+        self
+    }
+
+    /// Add a state whose handler can be cloned
+    ///
+    /// Like [`state`](Self::state), but lets
+    /// [`StateMachine::try_clone`] clone the handler along with the machine.
+    /// A machine can only be cloned if all of its states were added this way
+    /// or with [`shared_state`](Self::shared_state).
+    /// States given transitions with [`transition`](Self::transition) cannot
+    /// be cloned.
+    pub fn cloneable_state<T>(mut self, state_id: S, state_impl: T) -> Self
+    where
+        T: Stateful<S, CTX, E> + Clone + 'static,
+    {
+        self.states
+            .insert(state_id, StateEntry::Cloneable(Box::new(state_impl)));
+        self
+    }
+
+    /// Add a state whose handler is shared with other state machines
+    ///
+    /// Like [`state`](Self::state), but the handler is kept behind an [`Arc`],
+    /// so clones of the builder and every machine built from them use this
+    /// same instance, including clones made with [`StateMachine::try_clone`].
+    /// [`SharedStateful`] methods take `&self` and are called without any
+    /// locking, so the handler must be stateless or synchronize its own data.
+    pub fn shared_state<T>(mut self, state_id: S, state_impl: Arc<T>) -> Self
+    where
+        T: SharedStateful<S, CTX, E> + 'static,
+    {
+        self.states.insert(state_id, StateEntry::Shared(state_impl));
+        self
+    }
+
+    /// Add several states to the state machine at once
+    ///
+    /// Entries are inserted in iteration order, so if the same state id
+    /// appears more than once (or was already added via [`state`](Self::state)),
+    /// the later handler replaces the earlier one.
+    pub fn states<I>(mut self, entries: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Box<dyn Stateful<S, CTX, E> + Send + Sync>)>,
+    {
+        self.states.extend(
+            entries
+                .into_iter()
+                .map(|(state, handler)| (state, StateEntry::Plain(handler))),
+        );
+        self
+    }
+
+    /// Register `alias` as another key for the handler of `existing`
+    ///
+    /// The machine treats `alias` as a state of its own: it has its own
+    /// superstate, appears as the current state and in the transition log,
+    /// and transitions between `existing` and `alias` exit and enter as usual.
+    /// Both keys share a single handler instance, so any data it holds is
+    /// shared too. `existing` must be registered via [`state`](Self::state)
+    /// or [`states`](Self::states); aliases of aliases are not resolved.
+    pub fn state_alias(mut self, existing: S, alias: S) -> Self {
+        self.aliases.insert(alias, existing);
+        self
+    }
+
+    /// Set the state entered by [`build_and_init`](Self::build_and_init)
+    pub fn initial(mut self, state: S) -> Self {
+        self.initial = Some(state);
         self
     }
 
@@ -47,12 +209,590 @@ where
     where
         F: Fn(&S) -> Option<S> + Send + Sync + 'static,
     {
-        self.superstate_fn = Some(Box::new(func));
+        self.superstate_fn = Some(Arc::new(func));
+        self
+    }
+
+    /// Set a superstate function that resolves parents asynchronously from
+    /// the context
+    ///
+    /// Used in place of the [`superstate_fn`](Self::superstate_fn) when an
+    /// event is delegated with [`Response::Super`], so a state's parent can
+    /// depend on something that must be looked up. The synchronous superstate
+    /// function is still used to decide which states a transition exits and
+    /// enters, and for diagrams and validation.
+    pub fn async_superstate_fn<F>(mut self, func: F) -> Self
+    where
+        F: for<'a> Fn(&'a S, &'a CTX) -> BoxFuture<'a, Option<S>> + Send + Sync + 'static,
+    {
+        self.async_superstate_fn = Some(Arc::new(func));
+        self
+    }
+
+    /// Set the superstate relationship from a map of each state to its superstate
+    ///
+    /// States missing from the map have no superstate. This replaces any
+    /// function set via [`superstate_fn`](Self::superstate_fn), and vice versa.
+    pub fn superstate_map(mut self, map: HashMap<S, S>) -> Self
+    where
+        S: Sync,
+    {
+        self.superstate_fn = Some(Arc::new(move |state| map.get(state).cloned()));
+        self
+    }
+
+    /// Register a callback invoked with `(from, to, context)` after every
+    /// successful transition
+    pub fn on_transition<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&S, &S, &CTX) + Send + Sync + 'static,
+    {
+        self.transition_listeners.push(Arc::new(f));
+        self
+    }
+
+    /// Register a callback invoked with `(state, event, kind)` for every
+    /// handler consulted for an event
+    ///
+    /// During superstate delegation the callback runs once per state tried,
+    /// so an event bubbled from a leaf to its parent is observed as the
+    /// leaf's [`ResponseKind::Super`] followed by the parent's response.
+    /// Responses from the [global handler](Self::global_handler) and the
+    /// [unhandled-event fallback](Self::on_unhandled) are observed for the
+    /// active state.
+    pub fn on_event_observed<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&S, &E, &ResponseKind) + Send + Sync + 'static,
+    {
+        self.event_observers.push(Arc::new(f));
+        self
+    }
+
+    /// Register a callback invoked with `(state, event, error)` whenever
+    /// [`StateMachine::process_event`] is about to return an error
+    ///
+    /// Covers every failure, including unhandled events, guard rejections
+    /// and illegal transitions, but not errors recovered from with
+    /// [`on_error_goto`](Self::on_error_goto). `state` is the current state
+    /// once the event failed; nothing is reported before the machine is
+    /// initialized.
+    pub fn on_rejected<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&S, &E, &FsmError<S>) + Send + Sync + 'static,
+    {
+        self.rejection_listeners.push(Arc::new(f));
+        self
+    }
+
+    /// Add an event middleware, run before events reach the current state
+    ///
+    /// Middleware runs in registration order; the first rejection stops the event.
+    pub fn middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: EventMiddleware<CTX, E> + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Set a handler consulted for every event before the current state
+    ///
+    /// Acts as a handler above the top of the hierarchy that sees events
+    /// first: returning `Some(response)` processes that response as if the
+    /// current state had returned it, skipping the states' `on_event`.
+    /// Returning `None` (or `Some(Response::Super)`) dispatches the event as
+    /// usual. Middleware still runs before it.
+    pub fn global_handler<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&E, &mut CTX) -> Option<Response<S>> + Send + Sync + 'static,
+    {
+        self.global_handler = Some(Arc::new(f));
+        self
+    }
+
+    /// Set a fallback for events that no state handled
+    ///
+    /// Called with the current state once `Super` delegation runs out of
+    /// superstates. Its response is processed as if the state had returned it;
+    /// returning [`Response::Super`] keeps the default
+    /// [`FsmError::InvalidEvent`].
+    pub fn on_unhandled<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&S, &E, &mut CTX) -> Response<S> + Send + Sync + 'static,
+    {
+        self.unhandled_handler = Some(Arc::new(f));
+        self
+    }
+
+    /// Guard the transition from `from` to `to` with a check on the context
+    ///
+    /// If the guard returns `false`, the transition is rejected with
+    /// [`FsmError::EdgeGuardRejected`] before any state is exited. Registering
+    /// a second guard for the same edge replaces the first.
+    pub fn guard<F>(mut self, from: S, to: S, f: F) -> Self
+    where
+        F: Fn(&CTX) -> bool + Send + Sync + 'static,
+    {
+        self.edge_guards.insert((from, to), Arc::new(f));
+        self
+    }
+
+    /// Check the context against the current state after every transition
+    ///
+    /// Unlike guards, which run before a state is entered, invariants run
+    /// once a transition has completed, in registration order. The first to
+    /// return `Err` fails the transition with [`FsmError::InvariantViolated`];
+    /// the machine is left in the state it entered, so the error reports a
+    /// state whose invariants do not hold.
+    pub fn invariant<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&S, &CTX) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.invariants.push(Arc::new(f));
+        self
+    }
+
+    /// Declare that events matching `on` take the state machine from `from` to `to`
+    ///
+    /// Declared transitions for a state are tried in registration order before
+    /// its own handler, if any; events matching none of them go to the handler,
+    /// or bubble up to the superstate when `from` has no handler. States named
+    /// only in declared transitions need no handler at all, so simple machines
+    /// can be built from `transition` calls alone.
+    pub fn transition<F>(mut self, from: S, on: F, to: S) -> Self
+    where
+        F: Fn(&E) -> bool + Send + Sync + 'static,
+        S: Sync,
+    {
+        self.transition_table
+            .entry(from)
+            .or_default()
+            .push((Arc::new(on), to));
+        self.apply_transition_table = Some(apply_transition_table::<S, CTX, E>);
+        self
+    }
+
+    /// Declare `(from, to)` as a legal transition
+    ///
+    /// Once any edge is declared, every transition between states must be
+    /// declared, or it fails with [`FsmError::IllegalTransition`] before any
+    /// state is exited. This includes re-entering a state (`from == to`);
+    /// [`StateMachine::init`] from an uninitialized machine is not checked.
+    /// With no edges declared, all transitions are allowed.
+    pub fn allowed_edge(mut self, from: S, to: S) -> Self {
+        self.allowed_edges.insert((from, to));
+        self
+    }
+
+    /// Mark `state` as terminal
+    ///
+    /// Once the machine is in a terminal state, [`StateMachine::process_event`]
+    /// fails with [`FsmError::Terminal`] for every event without calling any
+    /// handler. [`StateMachine::init`], [`StateMachine::reset`] and
+    /// [`StateMachine::force_transition`] can still leave it.
+    pub fn terminal(mut self, state: S) -> Self {
+        self.terminal_states.insert(state);
+        self
+    }
+
+    /// Transition to `target` whenever `state` fails, instead of returning
+    /// the error
+    ///
+    /// Applies when processing an event or initializing would fail with
+    /// [`FsmError::StateInvalid`] or [`FsmError::InvalidEvent`] for `state`:
+    /// the machine transitions to `target` and the call succeeds unless that
+    /// transition fails in turn. Errors from states without a recovery target
+    /// are returned as usual. Setting a second target for `state` replaces
+    /// the first.
+    pub fn on_error_goto(mut self, state: S, target: S) -> Self {
+        self.error_recovery.insert(state, target);
+        self
+    }
+
+    /// Run `action` each time `state` is entered, after its own `on_enter`
+    ///
+    /// Useful for wiring side effects such as logging around handlers that
+    /// can't be edited. Several actions for one state run in registration order.
+    pub fn on_enter_action<F>(mut self, state: S, action: F) -> Self
+    where
+        F: Fn(&mut CTX) + Send + Sync + 'static,
+    {
+        self.enter_actions
+            .entry(state)
+            .or_default()
+            .push(Arc::new(action));
+        self
+    }
+
+    /// Run `action` each time `state` is exited, before its own `on_exit`
+    ///
+    /// Several actions for one state run in registration order.
+    pub fn on_exit_action<F>(mut self, state: S, action: F) -> Self
+    where
+        F: Fn(&mut CTX) + Send + Sync + 'static,
+    {
+        self.exit_actions
+            .entry(state)
+            .or_default()
+            .push(Arc::new(action));
+        self
+    }
+
+    /// Use an existing event queue, typically one whose handle is also stored
+    /// in the context so handlers can post follow-up events
+    pub fn event_queue(mut self, queue: EventQueue<E>) -> Self {
+        self.event_queue = Some(queue);
+        self
+    }
+
+    /// Set the maximum number of queued events processed per call
+    pub fn max_queued_events(mut self, max: usize) -> Self {
+        self.max_queued_events = max;
+        self
+    }
+
+    /// Set how many times `on_enter` (or `on_exit_checked`) may redirect a
+    /// single transition to another state
+    ///
+    /// Past this, the transition fails with [`FsmError::EnterChainTooLong`]
+    /// instead of looping forever between states that keep redirecting to
+    /// each other. Defaults to the number of registered states.
+    pub fn max_enter_chain(mut self, max: usize) -> Self {
+        self.max_enter_chain = Some(max);
+        self
+    }
+
+    /// Seed the generator that picks among the states of a
+    /// [`Response::Choice`], so the same events always make the same picks
+    ///
+    /// Without a seed, each machine is seeded randomly.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Set the event timeout drivers inject when the current state's timeout
+    /// elapses
+    ///
+    /// Only states with a timeout from [`Stateful::get_timeout`] ever time
+    /// out, and a state's own [`Stateful::timeout_event`] takes precedence.
+    /// With this set, `tokio_utils::spawn_actor` fires timeouts without any
+    /// further wiring.
+    pub fn timeout_event(mut self, event: E) -> Self
+    where
+        E: Clone,
+    {
+        self.timeout_event = Some((event, E::clone));
+        self
+    }
+
+    /// Collect per-state counters, available via [`StateMachine::metrics`]
+    pub fn collect_metrics(mut self) -> Self {
+        self.collect_metrics = true;
+        self
+    }
+
+    /// Record every transition with a timestamp, available via
+    /// [`StateMachine::history`]
+    ///
+    /// Keeps the most recent [`DEFAULT_HISTORY_CAPACITY`] records unless
+    /// changed with [`history_capacity`](Self::history_capacity).
+    pub fn with_history_log(mut self) -> Self {
+        self.history_capacity
+            .get_or_insert(DEFAULT_HISTORY_CAPACITY);
+        self
+    }
+
+    /// Record transition history like [`with_history_log`](Self::with_history_log),
+    /// keeping at most `capacity` records
+    pub fn history_capacity(mut self, capacity: usize) -> Self {
+        self.history_capacity = Some(capacity);
         self
     }
 
+    /// Publish the target of every transition on a broadcast channel
+    ///
+    /// Returns the builder along with a receiver; more can be created with
+    /// [`Receiver::resubscribe`](tokio::sync::broadcast::Receiver::resubscribe).
+    /// Receivers that fall more than `capacity` states behind miss the oldest
+    /// ones, as usual for broadcast channels.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero.
+    #[cfg(feature = "tokio-integration")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio-integration")))]
+    pub fn state_broadcast(
+        mut self,
+        capacity: usize,
+    ) -> (Self, tokio::sync::broadcast::Receiver<S>) {
+        let (sender, receiver) = tokio::sync::broadcast::channel(capacity);
+        self.state_broadcast = Some(sender);
+        (self, receiver)
+    }
+
     /// Build the state machine
-    pub fn build(self) -> StateMachine<S, CTX, E> {
-        StateMachine::new(self.context, self.states, self.superstate_fn)
+    pub fn build(mut self) -> StateMachine<S, CTX, E> {
+        self.register_transition_table();
+        let mut fsm =
+            StateMachine::with_registry(self.context, StateRegistry::new(self.states), None);
+        for (alias, existing) in self.aliases {
+            fsm.states.alias(existing, alias);
+        }
+        if let Some(superstate_fn) = self.superstate_fn {
+            fsm.superstate_fn = superstate_fn;
+        }
+        fsm.async_superstate_fn = self.async_superstate_fn;
+        fsm.transition_listeners = self
+            .transition_listeners
+            .into_iter()
+            .map(|f| Box::new(move |from: &S, to: &S, context: &CTX| f(from, to, context)) as _)
+            .collect();
+        fsm.event_observers = self
+            .event_observers
+            .into_iter()
+            .map(|f| {
+                Box::new(move |state: &S, event: &E, kind: &ResponseKind| f(state, event, kind))
+                    as _
+            })
+            .collect();
+        fsm.rejection_listeners = self
+            .rejection_listeners
+            .into_iter()
+            .map(|f| {
+                Box::new(move |state: &S, event: &E, error: &FsmError<S>| f(state, event, error))
+                    as _
+            })
+            .collect();
+        fsm.middleware = self.middleware;
+        fsm.global_handler = self
+            .global_handler
+            .map(|f| Box::new(move |event: &E, context: &mut CTX| f(event, context)) as _);
+        fsm.unhandled_handler = self.unhandled_handler.map(|f| {
+            Box::new(move |state: &S, event: &E, context: &mut CTX| f(state, event, context)) as _
+        });
+        fsm.edge_guards = self.edge_guards;
+        fsm.invariants = self.invariants;
+        fsm.allowed_edges = self.allowed_edges;
+        fsm.terminal_states = self.terminal_states;
+        fsm.error_recovery = self.error_recovery;
+        fsm.enter_actions = box_actions(self.enter_actions);
+        fsm.exit_actions = box_actions(self.exit_actions);
+        if let Some(queue) = self.event_queue {
+            fsm.event_queue = queue;
+        }
+        fsm.max_queued_events = self.max_queued_events;
+        fsm.max_enter_chain = self.max_enter_chain;
+        if let Some(seed) = self.rng_seed {
+            fsm.rng_state = seed;
+        }
+        fsm.timeout_event = self.timeout_event;
+        fsm.collect_metrics = self.collect_metrics;
+        fsm.history = TransitionHistory::new(self.history_capacity.unwrap_or(0));
+        #[cfg(feature = "tokio-integration")]
+        {
+            fsm.state_broadcast = self.state_broadcast;
+        }
+        fsm
+    }
+
+    /// Build a state machine with the given context, leaving the builder
+    /// in place so it can build more machines from the same configuration
+    ///
+    /// Handlers added with [`cloneable_state`](Self::cloneable_state) are
+    /// copied into the new machine. Handlers added with
+    /// [`shared_state`](Self::shared_state), declared transitions, callbacks,
+    /// superstate functions, edge guards, the event queue, and the state
+    /// broadcast are shared with every other machine built from this builder.
+    ///
+    /// Returns `None` if any state was added with [`state`](Self::state),
+    /// [`state_boxed`](Self::state_boxed), or [`states`](Self::states), or if
+    /// any [`middleware`](Self::middleware) is registered, since those can be
+    /// neither copied nor shared.
+    pub fn build_with_context(&self, context: CTX) -> Option<StateMachine<S, CTX, E>> {
+        self.clone_with_context(context).map(Self::build)
     }
+
+    /// Clone the builder, so each clone can build its own machine
+    ///
+    /// The clone copies and shares handlers and callbacks as described for
+    /// [`build_with_context`](Self::build_with_context), and returns `None`
+    /// under the same conditions.
+    pub fn try_clone(&self) -> Option<Self>
+    where
+        CTX: Clone,
+    {
+        self.clone_with_context(self.context.clone())
+    }
+
+    /// Copy the builder's configuration with a new context
+    fn clone_with_context(&self, context: CTX) -> Option<Self> {
+        if !self.middleware.is_empty() {
+            return None;
+        }
+        let states = self
+            .states
+            .iter()
+            .map(|(state, entry)| Some((state.clone(), entry.try_clone()?)))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            context,
+            states,
+            aliases: self.aliases.clone(),
+            initial: self.initial.clone(),
+            superstate_fn: self.superstate_fn.clone(),
+            async_superstate_fn: self.async_superstate_fn.clone(),
+            transition_listeners: self.transition_listeners.clone(),
+            event_observers: self.event_observers.clone(),
+            rejection_listeners: self.rejection_listeners.clone(),
+            middleware: Vec::new(),
+            global_handler: self.global_handler.clone(),
+            unhandled_handler: self.unhandled_handler.clone(),
+            edge_guards: self.edge_guards.clone(),
+            invariants: self.invariants.clone(),
+            allowed_edges: self.allowed_edges.clone(),
+            terminal_states: self.terminal_states.clone(),
+            error_recovery: self.error_recovery.clone(),
+            transition_table: self.transition_table.clone(),
+            apply_transition_table: self.apply_transition_table,
+            enter_actions: self.enter_actions.clone(),
+            exit_actions: self.exit_actions.clone(),
+            event_queue: self.event_queue.clone(),
+            max_queued_events: self.max_queued_events,
+            max_enter_chain: self.max_enter_chain,
+            rng_seed: self.rng_seed,
+            timeout_event: self
+                .timeout_event
+                .as_ref()
+                .map(|(event, copy)| (copy(event), *copy)),
+            collect_metrics: self.collect_metrics,
+            history_capacity: self.history_capacity,
+            #[cfg(feature = "tokio-integration")]
+            state_broadcast: self.state_broadcast.clone(),
+        })
+    }
+
+    /// Build the state machine, [`prepare`](StateMachine::prepare) it, and
+    /// initialize it in the state set with [`initial`](Self::initial)
+    ///
+    /// # Errors
+    /// Returns [`FsmError::NoInitialState`] if no initial state was set, or
+    /// any error returned by [`StateMachine::init`].
+    pub async fn build_and_init(self) -> Result<StateMachine<S, CTX, E>, FsmError<S>> {
+        let initial = self.initial.clone().ok_or(FsmError::NoInitialState)?;
+        let mut fsm = self.build();
+        fsm.prepare().await;
+        fsm.init(initial).await?;
+        Ok(fsm)
+    }
+
+    /// Build the state machine, checking the states and superstate hierarchy first
+    ///
+    /// At least one state must be registered, including the
+    /// [`initial`](Self::initial) state if one was set. Every alias must point
+    /// at a registered state, every registered state's superstate (as returned
+    /// by the superstate function) must itself be registered, and no state may
+    /// be its own ancestor.
+    ///
+    /// # Errors
+    /// Returns [`FsmError::NoStatesRegistered`] if no state was added,
+    /// [`FsmError::StateNotRegistered`] with an unregistered initial state or
+    /// the first unregistered alias target or superstate found, or
+    /// [`FsmError::SuperstateCycle`] listing the states of the first cycle found.
+    pub fn build_validated(mut self) -> Result<StateMachine<S, CTX, E>, FsmError<S>> {
+        self.register_transition_table();
+        if self.states.is_empty() {
+            return Err(FsmError::NoStatesRegistered);
+        }
+        if let Some(initial) = &self.initial
+            && !self.states.contains_key(initial)
+            && !self.aliases.contains_key(initial)
+        {
+            return Err(FsmError::StateNotRegistered(initial.clone()));
+        }
+        self.validate_hierarchy()?;
+        Ok(self.build())
+    }
+
+    /// Register handlers for the states named in declared transitions
+    fn register_transition_table(&mut self) {
+        if let Some(apply) = self.apply_transition_table.take() {
+            apply(&mut self.states, std::mem::take(&mut self.transition_table));
+        }
+    }
+
+    /// Check the superstate function against the registered states
+    fn validate_hierarchy(&self) -> Result<(), FsmError<S>> {
+        if let Some(existing) = self
+            .aliases
+            .values()
+            .find(|existing| !self.states.contains_key(existing))
+        {
+            return Err(FsmError::StateNotRegistered(existing.clone()));
+        }
+
+        // Same precedence as `StateMachine::superstate`
+        let superstate_fn = |state: &S| {
+            let handler = self
+                .aliases
+                .get(state)
+                .and_then(|existing| self.states.get(existing))
+                .or_else(|| self.states.get(state));
+            handler
+                .and_then(|entry| entry.handler().superstate())
+                .or_else(|| self.superstate_fn.as_ref().and_then(|f| f(state)))
+        };
+
+        let is_registered =
+            |state: &S| self.states.contains_key(state) || self.aliases.contains_key(state);
+        let registered = || self.states.keys().chain(self.aliases.keys());
+
+        for state in registered() {
+            if let Some(parent) = superstate_fn(state)
+                && !is_registered(&parent)
+            {
+                return Err(FsmError::StateNotRegistered(parent));
+            }
+        }
+
+        for state in registered() {
+            let mut chain = vec![state.clone()];
+            let mut visited = HashSet::from([state.clone()]);
+            let mut current = state.clone();
+
+            while let Some(parent) = superstate_fn(&current) {
+                if !visited.insert(parent.clone()) {
+                    let start = chain.iter().position(|s| *s == parent).unwrap_or(0);
+                    return Err(FsmError::SuperstateCycle(chain.split_off(start)));
+                }
+                // Backstop: a chain can never be longer than the state count
+                if chain.len() > self.states.len() + self.aliases.len() {
+                    return Err(FsmError::SuperstateCycle(chain));
+                }
+                chain.push(parent.clone());
+                current = parent;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Wrap shared enter/exit actions for a built state machine
+fn box_actions<S, CTX>(
+    actions: HashMap<S, Vec<SharedStateAction<CTX>>>,
+) -> HashMap<S, Vec<StateAction<CTX>>>
+where
+    S: Hash + Eq,
+    CTX: 'static,
+{
+    actions
+        .into_iter()
+        .map(|(state, actions)| {
+            let actions = actions
+                .into_iter()
+                .map(|action| Box::new(move |context: &mut CTX| action(context)) as _)
+                .collect();
+            (state, actions)
+        })
+        .collect()
 }
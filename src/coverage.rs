@@ -0,0 +1,16 @@
+//! Which states and transitions a state machine has exercised
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// States entered and transitions taken so far, returned by
+/// [`StateMachine::coverage`](crate::StateMachine::coverage)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage<S: Hash + Eq> {
+    /// Registered states whose `on_enter` has run at least once
+    pub entered_states: HashSet<S>,
+    /// Registered states that have never been entered
+    pub unentered_states: HashSet<S>,
+    /// The unique `(from, to)` transitions taken
+    pub taken_edges: HashSet<(S, S)>,
+}
@@ -0,0 +1,26 @@
+//! A serializable description of a state machine's structure and status
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The declared topology of a state machine together with its live status.
+///
+/// Returned by [`StateMachine::describe`](crate::StateMachine::describe) for
+/// remote debuggers and UIs. Unlike an [`FsmSnapshot`](crate::FsmSnapshot),
+/// this includes every registered state and superstate edge, not only what
+/// has run. Lists are in unspecified order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsmDescription<S> {
+    /// Every registered state
+    pub states: Vec<S>,
+    /// A `(state, superstate)` edge for each registered state that has one
+    pub superstate_edges: Vec<(S, S)>,
+    /// The active state, or `None` if the machine was not initialized
+    pub current_state: Option<S>,
+    /// The current state followed by each of its superstates, leaf first
+    pub active_states: Vec<S>,
+    /// How long the machine has been in the current state
+    pub time_in_state: Option<Duration>,
+    /// The unique `(from, to)` transitions taken so far
+    pub transition_log: Vec<(S, S)>,
+}
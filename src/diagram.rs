@@ -0,0 +1,61 @@
+//! Shared helpers for the diagram exporters
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// State names grouped by the name of their superstate (`None` for top level).
+pub(crate) type Hierarchy = BTreeMap<Option<String>, Vec<String>>;
+
+/// Collect every state mentioned by the transition log or in `extra_states`,
+/// along with all of their ancestors, grouped by superstate.
+///
+/// Names are produced by `name_fn` and sorted so output is stable.
+pub(crate) fn collect_hierarchy<'a, S>(
+    transition_log: &HashSet<(S, S)>,
+    extra_states: impl IntoIterator<Item = &'a S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+    name_fn: fn(&S) -> String,
+) -> Hierarchy
+where
+    S: Debug + Eq + Hash + Clone + 'a,
+{
+    let mut known: HashSet<S> = extra_states.into_iter().cloned().collect();
+    for (from, to) in transition_log {
+        known.insert(from.clone());
+        known.insert(to.clone());
+    }
+
+    // Pull in ancestors so composite states can be drawn
+    let mut pending: Vec<S> = known.iter().cloned().collect();
+    while let Some(state) = pending.pop() {
+        if let Some(parent) = superstate_fn(&state)
+            && known.insert(parent.clone())
+        {
+            pending.push(parent);
+        }
+    }
+
+    let mut children: Hierarchy = BTreeMap::new();
+    for state in &known {
+        let parent = superstate_fn(state).map(|p| name_fn(&p));
+        children.entry(parent).or_default().push(name_fn(state));
+    }
+    for list in children.values_mut() {
+        list.sort();
+    }
+    children
+}
+
+/// Collect the transition log as sorted `(from, to)` name pairs.
+pub(crate) fn collect_edges<S>(
+    transition_log: &HashSet<(S, S)>,
+    name_fn: fn(&S) -> String,
+) -> Vec<(String, String)> {
+    let mut edges: Vec<(String, String)> = transition_log
+        .iter()
+        .map(|(from, to)| (name_fn(from), name_fn(to)))
+        .collect();
+    edges.sort();
+    edges
+}
@@ -0,0 +1,101 @@
+//! Graphviz DOT diagram generation for state machines
+
+use crate::diagram::{Hierarchy, collect_edges, collect_hierarchy};
+use std::collections::HashSet;
+use std::fmt::{Debug, Write};
+use std::hash::Hash;
+
+/// Generate a Graphviz `digraph` from a set of states and a transition log.
+///
+/// Every state in `states` or the log gets a node, superstates are drawn as
+/// `subgraph cluster_*` groupings, and the current state (if any) is filled.
+///
+/// # Arguments
+/// * `states` - States to draw even if they never appear in the log.
+/// * `transition_log` - Unique `(from, to)` transitions to draw as edges.
+/// * `current_state` - The state to highlight, if any.
+/// * `superstate_fn` - Function returning the superstate of a state.
+///
+/// # Returns
+/// The diagram source, starting with `digraph`.
+pub fn generate_dot<'a, S>(
+    states: impl IntoIterator<Item = &'a S>,
+    transition_log: &HashSet<(S, S)>,
+    current_state: Option<&'a S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone + 'a,
+{
+    let children = collect_hierarchy(
+        transition_log,
+        states.into_iter().chain(current_state),
+        superstate_fn,
+        state_name,
+    );
+
+    let mut out = String::new();
+    out.push_str("digraph fsm {\n");
+
+    let mut cluster_id = 0;
+    write_nodes(&mut out, &children, None, &mut cluster_id, 1);
+
+    if let Some(current) = current_state {
+        let _ = writeln!(
+            out,
+            "    {} [style=filled,fillcolor=greenyellow];",
+            state_name(current)
+        );
+    }
+
+    for (from, to) in collect_edges(transition_log, state_name) {
+        let _ = writeln!(out, "    {from} -> {to};");
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Write the states that share `parent`, wrapping superstates in clusters.
+fn write_nodes(
+    out: &mut String,
+    children: &Hierarchy,
+    parent: Option<String>,
+    cluster_id: &mut usize,
+    depth: usize,
+) {
+    let Some(states) = children.get(&parent) else {
+        return;
+    };
+    let indent = "    ".repeat(depth);
+
+    for name in states {
+        if children.contains_key(&Some(name.clone())) {
+            let _ = writeln!(out, "{indent}subgraph cluster_{cluster_id} {{");
+            *cluster_id += 1;
+            let _ = writeln!(out, "{indent}    label={name};");
+            let _ = writeln!(out, "{indent}    {name};");
+            write_nodes(out, children, Some(name.clone()), cluster_id, depth + 1);
+            let _ = writeln!(out, "{indent}}}");
+        } else {
+            let _ = writeln!(out, "{indent}{name};");
+        }
+    }
+}
+
+/// Turn a state's `Debug` output into a quoted, escaped DOT identifier.
+fn state_name<S: Debug>(state: &S) -> String {
+    let mut name = String::from('"');
+    for c in format!("{state:?}").chars() {
+        match c {
+            '"' | '\\' => {
+                name.push('\\');
+                name.push(c);
+            }
+            '\n' => name.push_str("\\n"),
+            _ => name.push(c),
+        }
+    }
+    name.push('"');
+    name
+}
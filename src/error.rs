@@ -13,10 +13,26 @@ pub enum FsmError<S: Debug> {
     #[error("State machine not initialized")]
     StateMachineNotInitialized,
 
+    /// State machine has no initial state to start in or return to
+    #[error("State machine has no initial state")]
+    NoInitialState,
+
+    /// The builder has no states registered
+    #[error("No states registered")]
+    NoStatesRegistered,
+
     /// State returned an error during processing
     #[error("State {0:?} error: {1}")]
     StateInvalid(S, String),
 
+    /// State returned [`Response::Fail`](crate::Response::Fail) with a typed error
+    #[error("State {0:?} {1} error: {2}")]
+    Handler(
+        S,
+        ErrorPhase,
+        #[source] Box<dyn std::error::Error + Send + Sync>,
+    ),
+
     /// Event could not be handled by the current state or its superstates
     #[error("Invalid event in state {0:?}: {1}")]
     InvalidEvent(S, String),
@@ -29,7 +45,132 @@ pub enum FsmError<S: Debug> {
     #[error("State {0:?} on_enter cannot return Super")]
     OnEnterSuper(S),
 
+    /// The superstate function forms a cycle through the listed states
+    #[error("Superstate cycle detected: {0:?}")]
+    SuperstateCycle(Vec<S>),
+
+    /// Superstate delegation went deeper than the number of registered states
+    #[error("Superstate delegation depth exceeded in state {0:?}")]
+    SuperstateDepthExceeded(S),
+
+    /// States kept redirecting a transition from `on_enter` (or
+    /// `on_exit_checked`) past the configured limit; holds the next target
+    #[error("Transition chain too long, next target {0:?}")]
+    EnterChainTooLong(S),
+
+    /// An invariant registered with
+    /// [`StateMachineBuilder::invariant`](crate::StateMachineBuilder::invariant)
+    /// failed after a transition into the state
+    #[error("Invariant violated in state {0:?}: {1}")]
+    InvariantViolated(S, String),
+
+    /// A state's [`can_enter`](crate::Stateful::can_enter) guard refused the transition
+    #[error("Transition to state {0:?} rejected by guard")]
+    GuardRejected(S),
+
+    /// A guard registered for the `(from, to)` edge refused the transition
+    #[error("Transition from {0:?} to {1:?} rejected by guard")]
+    EdgeGuardRejected(S, S),
+
+    /// A transition not declared with
+    /// [`allowed_edge`](crate::StateMachineBuilder::allowed_edge) was attempted
+    #[error("Transition from {0:?} to {1:?} is not allowed")]
+    IllegalTransition(S, S),
+
+    /// An event was processed in a
+    /// [terminal](crate::StateMachineBuilder::terminal) state
+    #[error("State {0:?} is terminal")]
+    Terminal(S),
+
+    /// An event middleware rejected the event
+    #[error("Event rejected: {0}")]
+    Rejected(String),
+
+    /// A state machine operation did not complete within its timeout
+    #[error("State machine operation timed out")]
+    Timeout,
+
+    /// A blocking helper was called from within an async runtime
+    #[error("Cannot block on an event from within an async runtime")]
+    InsideAsyncRuntime,
+
+    /// The task running a state machine actor has stopped
+    #[error("State machine actor has stopped")]
+    ActorStopped,
+
+    /// More queued events were processed for one call than the configured limit
+    #[error("Event queue overflow: more than {0} queued events processed")]
+    EventQueueOverflow(usize),
+
+    /// A state returned [`Response::Pop`](crate::Response::Pop) with nothing on the state stack
+    #[error("Cannot pop state: state stack is empty")]
+    EmptyStateStack,
+
+    /// [`go_back`](crate::StateMachine::go_back) was called before any transition
+    #[error("No previous state to go back to")]
+    NoPreviousState,
+
+    /// A transition to the given state was cancelled partway through, so the
+    /// machine may be between states
+    #[error("Transition to state {0:?} was interrupted; state machine is inconsistent")]
+    InconsistentState(S),
+
+    /// [`replace_state`](crate::StateMachine::replace_state) or
+    /// [`remove_state`](crate::StateMachine::remove_state) was called for the
+    /// current state or one of its superstates
+    #[error("State {0:?} is active and cannot be replaced or removed")]
+    StateActive(S),
+
+    /// An output attached with [`Response::with_output`](crate::Response::with_output)
+    /// is not of the type requested from
+    /// [`process_event_with_output`](crate::StateMachine::process_event_with_output)
+    #[error("Output is not of the requested type {0}")]
+    OutputType(&'static str),
+
+    /// An event could not be decoded, see
+    /// [`process_json`](crate::StateMachine::process_json)
+    #[error("Failed to decode event: {0}")]
+    EventDecode(String),
+
     /// Generic error type for custom errors
     #[error("Custom error: {0}")]
     Custom(String),
 }
+
+impl<S: Debug> FsmError<S> {
+    /// Get the handler phase a state's error came from
+    ///
+    /// [`StateInvalid`](Self::StateInvalid) and [`OnEnterSuper`](Self::OnEnterSuper)
+    /// come from entering a state, [`InvalidEvent`](Self::InvalidEvent) from
+    /// handling an event, and [`Handler`](Self::Handler) carries its phase.
+    /// Other errors are not raised by a handler and return `None`.
+    pub fn phase(&self) -> Option<ErrorPhase> {
+        match self {
+            FsmError::StateInvalid(..) | FsmError::OnEnterSuper(_) => Some(ErrorPhase::Enter),
+            FsmError::InvalidEvent(..) => Some(ErrorPhase::Event),
+            FsmError::Handler(_, phase, _) => Some(*phase),
+            _ => None,
+        }
+    }
+}
+
+/// Which state handler an error came from, see [`FsmError::phase`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorPhase {
+    /// `on_enter` (or `on_enter_with`)
+    Enter,
+    /// `on_event` (or `on_event_in`), including superstates it delegated to
+    Event,
+    /// `on_exit` (or `on_exit_checked`)
+    Exit,
+}
+
+impl std::fmt::Display for ErrorPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorPhase::Enter => write!(f, "on_enter"),
+            ErrorPhase::Event => write!(f, "on_event"),
+            ErrorPhase::Exit => write!(f, "on_exit"),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Closure-based states, for small states that don't warrant their own type
+
+use crate::{Response, Stateful};
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// A boxed, `Send` future borrowing from its arguments for `'a`
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+type EnterFn<S, CTX> =
+    Box<dyn for<'a> FnMut(&'a mut CTX) -> BoxFuture<'a, Response<S>> + Send + Sync>;
+type EventFn<S, CTX, E> =
+    Box<dyn for<'a> FnMut(&'a E, &'a mut CTX) -> BoxFuture<'a, Response<S>> + Send + Sync>;
+type ExitFn<CTX> = Box<dyn for<'a> FnMut(&'a mut CTX) -> BoxFuture<'a, ()> + Send + Sync>;
+type TimeoutFn<CTX> = Box<dyn Fn(&CTX) -> Option<Duration> + Send + Sync>;
+
+/// A state built from closures, created with [`fn_state`].
+pub struct FnState<S, CTX, E> {
+    on_enter: EnterFn<S, CTX>,
+    on_event: EventFn<S, CTX, E>,
+    on_exit: ExitFn<CTX>,
+    timeout: Option<TimeoutFn<CTX>>,
+}
+
+/// Build a state from `on_enter`, `on_event`, and `on_exit` closures
+///
+/// Each closure returns a [`BoxFuture`], typically written as
+/// `|ctx| Box::pin(async move { ... })`.
+///
+/// ```rust
+/// use async_hierarchical_fsm::{Response, StateMachineBuilder, fn_state};
+///
+/// let fsm = StateMachineBuilder::<&str, u32, ()>::new(0)
+///     .state(
+///         "counting",
+///         fn_state(
+///             |_count| Box::pin(async { Response::Handled }),
+///             |_event, count| {
+///                 Box::pin(async move {
+///                     *count += 1;
+///                     Response::Handled
+///                 })
+///             },
+///             |_count| Box::pin(async {}),
+///         ),
+///     )
+///     .build();
+/// ```
+pub fn fn_state<S, CTX, E, En, Ev, Ex>(
+    on_enter: En,
+    on_event: Ev,
+    on_exit: Ex,
+) -> FnState<S, CTX, E>
+where
+    En: for<'a> FnMut(&'a mut CTX) -> BoxFuture<'a, Response<S>> + Send + Sync + 'static,
+    Ev: for<'a> FnMut(&'a E, &'a mut CTX) -> BoxFuture<'a, Response<S>> + Send + Sync + 'static,
+    Ex: for<'a> FnMut(&'a mut CTX) -> BoxFuture<'a, ()> + Send + Sync + 'static,
+{
+    FnState {
+        on_enter: Box::new(on_enter),
+        on_event: Box::new(on_event),
+        on_exit: Box::new(on_exit),
+        timeout: None,
+    }
+}
+
+impl<S, CTX, E> FnState<S, CTX, E> {
+    /// Set the closure used for [`Stateful::get_timeout`]
+    pub fn with_timeout<F>(mut self, timeout: F) -> Self
+    where
+        F: Fn(&CTX) -> Option<Duration> + Send + Sync + 'static,
+    {
+        self.timeout = Some(Box::new(timeout));
+        self
+    }
+}
+
+#[async_trait]
+impl<S, CTX, E> Stateful<S, CTX, E> for FnState<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + 'static,
+    CTX: Send + Sync + 'static,
+    E: Debug + Sync + 'static,
+{
+    async fn on_enter(&mut self, context: &mut CTX) -> Response<S> {
+        (self.on_enter)(context).await
+    }
+
+    async fn on_event(&mut self, event: &E, context: &mut CTX) -> Response<S> {
+        (self.on_event)(event, context).await
+    }
+
+    async fn on_exit(&mut self, context: &mut CTX) {
+        (self.on_exit)(context).await
+    }
+
+    async fn get_timeout(&self, context: &CTX) -> Option<Duration> {
+        self.timeout.as_ref().and_then(|timeout| timeout(context))
+    }
+}
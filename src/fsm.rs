@@ -1,4 +1,18 @@
-use crate::FsmError;
+use crate::coverage::Coverage;
+#[cfg(feature = "serde")]
+use crate::description::FsmDescription;
+use crate::dot::generate_dot;
+use crate::fn_state::BoxFuture;
+use crate::history::{TransitionHistory, TransitionRecord};
+use crate::mermaid::{generate_mermaid, generate_mermaid_static};
+use crate::metrics::Metrics;
+use crate::middleware::{EventMiddleware, MiddlewareDecision};
+use crate::plantuml::{generate_plantuml, generate_plantuml_static};
+use crate::queue::EventQueue;
+use crate::registry::{StateEntry, StateRegistry};
+use crate::snapshot::FsmSnapshot;
+use crate::trace::instrument;
+use crate::{ErrorPhase, FsmError};
 /// A generic asynchronous finite state machine (FSM) framework supporting hierarchical states,
 /// event-driven transitions.
 ///
@@ -33,39 +47,293 @@ use crate::FsmError;
 /// - [`Response`]: Enum for state handler responses.
 /// - [`Error`]: Error type for the state machine.
 use async_trait::async_trait;
+use std::any::Any;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::time::Duration;
-use std::{collections::HashMap, fmt::Debug, hash::Hash};
+// Tokio's clock follows paused time in tests; without Tokio, use the system clock
+#[cfg(not(feature = "tokio-integration"))]
+pub(crate) use std::time::Instant;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Debug,
+    hash::Hash,
+};
+#[cfg(feature = "tokio-integration")]
+pub(crate) use tokio::time::Instant;
 
 // Type alias for the complex superstate function type - make it public
 pub type SuperstateFn<S> = Box<dyn Fn(&S) -> Option<S> + Send + Sync>;
 
+pub(crate) type SharedSuperstateFn<S> = Arc<dyn Fn(&S) -> Option<S> + Send + Sync>;
+
+/// Superstate function that resolves a state's parent asynchronously, with the context
+pub(crate) type SharedAsyncSuperstateFn<S, CTX> =
+    Arc<dyn for<'a> Fn(&'a S, &'a CTX) -> BoxFuture<'a, Option<S>> + Send + Sync>;
+
+/// A boxed state handler, as registered with the state machine
+pub(crate) type BoxedState<S, CTX, E> = Box<dyn Stateful<S, CTX, E> + Send + Sync>;
+
+/// An event stored with the `E::clone` used to hand out copies of it, so
+/// only the method storing it needs `E: Clone`
+pub(crate) type CopyableEvent<E> = (E, fn(&E) -> E);
+
+/// Callback invoked with `(from, to, context)` after every successful transition
+pub type TransitionListener<S, CTX> = Box<dyn FnMut(&S, &S, &CTX) + Send + Sync>;
+
+/// Fallback invoked with `(state, event, context)` for events no state handled
+pub type UnhandledHandler<S, CTX, E> =
+    Box<dyn FnMut(&S, &E, &mut CTX) -> Response<S> + Send + Sync>;
+
+/// Handler invoked with `(event, context)` before the current state sees an event
+pub type GlobalHandler<S, CTX, E> =
+    Box<dyn FnMut(&E, &mut CTX) -> Option<Response<S>> + Send + Sync>;
+
+/// Callback invoked with `(state, event, kind)` for every handler consulted for an event
+pub type EventObserver<S, E> = Box<dyn FnMut(&S, &E, &ResponseKind) + Send + Sync>;
+
+/// Callback invoked with `(state, event, error)` for every event that failed
+pub type RejectionListener<S, E> = Box<dyn FnMut(&S, &E, &FsmError<S>) + Send + Sync>;
+
+/// Side effect run with the context when a particular state is entered or exited
+pub type StateAction<CTX> = Box<dyn FnMut(&mut CTX) + Send + Sync>;
+
+/// Guard checked with the context before taking a registered `(from, to)` edge
+pub type EdgeGuard<CTX> = Arc<dyn Fn(&CTX) -> bool + Send + Sync>;
+
+/// Check on the new state and context run after every transition
+pub type Invariant<S, CTX> = Arc<dyn Fn(&S, &CTX) -> Result<(), String> + Send + Sync>;
+
+/// Default limit on queued events processed per call, see [`StateMachine::event_queue`]
+pub const DEFAULT_MAX_QUEUED_EVENTS: usize = 100;
+
+/// Advance a SplitMix64 generator, returning its next value
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Pick one of `choices` for a [`Response::Choice`], or `None` if it is empty
+fn pick<S>(rng_state: &mut u64, mut choices: Vec<S>) -> Option<S> {
+    if choices.is_empty() {
+        return None;
+    }
+    let index = splitmix64(rng_state) % choices.len() as u64;
+    Some(choices.swap_remove(index as usize))
+}
+
+/// Error message for a [`Response::Choice`] with nothing to choose from
+const EMPTY_CHOICE: &str = "Choice has no states to choose from";
+
+/// Get the state whose handler produced `err`, if a handler did
+fn error_source<S: Debug>(err: &FsmError<S>) -> Option<&S> {
+    match err {
+        FsmError::StateInvalid(state, _)
+        | FsmError::InvalidEvent(state, _)
+        | FsmError::Handler(state, ..) => Some(state),
+        _ => None,
+    }
+}
+
+/// Seed for machines not given one with `StateMachineBuilder::rng_seed`
+fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
 #[async_trait]
 /// Trait for stateful components in the state machine.
 pub trait Stateful<S: Hash + Eq + Clone, CTX, E: Debug>: Send + Sync {
     /// Called when entering the state.
     ///
+    /// The default implementation does nothing and returns [`Response::Handled`].
+    ///
+    /// # Arguments
+    /// * `context` - Mutable reference to the shared context.
+    ///
+    /// # Returns
+    /// A [`Response`] indicating how to proceed after entering the state.
+    async fn on_enter(&mut self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        let _ = context;
+        Response::Handled
+    }
+
+    /// Called instead of [`on_enter`](Self::on_enter) when the state is entered
+    /// via [`Response::TransitionWith`].
+    ///
+    /// The default implementation ignores the payload and calls `on_enter`.
+    ///
     /// # Arguments
     /// * `context` - Mutable reference to the shared context.
+    /// * `payload` - Data handed over by the handler that requested the transition.
     ///
     /// # Returns
     /// A [`Response`] indicating how to proceed after entering the state.
-    async fn on_enter(&mut self, context: &mut CTX) -> Response<S>;
+    async fn on_enter_with(
+        &mut self,
+        context: &mut CTX,
+        payload: Box<dyn Any + Send>,
+    ) -> Response<S>
+    where
+        CTX: Send,
+    {
+        let _ = payload;
+        self.on_enter(context).await
+    }
 
     /// Called when an event occurs in the state.
     ///
+    /// The default implementation returns [`Response::Super`], so events the
+    /// state does not handle bubble up to its superstate (and fail with
+    /// [`FsmError::InvalidEvent`] once no superstate is left).
+    ///
+    /// # Arguments
+    /// * `event` - Reference to the event to process.
+    /// * `context` - Mutable reference to the shared context.
+    ///
+    /// # Returns
+    /// A [`Response`] indicating how to proceed after handling the event.
+    //
+    // Written out as `#[async_trait]` would expand it, so the default body
+    // does not require `E: Sync` (see `on_event_in`).
+    fn on_event<'life0, 'life1, 'life2, 'async_trait>(
+        &'life0 mut self,
+        event: &'life1 E,
+        context: &'life2 mut CTX,
+    ) -> Pin<Box<dyn Future<Output = Response<S>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        Self: 'async_trait,
+    {
+        let _ = (event, context);
+        Box::pin(async { Response::Super })
+    }
+
+    /// Called instead of [`on_event`](Self::on_event), with the state the
+    /// handler is running as.
+    ///
+    /// This lets one handler registered under several states tell them apart.
+    /// During superstate delegation, `state` is the superstate handling the
+    /// event. The default implementation ignores `state` and calls `on_event`;
+    /// override it with a plain `async fn` like the other methods.
+    ///
     /// # Arguments
+    /// * `state` - The state this handler is registered under.
     /// * `event` - Reference to the event to process.
     /// * `context` - Mutable reference to the shared context.
     ///
     /// # Returns
     /// A [`Response`] indicating how to proceed after handling the event.
-    async fn on_event(&mut self, event: &E, context: &mut CTX) -> Response<S>;
+    //
+    // Written out as `#[async_trait]` would expand it, so the default body
+    // can hand back `on_event`'s future without requiring `E: Sync`.
+    fn on_event_in<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 mut self,
+        state: &'life1 S,
+        event: &'life2 E,
+        context: &'life3 mut CTX,
+    ) -> Pin<Box<dyn Future<Output = Response<S>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        let _ = state;
+        self.on_event(event, context)
+    }
 
     /// Called when exiting the state.
     ///
+    /// The default implementation does nothing.
+    ///
     /// # Arguments
     /// * `context` - Mutable reference to the shared context.
-    async fn on_exit(&mut self, context: &mut CTX);
+    async fn on_exit(&mut self, context: &mut CTX)
+    where
+        CTX: Send,
+    {
+        let _ = context;
+    }
+
+    /// Called when exiting the state, in place of [`on_exit`](Self::on_exit),
+    /// with a chance to veto or redirect the transition.
+    ///
+    /// Returning [`Response::Error`] or [`Response::Fail`] aborts the
+    /// transition with [`FsmError::Handler`] in the [`ErrorPhase::Exit`]
+    /// phase, leaving the machine in its source state; states below this one
+    /// that already exited are not re-entered. Returning
+    /// [`Response::Transition`] or [`Response::TransitionWith`] redirects the
+    /// transition to a different target before anything is entered. Any other
+    /// response lets the transition proceed.
+    ///
+    /// The default implementation calls `on_exit` and returns [`Response::Handled`].
+    ///
+    /// # Arguments
+    /// * `context` - Mutable reference to the shared context.
+    ///
+    /// # Returns
+    /// A [`Response`] indicating whether and where the transition continues.
+    async fn on_exit_checked(&mut self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        self.on_exit(context).await;
+        Response::Handled
+    }
+
+    /// Called once for each registered state by
+    /// [`StateMachine::prepare`], before the machine starts.
+    ///
+    /// Use this for one-time async setup that should not repeat on every
+    /// `on_enter`. The default implementation does nothing.
+    ///
+    /// # Arguments
+    /// * `context` - Mutable reference to the shared context.
+    async fn on_register(&mut self, context: &mut CTX)
+    where
+        CTX: Send,
+    {
+        let _ = context;
+    }
+
+    /// The state's superstate, declared alongside the state itself.
+    ///
+    /// Takes precedence over the builder's
+    /// [`superstate_fn`](crate::StateMachineBuilder::superstate_fn), which is
+    /// consulted when this returns `None` (the default). States sharing a
+    /// handler through an alias share its superstate too.
+    fn superstate(&self) -> Option<S> {
+        None
+    }
+
+    /// Guard checked before the state is entered as a transition target.
+    ///
+    /// If this returns `false`, the transition is rejected before any state
+    /// is exited, leaving the machine in its source state.
+    ///
+    /// # Arguments
+    /// * `context` - Reference to the shared context.
+    ///
+    /// # Returns
+    /// `true` (the default) to allow entering the state.
+    async fn can_enter(&self, context: &CTX) -> bool {
+        let _ = context;
+        true
+    }
 
     /// Optionally returns a timeout duration for the state.
     ///
@@ -78,6 +346,71 @@ pub trait Stateful<S: Hash + Eq + Clone, CTX, E: Debug>: Send + Sync {
         let _ = context; // Placeholder for the actual implementation
         None
     }
+
+    /// Optionally returns the event a timeout driver should inject once the
+    /// duration from [`get_timeout`](Self::get_timeout) elapses.
+    ///
+    /// Drivers such as `tokio_utils::spawn_actor` ask for this again after
+    /// every event, so it may change as the state is re-entered. Overrides
+    /// any machine-wide event set with
+    /// [`StateMachineBuilder::timeout_event`](crate::StateMachineBuilder::timeout_event).
+    ///
+    /// # Returns
+    /// The event to process on timeout, or `None` (the default) for none.
+    fn timeout_event(&self) -> Option<E> {
+        None
+    }
+
+    /// Called by [`StateMachine::process_timeout`] when the duration from
+    /// [`get_timeout`](Self::get_timeout) elapses.
+    ///
+    /// Return a transition to leave the state without a timeout event, or
+    /// [`Response::Super`] to let the superstate handle the timeout. The
+    /// default implementation does nothing and returns [`Response::Handled`].
+    ///
+    /// # Arguments
+    /// * `context` - Mutable reference to the shared context.
+    async fn on_timeout(&mut self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        let _ = context;
+        Response::Handled
+    }
+
+    /// Returns how to retry `on_enter` when it fails.
+    ///
+    /// Consulted only after `on_enter` returns [`Response::Error`] or
+    /// [`Response::Fail`]; the last failure is reported if every retry fails.
+    /// A retried target state is entered with plain `on_enter`, since any
+    /// transition payload was consumed by the first attempt.
+    ///
+    /// # Returns
+    /// A [`RetryPolicy`]; the default performs no retries.
+    async fn enter_retry(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+}
+
+/// A [`Stateful`] handler that can be cloned behind a trait object.
+///
+/// Implemented for every `Clone` handler. Register handlers with
+/// [`StateMachineBuilder::cloneable_state`](crate::StateMachineBuilder::cloneable_state)
+/// to make the state machine cloneable with [`StateMachine::try_clone`].
+pub trait CloneableStateful<S: Hash + Eq + Clone, CTX, E: Debug>: Stateful<S, CTX, E> {
+    /// Clone the handler into a new box
+    fn clone_box(&self) -> Box<dyn CloneableStateful<S, CTX, E> + Send + Sync>;
+}
+
+impl<S, CTX, E, T> CloneableStateful<S, CTX, E> for T
+where
+    S: Hash + Eq + Clone,
+    E: Debug,
+    T: Stateful<S, CTX, E> + Clone + 'static,
+{
+    fn clone_box(&self) -> Box<dyn CloneableStateful<S, CTX, E> + Send + Sync> {
+        Box::new(self.clone())
+    }
 }
 
 /// Response type for state handlers, indicating how to proceed after handling an event or entering a state.
@@ -85,12 +418,156 @@ pub trait Stateful<S: Hash + Eq + Clone, CTX, E: Debug>: Send + Sync {
 pub enum Response<S> {
     /// Event was handled successfully, no transition needed
     Handled,
+    /// Event was handled internally; like [`Response::Handled`], no lifecycle
+    /// hooks run, but the machine also records that the event was handled
+    /// without a transition, see [`StateMachine::last_handled_internally`]
+    HandledInternal,
     /// An error occurred, with a message
     Error(String),
+    /// An error occurred, carried as a typed error that callers can downcast
+    Fail(Box<dyn Error + Send + Sync>),
     /// Transition to a new state
     Transition(S),
+    /// Transition to a new state, handing a payload to its [`Stateful::on_enter_with`]
+    TransitionWith(S, Box<dyn Any + Send>),
     /// Delegate to superstate (if applicable)
     Super,
+    /// Exit and re-enter the current state, running `on_exit` then `on_enter`
+    Reenter,
+    /// Switch to a new state without running `on_exit` or `on_enter`
+    SilentTransition(S),
+    /// Push the current state onto the state stack, then transition to a new state
+    Push(S),
+    /// Transition back to the state on top of the state stack, removing it
+    Pop,
+    /// Emit an output, then proceed as the inner response; built with
+    /// [`Response::with_output`]
+    Output(Box<dyn Any + Send + Sync>, Box<Response<S>>),
+    /// Transition to one of several states, picked at random
+    ///
+    /// The pick is reproducible for machines given a seed with
+    /// [`StateMachineBuilder::rng_seed`](crate::StateMachineBuilder::rng_seed).
+    Choice(Vec<S>),
+}
+
+impl<S> Response<S> {
+    /// Get which kind of response this is, without its payload
+    pub fn kind(&self) -> ResponseKind {
+        match self {
+            Response::Handled => ResponseKind::Handled,
+            Response::HandledInternal => ResponseKind::HandledInternal,
+            Response::Error(_) => ResponseKind::Error,
+            Response::Fail(_) => ResponseKind::Fail,
+            Response::Transition(_) => ResponseKind::Transition,
+            Response::TransitionWith(..) => ResponseKind::TransitionWith,
+            Response::Super => ResponseKind::Super,
+            Response::Reenter => ResponseKind::Reenter,
+            Response::SilentTransition(_) => ResponseKind::SilentTransition,
+            Response::Push(_) => ResponseKind::Push,
+            Response::Pop => ResponseKind::Pop,
+            Response::Output(_, inner) => inner.kind(),
+            Response::Choice(_) => ResponseKind::Choice,
+        }
+    }
+
+    /// Attach an output to this response
+    ///
+    /// Outputs are collected in the order they are attached, including
+    /// across superstates an event is delegated to, and returned by
+    /// [`StateMachine::process_event_with_output`].
+    pub fn with_output<O: Any + Send + Sync>(self, output: O) -> Self {
+        Response::Output(Box::new(output), Box::new(self))
+    }
+
+    /// Move any attached outputs onto `outputs`, in the order they were attached
+    fn split_outputs(self, outputs: &mut Vec<Box<dyn Any + Send + Sync>>) -> Self {
+        match self {
+            Response::Output(output, inner) => {
+                let response = inner.split_outputs(outputs);
+                outputs.push(output);
+                response
+            }
+            response => response,
+        }
+    }
+}
+
+/// Compares responses so handlers can be unit tested with `assert_eq!`
+///
+/// [`Response::Error`] compares its message and [`Response::Fail`] the
+/// `Display` text of its error. Type-erased payloads cannot be compared, so a
+/// [`Response::TransitionWith`] or [`Response::Output`] is never equal to
+/// anything, including itself.
+impl<S: PartialEq> PartialEq for Response<S> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Response::Handled, Response::Handled)
+            | (Response::HandledInternal, Response::HandledInternal)
+            | (Response::Super, Response::Super)
+            | (Response::Reenter, Response::Reenter)
+            | (Response::Pop, Response::Pop) => true,
+            (Response::Error(a), Response::Error(b)) => a == b,
+            (Response::Fail(a), Response::Fail(b)) => a.to_string() == b.to_string(),
+            (Response::Transition(a), Response::Transition(b))
+            | (Response::SilentTransition(a), Response::SilentTransition(b))
+            | (Response::Push(a), Response::Push(b)) => a == b,
+            (Response::Choice(a), Response::Choice(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The variants of [`Response`] without their payloads, as seen by event observers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseKind {
+    /// [`Response::Handled`]
+    Handled,
+    /// [`Response::HandledInternal`]
+    HandledInternal,
+    /// [`Response::Error`]
+    Error,
+    /// [`Response::Fail`]
+    Fail,
+    /// [`Response::Transition`]
+    Transition,
+    /// [`Response::TransitionWith`]
+    TransitionWith,
+    /// [`Response::Super`]
+    Super,
+    /// [`Response::Reenter`]
+    Reenter,
+    /// [`Response::SilentTransition`]
+    SilentTransition,
+    /// [`Response::Push`]
+    Push,
+    /// [`Response::Pop`]
+    Pop,
+    /// [`Response::Choice`]
+    Choice,
+}
+
+/// Result of [`StateMachine::process_event_tracked`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionOutcome<S> {
+    /// The state before the event was processed
+    pub from: Option<S>,
+    /// The state after the event (and any queued events) were processed
+    pub to: S,
+    /// Whether any transition took place, including re-entering the same state
+    pub transitioned: bool,
+}
+
+/// How often to retry a failed `on_enter`, returned by [`Stateful::enter_retry`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub attempts: u32,
+    /// Delay before each retry
+    ///
+    /// Slept with Tokio when `tokio-integration` is enabled, otherwise with
+    /// async-std when `async-std-integration` is; with neither, retries
+    /// happen immediately.
+    pub backoff: Duration,
 }
 
 /// A generic asynchronous finite state machine (FSM) implementation.
@@ -100,13 +577,75 @@ where
     E: Debug + Send + 'static,
     CTX: Send + 'static,
 {
-    states: HashMap<S, Box<dyn Stateful<S, CTX, E> + Send + Sync>>,
+    pub(crate) states: StateRegistry<S, CTX, E>,
     current_state: Option<S>,
     context: CTX,
-    superstate_fn: SuperstateFn<S>,
+    // Shared so clones of the machine can use it too
+    pub(crate) superstate_fn: SharedSuperstateFn<S>,
+    // Used instead of `superstate_fn` when delegating events, if set
+    pub(crate) async_superstate_fn: Option<SharedAsyncSuperstateFn<S, CTX>>,
     initial_state: Option<S>,
     // Transition log - only one record per unique state-to-state transition
-    // Key: (from_state, to_state), Value: TransitionRecord
+    transition_log: HashSet<(S, S)>,
+    // Every state whose on_enter has run, for coverage reports
+    entered_states: HashSet<S>,
+    // Full transition history, kept only when enabled in the builder
+    pub(crate) history: TransitionHistory<S>,
+    pub(crate) transition_listeners: Vec<TransitionListener<S, CTX>>,
+    pub(crate) event_observers: Vec<EventObserver<S, E>>,
+    pub(crate) rejection_listeners: Vec<RejectionListener<S, E>>,
+    pub(crate) middleware: Vec<Box<dyn EventMiddleware<CTX, E> + Send + Sync>>,
+    pub(crate) global_handler: Option<GlobalHandler<S, CTX, E>>,
+    pub(crate) unhandled_handler: Option<UnhandledHandler<S, CTX, E>>,
+    pub(crate) edge_guards: HashMap<(S, S), EdgeGuard<CTX>>,
+    pub(crate) invariants: Vec<Invariant<S, CTX>>,
+    // Legal `(from, to)` transitions; empty allows every transition
+    pub(crate) allowed_edges: HashSet<(S, S)>,
+    // States that no event may leave, see `StateMachineBuilder::terminal`
+    pub(crate) terminal_states: HashSet<S>,
+    // State -> state to transition to when it fails, see `on_error_goto`
+    pub(crate) error_recovery: HashMap<S, S>,
+    pub(crate) enter_actions: HashMap<S, Vec<StateAction<CTX>>>,
+    pub(crate) exit_actions: HashMap<S, Vec<StateAction<CTX>>>,
+    pub(crate) event_queue: EventQueue<E>,
+    pub(crate) max_queued_events: usize,
+    // Most transitions one transition may chain into; the state count if unset
+    pub(crate) max_enter_chain: Option<usize>,
+    pub(crate) collect_metrics: bool,
+    metrics: Metrics<S>,
+    // Last active leaf state under each exited state
+    deep_history: HashMap<S, S>,
+    // States saved by Response::Push, restored by Response::Pop
+    state_stack: Vec<S>,
+    // Source of the last completed transition, for go_back
+    previous_state: Option<S>,
+    // Target of the transition in progress; left set if it was cancelled
+    pending_transition: Option<S>,
+    // Number of completed transitions, used to tell whether an event moved the machine
+    transition_count: u64,
+    // Whether the last `process_event` call succeeded with a transition
+    last_transitioned: bool,
+    // Whether the last `process_event` call was answered with `HandledInternal`
+    handled_internally: bool,
+    // Current state's timeout as of the last transition
+    cached_timeout: Option<Duration>,
+    // When the current state was entered
+    entered_at: Option<Instant>,
+    // Whether `on_register` has been called on every state
+    prepared: bool,
+    // Outputs attached to responses since the last `process_event` began
+    outputs: Vec<Box<dyn Any + Send + Sync>>,
+    // Set while suspended, to copy events into `suspended_events`; a function
+    // pointer so only `suspend` needs `E: Clone`
+    suspended: Option<fn(&E) -> E>,
+    // Events received while suspended, in arrival order
+    suspended_events: VecDeque<E>,
+    // State of the generator behind `Response::Choice`
+    pub(crate) rng_state: u64,
+    // Machine-wide timeout event
+    pub(crate) timeout_event: Option<CopyableEvent<E>>,
+    #[cfg(feature = "tokio-integration")]
+    pub(crate) state_broadcast: Option<tokio::sync::broadcast::Sender<S>>,
 }
 
 impl<S, CTX, E> StateMachine<S, CTX, E>
@@ -120,21 +659,218 @@ where
         context: CTX,
         states: HashMap<S, Box<dyn Stateful<S, CTX, E> + Send + Sync>>,
         superstate_fn: Option<SuperstateFn<S>>,
+    ) -> Self {
+        let states = states
+            .into_iter()
+            .map(|(state, handler)| (state, StateEntry::Plain(handler)))
+            .collect();
+        Self::with_registry(context, StateRegistry::new(states), superstate_fn)
+    }
+
+    pub(crate) fn with_registry(
+        context: CTX,
+        states: StateRegistry<S, CTX, E>,
+        superstate_fn: Option<SuperstateFn<S>>,
     ) -> Self {
         Self {
             states,
             current_state: None,
             context,
-            superstate_fn: superstate_fn.unwrap_or_else(|| Box::new(|_| None)),
+            superstate_fn: superstate_fn.map_or_else(|| Arc::new(|_: &S| None) as _, Arc::from),
+            async_superstate_fn: None,
             initial_state: None,
-            // Initialize the transition log
+            transition_log: HashSet::new(),
+            entered_states: HashSet::new(),
+            history: TransitionHistory::new(0),
+            transition_listeners: Vec::new(),
+            event_observers: Vec::new(),
+            rejection_listeners: Vec::new(),
+            middleware: Vec::new(),
+            global_handler: None,
+            unhandled_handler: None,
+            edge_guards: HashMap::new(),
+            invariants: Vec::new(),
+            allowed_edges: HashSet::new(),
+            terminal_states: HashSet::new(),
+            error_recovery: HashMap::new(),
+            enter_actions: HashMap::new(),
+            exit_actions: HashMap::new(),
+            event_queue: EventQueue::new(),
+            max_queued_events: DEFAULT_MAX_QUEUED_EVENTS,
+            max_enter_chain: None,
+            collect_metrics: false,
+            metrics: Metrics::new(),
+            deep_history: HashMap::new(),
+            state_stack: Vec::new(),
+            previous_state: None,
+            pending_transition: None,
+            transition_count: 0,
+            last_transitioned: false,
+            handled_internally: false,
+            cached_timeout: None,
+            entered_at: None,
+            prepared: false,
+            outputs: Vec::new(),
+            suspended: None,
+            suspended_events: VecDeque::new(),
+            timeout_event: None,
+            rng_state: random_seed(),
+            #[cfg(feature = "tokio-integration")]
+            state_broadcast: None,
+        }
+    }
+
+    /// Call [`Stateful::on_register`] on every registered state
+    ///
+    /// Only the first call does anything, so this can safely be called before
+    /// every [`init`](Self::init). Aliases share their state's handler, which
+    /// is called once.
+    pub async fn prepare(&mut self) {
+        if self.prepared {
+            return;
         }
+        self.prepared = true;
+        for handler in self.states.values_mut() {
+            handler.on_register(&mut self.context).await;
+        }
+    }
+
+    /// Check whether [`prepare`](Self::prepare) has run
+    pub fn is_prepared(&self) -> bool {
+        self.prepared
     }
 
     /// Initialize the state machine with an initial state
+    ///
+    /// This also recovers from [`FsmError::InconsistentState`], entering
+    /// `state` without exiting the states the cancelled transition left.
     pub async fn init(&mut self, state: S) -> Result<(), FsmError<S>> {
+        self.abandon_pending_transition();
         self.initial_state = Some(state.clone());
-        self.transition_to(state).await
+        let result = self.transition_to(state).await;
+        self.recover(result).await?;
+        self.drain_event_queue().await
+    }
+
+    /// Forget a cancelled transition, so the next one enters from scratch
+    ///
+    /// The cancelled transition may already have exited any of the states
+    /// still recorded as active, so none of them is exited again.
+    fn abandon_pending_transition(&mut self) {
+        if self.pending_transition.take().is_some() {
+            self.current_state = None;
+            self.state_stack.clear();
+        }
+    }
+
+    /// Replace a state's error with a transition to its recovery target, if
+    /// one was set with
+    /// [`on_error_goto`](crate::StateMachineBuilder::on_error_goto)
+    ///
+    /// Only [`FsmError::StateInvalid`] and [`FsmError::InvalidEvent`] are
+    /// recovered from, and only once: an error from the recovery transition
+    /// itself is returned as is.
+    async fn recover(&mut self, result: Result<(), FsmError<S>>) -> Result<(), FsmError<S>> {
+        let Err(FsmError::StateInvalid(state, _) | FsmError::InvalidEvent(state, _)) = &result
+        else {
+            return result;
+        };
+        let Some(target) = self.error_recovery.get(state).cloned() else {
+            return result;
+        };
+
+        #[cfg(feature = "tracing")]
+        if let Err(err) = &result {
+            tracing::warn!(state = ?state, target = ?target, error = %err, "recovering from error");
+        }
+        // A failed transition that left no state active can be recovered by
+        // entering the target from scratch
+        if self.current_state.is_none() {
+            self.pending_transition = None;
+        }
+        self.transition_to(target).await
+    }
+
+    /// Return to the state the machine was initialized with
+    ///
+    /// Runs the usual `on_exit`/`on_enter` hooks and clears the state stack.
+    /// The context is left untouched. This also recovers from
+    /// [`FsmError::InconsistentState`].
+    ///
+    /// # Errors
+    /// Returns [`FsmError::NoInitialState`] if [`init`](Self::init) was never called.
+    pub async fn reset(&mut self) -> Result<(), FsmError<S>> {
+        let initial = self.initial_state.clone().ok_or(FsmError::NoInitialState)?;
+        self.abandon_pending_transition();
+        self.state_stack.clear();
+        self.transition_to(initial).await?;
+        self.drain_event_queue().await
+    }
+
+    /// Transition to the leaf state that was last active under `parent`
+    ///
+    /// If `parent` has never been exited, transitions to `default` instead.
+    /// Superstates between the common ancestor and the restored leaf are
+    /// entered in order, as with any other transition.
+    pub async fn restore_deep_history(&mut self, parent: S, default: S) -> Result<(), FsmError<S>> {
+        let target = self.deep_history.get(&parent).cloned().unwrap_or(default);
+        self.transition_to(target).await?;
+        self.drain_event_queue().await
+    }
+
+    /// Transition to `target` directly, without any event
+    ///
+    /// Intended for administrative overrides. The usual guards and
+    /// `on_exit`/`on_enter` hooks run, and the transition is logged like any
+    /// other.
+    ///
+    /// # Errors
+    /// Returns [`FsmError::StateMachineNotInitialized`] before
+    /// [`init`](Self::init), or any error from the transition itself.
+    pub async fn force_transition(&mut self, target: S) -> Result<(), FsmError<S>> {
+        if self.current_state.is_none() {
+            return Err(FsmError::StateMachineNotInitialized);
+        }
+        self.transition_to(target).await?;
+        self.drain_event_queue().await
+    }
+
+    /// Transition back to the source of the last completed transition
+    ///
+    /// Only one step is remembered, so calling this twice in a row returns to
+    /// where the first call started. Unlike [`Response::Pop`], this does not
+    /// touch the state stack.
+    ///
+    /// # Errors
+    /// Returns [`FsmError::NoPreviousState`] if no transition has completed yet.
+    pub async fn go_back(&mut self) -> Result<(), FsmError<S>> {
+        let previous = self
+            .previous_state
+            .clone()
+            .ok_or(FsmError::NoPreviousState)?;
+        self.transition_to(previous).await?;
+        self.drain_event_queue().await
+    }
+
+    /// Get the source of the last completed transition, if any
+    pub fn previous_state(&self) -> Option<&S> {
+        self.previous_state.as_ref()
+    }
+
+    /// Get the leaf state that was last active under `parent`, if any
+    pub fn deep_history(&self, parent: &S) -> Option<&S> {
+        self.deep_history.get(parent)
+    }
+
+    /// Register a callback invoked with `(from, to, context)` after every
+    /// successful transition
+    ///
+    /// Multiple callbacks may be registered; they run in registration order.
+    pub fn on_transition<F>(&mut self, f: F)
+    where
+        F: FnMut(&S, &S, &CTX) + Send + Sync + 'static,
+    {
+        self.transition_listeners.push(Box::new(f));
     }
 
     /// Get timeout for current state
@@ -147,304 +883,4651 @@ where
         None
     }
 
+    /// Get how much of the current state's timeout is left
+    ///
+    /// The timeout from [`Stateful::get_timeout`] less
+    /// [`time_in_state`](Self::time_in_state), saturating at zero. Useful for
+    /// arming a timer for the remaining time, e.g. after a restore. Returns
+    /// `None` when the current state has no timeout.
+    pub async fn remaining_timeout(&self) -> Option<Duration> {
+        let timeout = self.get_current_timeout().await?;
+        let elapsed = self.time_in_state().unwrap_or_default();
+        Some(timeout.saturating_sub(elapsed))
+    }
+
+    /// Get how long the machine has been in the current state
+    ///
+    /// Measured from when the current state was last entered, including by
+    /// re-entering it; `None` before [`init`](Self::init). With
+    /// `tokio-integration` enabled this uses Tokio's clock, so it follows
+    /// paused time in tests.
+    pub fn time_in_state(&self) -> Option<Duration> {
+        self.entered_at.map(|entered_at| entered_at.elapsed())
+    }
+
+    /// Get the current state's timeout as of the last transition
+    ///
+    /// Refreshed from [`Stateful::get_timeout`] whenever the machine changes
+    /// state, so drivers can read it without awaiting. Context changes made by
+    /// handled events are not reflected until the next transition; use
+    /// [`get_current_timeout`](Self::get_current_timeout) for a fresh value.
+    pub fn cached_timeout(&self) -> Option<Duration> {
+        self.cached_timeout
+    }
+
+    /// Re-read the current state's timeout into the cache
+    async fn refresh_cached_timeout(&mut self) {
+        self.cached_timeout = self.get_current_timeout().await;
+    }
+
+    /// Get the event to inject when the current state's timeout elapses
+    ///
+    /// This is the current state's own [`Stateful::timeout_event`] if it has
+    /// one, otherwise the machine-wide event set with
+    /// [`StateMachineBuilder::timeout_event`](crate::StateMachineBuilder::timeout_event).
+    pub fn current_timeout_event(&self) -> Option<E> {
+        self.current_state
+            .as_ref()
+            .and_then(|current| self.states.get(current))
+            .and_then(|state| state.timeout_event())
+            .or_else(|| self.timeout_event.as_ref().map(|(event, copy)| copy(event)))
+    }
+
     /// Transition to a new state
     async fn transition_to(&mut self, target: S) -> Result<(), FsmError<S>> {
-        let mut current_target = target;
+        self.transition_to_with(target, None).await
+    }
 
-        loop {
-            // Exit current state if it exists
-            if let Some(current) = &self.current_state
-                && let Some(s) = self.states.get_mut(current)
-            {
-                s.on_exit(&mut self.context).await;
-            }
+    /// Transition to a new state, passing an optional payload to the entered state
+    ///
+    /// States are exited from the current state up to (but excluding) the
+    /// lowest common ancestor of the source and target, then entered from just
+    /// below that ancestor down to the target. A transition to the current
+    /// state or one of its ancestors exits and re-enters the target itself.
+    ///
+    /// If the transition fails after exiting states, the machine falls back to
+    /// the deepest state that is still active. If no state is, the machine is
+    /// left with no current state and fails with
+    /// [`FsmError::InconsistentState`] until [`init`](Self::init) or
+    /// [`reset`](Self::reset) is called.
+    async fn transition_to_with(
+        &mut self,
+        target: S,
+        payload: Option<Box<dyn Any + Send>>,
+    ) -> Result<(), FsmError<S>> {
+        if let Some(pending) = &self.pending_transition {
+            return Err(FsmError::InconsistentState(pending.clone()));
+        }
+        self.pending_transition = Some(target.clone());
+
+        let mut exited = HashSet::new();
+        let result = instrument!(
+            self.run_transition(target, payload, &mut exited),
+            "transition",
+            from = ?self.current_state,
+            to = ?target
+        )
+        .await;
+        let pending = self.pending_transition.take();
+        if result.is_err() && !exited.is_empty() {
+            self.settle_failed_transition(&exited, pending);
+        }
+        // Even a failed transition may have changed the current state
+        self.refresh_cached_timeout().await;
+        result?;
+        self.check_invariants()
+    }
 
-            // Update current state BEFORE entering new state
-            self.current_state = Some(current_target.clone());
+    /// Move the current state up to the deepest state not in `exited`, or
+    /// mark the transition to `target` as interrupted if every state was exited
+    fn settle_failed_transition(&mut self, exited: &HashSet<S>, target: Option<S>) {
+        let active = self
+            .ancestors()
+            .into_iter()
+            .find(|state| !exited.contains(state));
+        if active.is_none() {
+            self.pending_transition = target;
+        }
+        self.current_state = active;
+    }
 
-            // Enter the new state
-            let s = if let Some(state) = self.states.get_mut(&current_target) {
-                state
-            } else {
-                return Err(FsmError::StateNotRegistered(current_target.clone()));
+    /// Run every invariant against the current state, in registration order
+    fn check_invariants(&self) -> Result<(), FsmError<S>> {
+        let Some(current) = &self.current_state else {
+            return Ok(());
+        };
+        for invariant in &self.invariants {
+            invariant(current, &self.context)
+                .map_err(|reason| FsmError::InvariantViolated(current.clone(), reason))?;
+        }
+        Ok(())
+    }
+
+    /// Exit and enter states for a transition, following any transitions
+    /// requested by `on_enter`
+    ///
+    /// `exited` collects the states exited and not entered again, so one
+    /// redirected by `on_exit_checked` is not exited a second time, and a
+    /// failed transition knows which states are still active.
+    async fn run_transition(
+        &mut self,
+        target: S,
+        mut payload: Option<Box<dyn Any + Send>>,
+        exited: &mut HashSet<S>,
+    ) -> Result<(), FsmError<S>> {
+        let mut current_target = target;
+        // Passes through the loop: the transition itself, then one per redirect
+        let mut passes = 0;
+        let max_redirects = self.max_enter_chain.unwrap_or_else(|| self.states.len());
+
+        'transition: loop {
+            passes += 1;
+            if passes > max_redirects + 1 {
+                return Err(FsmError::EnterChainTooLong(current_target));
+            }
+            let Some(target_state) = self.states.get(&current_target) else {
+                return Err(FsmError::StateNotRegistered(current_target));
             };
+            if !self.allowed_edges.is_empty()
+                && let Some(current) = &self.current_state
+                && !self
+                    .allowed_edges
+                    .contains(&(current.clone(), current_target.clone()))
+            {
+                return Err(FsmError::IllegalTransition(current.clone(), current_target));
+            }
+            if !self.edge_guards.is_empty()
+                && let Some(current) = &self.current_state
+                && let Some(guard) = self
+                    .edge_guards
+                    .get(&(current.clone(), current_target.clone()))
+                && !guard(&self.context)
+            {
+                return Err(FsmError::EdgeGuardRejected(current.clone(), current_target));
+            }
+            if !target_state.can_enter(&self.context).await {
+                return Err(FsmError::GuardRejected(current_target));
+            }
+            let source = self.current_state.clone();
 
-            // Handle the on_enter response
-            match s.on_enter(&mut self.context).await {
-                Response::Handled => {
-                    return Ok(());
+            let exit_chain = match &self.current_state {
+                Some(current) => self.ancestors_of(current),
+                None => Vec::new(),
+            };
+            let enter_chain = self.ancestors_of(&current_target);
+            let lca = exit_chain
+                .iter()
+                .find(|s| **s != current_target && enter_chain.contains(s))
+                .cloned();
+
+            // Exit from the current state up to the common ancestor,
+            // remembering the active leaf for each exited state's deep history
+            for state in exit_chain.iter().take_while(|s| Some(*s) != lca.as_ref()) {
+                if !exited.insert(state.clone()) {
+                    continue;
                 }
-                Response::Transition(new_state) => {
-                    current_target = new_state;
-                    // Continue the loop with the new target
+                if let Some(actions) = self.exit_actions.get_mut(state) {
+                    for action in actions {
+                        action(&mut self.context);
+                    }
                 }
-                Response::Error(e) => return Err(FsmError::StateInvalid(current_target, e)),
-                Response::Super => {
-                    return Err(FsmError::OnEnterSuper(current_target.clone()));
+                let response = match self.states.get_mut(state) {
+                    Some(s) => {
+                        instrument!(
+                            s.on_exit_checked(&mut self.context),
+                            "on_exit",
+                            state = ?state
+                        )
+                        .await
+                    }
+                    None => Response::Handled,
+                }
+                .split_outputs(&mut self.outputs);
+                match response {
+                    // A veto keeps the state active
+                    Response::Error(e) => {
+                        exited.remove(state);
+                        return Err(FsmError::Handler(state.clone(), ErrorPhase::Exit, e.into()));
+                    }
+                    Response::Fail(e) => {
+                        exited.remove(state);
+                        return Err(FsmError::Handler(state.clone(), ErrorPhase::Exit, e));
+                    }
+                    _ => {}
+                }
+                self.deep_history
+                    .insert(state.clone(), exit_chain[0].clone());
+                match response {
+                    Response::Transition(new_state) => {
+                        current_target = new_state;
+                        payload = None;
+                        continue 'transition;
+                    }
+                    Response::TransitionWith(new_state, p) => {
+                        current_target = new_state;
+                        payload = Some(p);
+                        continue 'transition;
+                    }
+                    _ => {}
                 }
             }
-        }
-    }
-
-    /// Process an event
-    pub async fn process_event(&mut self, event: &E) -> Result<(), FsmError<S>> {
-        let mut current_state = self
-            .current_state
-            .clone()
-            .ok_or(FsmError::StateMachineNotInitialized)?;
-
-        loop {
-            let handler = if let Some(state_handler) = self.states.get_mut(&current_state) {
-                state_handler
-            } else {
-                return Err(FsmError::StateNotRegistered(current_state.clone()));
-            };
 
-            match handler.on_event(event, &mut self.context).await {
-                Response::Handled => return Ok(()),
-                Response::Transition(new_state) => {
-                    // DON'T log here - let transition_to handle all logging
-                    return self.transition_to(new_state).await;
+            // Enter from just below the common ancestor down to the target
+            let to_enter: Vec<S> = enter_chain
+                .into_iter()
+                .take_while(|s| Some(s) != lca.as_ref())
+                .collect();
+
+            for state in to_enter.into_iter().rev() {
+                let Some(s) = self.states.get_mut(&state) else {
+                    continue;
+                };
+
+                // Only the transition target receives the payload
+                let response = if state == current_target
+                    && let Some(p) = payload.take()
+                {
+                    instrument!(
+                        s.on_enter_with(&mut self.context, p),
+                        "on_enter",
+                        state = ?state
+                    )
+                    .await
+                } else {
+                    instrument!(s.on_enter(&mut self.context), "on_enter", state = ?state).await
                 }
-                Response::Super => {
-                    // Try to find superstate and delegate the event to it
-                    if let Some(super_s) = (self.superstate_fn)(&current_state) {
-                        current_state = super_s;
-                        // Continue the loop to process the same event in the superstate
-                    } else {
-                        // If no superstate, the event is unhandled
-                        return Err(FsmError::InvalidEvent(
-                            current_state,
-                            "Unhandled event, no superstate available".to_string(),
-                        ));
+                .split_outputs(&mut self.outputs);
+
+                let response = if matches!(response, Response::Error(_) | Response::Fail(_)) {
+                    let policy = s.enter_retry().await;
+                    let mut response = response;
+                    let mut retries = 0;
+                    while retries < policy.attempts
+                        && matches!(response, Response::Error(_) | Response::Fail(_))
+                    {
+                        retries += 1;
+                        backoff_sleep(policy.backoff).await;
+                        response = instrument!(
+                            s.on_enter(&mut self.context),
+                            "on_enter",
+                            state = ?state,
+                            retry = retries
+                        )
+                        .await
+                        .split_outputs(&mut self.outputs);
+                    }
+                    response
+                } else {
+                    response
+                };
+
+                // Only commit the state once on_enter has succeeded; if it
+                // fails, transition_to_with falls back to a still active state
+                let failed = matches!(
+                    response,
+                    Response::Error(_) | Response::Fail(_) | Response::Super | Response::Reenter
+                );
+                if !failed {
+                    if let Some(actions) = self.enter_actions.get_mut(&state) {
+                        for action in actions {
+                            action(&mut self.context);
+                        }
                     }
+                    self.current_state = Some(state.clone());
+                    self.entered_at = Some(Instant::now());
+                    self.entered_states.insert(state.clone());
+                    exited.remove(&state);
                 }
 
-                Response::Error(e) => {
-                    return Err(FsmError::InvalidEvent(current_state, e));
+                // The target has been entered, so the transition took place
+                let entered = state == current_target && !failed;
+                if entered && self.history.is_enabled() {
+                    self.history.record(source.clone(), state.clone());
+                }
+                if entered && let Some(from) = &source {
+                    self.transition_log.insert((from.clone(), state.clone()));
+                    if self.collect_metrics {
+                        self.metrics.record_transition(from);
+                    }
+                    self.transition_count += 1;
+                    self.previous_state = Some(from.clone());
+                    self.broadcast_state(&state);
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(from = ?from, to = ?state, "transition");
+                    for listener in &mut self.transition_listeners {
+                        listener(from, &state, &self.context);
+                    }
                 }
-            }
-        }
-    }
 
-    /// Get the current state
-    pub fn current_state(&self) -> Option<S> {
-        self.current_state.clone()
-    }
+                // Handle the on_enter response
+                match response {
+                    Response::Handled | Response::HandledInternal => {}
+                    Response::SilentTransition(new_state) => {
+                        return self.silent_transition(new_state);
+                    }
+                    Response::Transition(new_state) => {
+                        current_target = new_state;
+                        // Restart the transition from the state just entered
+                        continue 'transition;
+                    }
+                    Response::TransitionWith(new_state, p) => {
+                        current_target = new_state;
+                        payload = Some(p);
+                        continue 'transition;
+                    }
+                    Response::Push(new_state) => {
+                        if !self.states.contains_key(&new_state) {
+                            return Err(FsmError::StateNotRegistered(new_state));
+                        }
+                        self.state_stack.push(state);
+                        current_target = new_state;
+                        continue 'transition;
+                    }
+                    Response::Choice(choices) => {
+                        let Some(new_state) = pick(&mut self.rng_state, choices) else {
+                            return Err(FsmError::StateInvalid(state, EMPTY_CHOICE.to_string()));
+                        };
+                        current_target = new_state;
+                        continue 'transition;
+                    }
+                    Response::Pop => {
+                        current_target = self.state_stack.pop().ok_or(FsmError::EmptyStateStack)?;
+                        continue 'transition;
+                    }
+                    Response::Error(e) => return Err(FsmError::StateInvalid(state, e)),
+                    Response::Fail(e) => {
+                        return Err(FsmError::Handler(state, ErrorPhase::Enter, e));
+                    }
+                    Response::Super => {
+                        return Err(FsmError::OnEnterSuper(state));
+                    }
+                    Response::Reenter => {
+                        return Err(FsmError::StateInvalid(
+                            state,
+                            "on_enter cannot return Reenter".to_string(),
+                        ));
+                    }
+                    Response::Output(..) => {
+                        unreachable!("outputs are split off on_enter responses")
+                    }
+                }
+            }
 
-    /// Get a reference to the context
-    pub fn context(&self) -> &CTX {
-        &self.context
+            return Ok(());
+        }
     }
 
-    /// Get a mutable reference to the context
-    pub fn context_mut(&mut self) -> &mut CTX {
-        &mut self.context
+    /// Get the superstate of `state`, or `None` if it has none
+    ///
+    /// A parent declared by the state's own [`Stateful::superstate`] wins over
+    /// the superstate function.
+    pub fn superstate(&self, state: &S) -> Option<S> {
+        self.states
+            .get(state)
+            .and_then(|handler| handler.superstate())
+            .or_else(|| (self.superstate_fn)(state))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::builder::StateMachineBuilder;
-    use std::sync::{Arc, Mutex};
-    use tokio::time::Duration;
+    /// Get `state` followed by each of its superstates, leaf first
+    pub fn ancestors_of(&self, state: &S) -> Vec<S> {
+        let mut chain = vec![state.clone()];
+        let mut current = state.clone();
 
-    // Test state enum
-    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-    enum TestState {
-        Root,
-        Menu,
-        Settings,
-        Display,
-        Volume,
+        // A chain can never be longer than the state count
+        while chain.len() <= self.states.len()
+            && let Some(parent) = self.superstate(&current)
+        {
+            chain.push(parent.clone());
+            current = parent;
+        }
+        chain
     }
 
-    // Test event enum
-    #[derive(Debug, Clone)]
-    enum TestEvent {
-        Enter,
-        Back,
-        Up,
-        Down,
-        Select,
-        Timeout,
+    /// Publish a transition target to state broadcast subscribers, if any
+    #[cfg(feature = "tokio-integration")]
+    fn broadcast_state(&self, state: &S) {
+        if let Some(sender) = &self.state_broadcast {
+            // Sending only fails when there are no receivers left
+            let _ = sender.send(state.clone());
+        }
     }
 
-    // Test context
-    #[derive(Debug)]
-    struct TestContext {
-        pub value: i32,
-        pub transitions: Vec<String>,
-        pub entries: Vec<String>,
-        pub exits: Vec<String>,
-    }
+    #[cfg(not(feature = "tokio-integration"))]
+    fn broadcast_state(&self, _state: &S) {}
 
-    impl TestContext {
-        fn new() -> Self {
-            Self {
-                value: 0,
-                transitions: Vec::new(),
-                entries: Vec::new(),
-                exits: Vec::new(),
+    /// Switch to a new state without running any lifecycle hooks
+    fn silent_transition(&mut self, target: S) -> Result<(), FsmError<S>> {
+        if !self.states.contains_key(&target) {
+            return Err(FsmError::StateNotRegistered(target));
+        }
+
+        if self.history.is_enabled() {
+            self.history
+                .record(self.current_state.clone(), target.clone());
+        }
+        if let Some(current) = self.current_state.take() {
+            self.transition_count += 1;
+            self.previous_state = Some(current.clone());
+            self.broadcast_state(&target);
+            if self.collect_metrics {
+                self.metrics.record_transition(&current);
             }
+            self.transition_log.insert((current, target.clone()));
         }
+        self.current_state = Some(target);
+        self.entered_at = Some(Instant::now());
+        Ok(())
     }
 
-    // Root state implementation
-    struct RootState;
-
-    #[async_trait]
-    impl Stateful<TestState, TestContext, TestEvent> for RootState {
-        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
-            context.entries.push("Root".to_string());
-            Response::Handled
+    /// Save the active state on the state stack, then transition to `target`
+    async fn push_state(&mut self, target: S) -> Result<(), FsmError<S>> {
+        if !self.states.contains_key(&target) {
+            return Err(FsmError::StateNotRegistered(target));
         }
+        let active = self
+            .current_state
+            .clone()
+            .ok_or(FsmError::StateMachineNotInitialized)?;
+        self.state_stack.push(active.clone());
 
-        async fn on_event(
-            &mut self,
-            event: &TestEvent,
-            context: &mut TestContext,
-        ) -> Response<TestState> {
-            match event {
-                TestEvent::Enter => {
-                    context.transitions.push("Root->Menu".to_string());
-                    Response::Transition(TestState::Menu)
-                }
-                _ => Response::Error("Root: Unhandled event".to_string()),
-            }
+        let result = self.transition_to(target).await;
+        if result.is_err() && self.never_left(&Some(active)) {
+            self.state_stack.pop();
         }
+        result
+    }
 
-        async fn on_exit(&mut self, context: &mut TestContext) {
-            context.exits.push("Root".to_string());
-        }
+    /// Transition back to the state on top of the state stack
+    async fn pop_state(&mut self) -> Result<(), FsmError<S>> {
+        let previous = self.state_stack.pop().ok_or(FsmError::EmptyStateStack)?;
+        let active = self.current_state.clone();
 
-        async fn get_timeout(&self, _context: &TestContext) -> Option<Duration> {
-            Some(Duration::from_secs(30))
+        let result = self.transition_to(previous.clone()).await;
+        if result.is_err() && self.never_left(&active) {
+            self.state_stack.push(previous);
         }
+        result
     }
 
-    // Menu state implementation
-    struct MenuState;
+    /// Check whether a failed transition left the machine in `active`, as
+    /// when a guard refused it before any state was exited
+    fn never_left(&self, active: &Option<S>) -> bool {
+        self.pending_transition.is_none() && self.current_state == *active
+    }
 
-    #[async_trait]
-    impl Stateful<TestState, TestContext, TestEvent> for MenuState {
-        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
-            context.entries.push("Menu".to_string());
-            Response::Handled
+    /// Process an event, then any events queued while handling it
+    ///
+    /// # Cancellation
+    /// Dropping the returned future (for example when it loses a
+    /// `tokio::select!` or `timeout` race) is safe while middleware or
+    /// `on_event` handlers are running: the machine stays in its current
+    /// state. Once a transition has started, cancelling it can leave states
+    /// exited but not entered. The machine records this, and every later
+    /// event or transition fails with [`FsmError::InconsistentState`] until
+    /// [`init`](Self::init) or [`reset`](Self::reset) is called.
+    ///
+    /// # Errors
+    /// In a [terminal](crate::StateMachineBuilder::terminal) state, every
+    /// event fails with [`FsmError::Terminal`] without reaching any handler.
+    pub async fn process_event(&mut self, event: &E) -> Result<(), FsmError<S>> {
+        self.outputs.clear();
+        self.last_transitioned = false;
+        self.handled_internally = false;
+        if let Some(copy) = self.suspended {
+            self.suspended_events.push_back(copy(event));
+            return Ok(());
         }
+        let count = self.transition_count;
+        let result = if let Some(current) = &self.current_state
+            && self.terminal_states.contains(current)
+        {
+            Err(FsmError::Terminal(current.clone()))
+        } else {
+            match self.dispatch_event(event).await {
+                Ok(()) => self.drain_event_queue().await,
+                Err(err) => Err(err),
+            }
+        };
+        self.history.current_event = None;
+        let result = self.finish_dispatch(result, count).await;
 
-        async fn on_event(
-            &mut self,
-            event: &TestEvent,
-            context: &mut TestContext,
-        ) -> Response<TestState> {
-            match event {
-                TestEvent::Back => {
-                    context.transitions.push("Menu->Root".to_string());
-                    Response::Transition(TestState::Root)
-                }
-                TestEvent::Select => {
-                    context.transitions.push("Menu->Settings".to_string());
-                    Response::Transition(TestState::Settings)
-                }
-                TestEvent::Up | TestEvent::Down => {
-                    context.value += if matches!(event, TestEvent::Up) {
-                        1
-                    } else {
-                        -1
-                    };
-                    Response::Handled
-                }
-                _ => Response::Super, // Delegate to superstate
+        if let Err(err) = &result
+            && let Some(current) = &self.current_state
+        {
+            for listener in &mut self.rejection_listeners {
+                listener(current, event, err);
             }
         }
 
-        async fn on_exit(&mut self, context: &mut TestContext) {
-            context.exits.push("Menu".to_string());
+        #[cfg(feature = "tracing")]
+        if let Err(err) = &result {
+            tracing::error!(event = ?event, error = %err, "event failed");
         }
+        result
+    }
 
-        async fn get_timeout(&self, context: &TestContext) -> Option<Duration> {
-            if context.value > 5 {
-                Some(Duration::from_secs(5)) // Short timeout when value is high
-            } else {
-                Some(Duration::from_secs(10))
+    /// Deserialize an event from JSON and process it like
+    /// [`process_event`](Self::process_event)
+    ///
+    /// Returns [`FsmError::EventDecode`] if `json` is not a valid `E`, in
+    /// which case no event is processed.
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub async fn process_json(&mut self, json: &str) -> Result<(), FsmError<S>>
+    where
+        E: serde::de::DeserializeOwned,
+    {
+        let event: E =
+            serde_json::from_str(json).map_err(|e| FsmError::EventDecode(e.to_string()))?;
+        self.process_event(&event).await
+    }
+
+    /// Process an event, first processing the current state's timeout if it
+    /// has already elapsed
+    ///
+    /// An alternative to a background timer: the timeout from
+    /// [`Stateful::get_timeout`] is measured from when the current state was
+    /// entered, and only checked when the next event arrives. An elapsed
+    /// timeout is handled as by a timeout driver: the machine
+    /// [processes the timeout](Self::process_timeout), and if that does not
+    /// cause a transition, processes the
+    /// [timeout event](Self::current_timeout_event), or `timeout_event` if
+    /// the machine has none. `event` is then handled by whatever state the
+    /// timeout led to.
+    ///
+    /// # Errors
+    /// If the timeout or the timeout event fails, that error is returned and
+    /// `event` is not processed, so the caller can still decide what to do
+    /// with it. The failure is recovered from as usual, with
+    /// [`on_error_goto`](crate::StateMachineBuilder::on_error_goto).
+    pub async fn process_event_with_elapsed_timeout(
+        &mut self,
+        event: &E,
+        timeout_event: E,
+    ) -> Result<(), FsmError<S>> {
+        if let Some(timeout) = self.get_current_timeout().await
+            && let Some(elapsed) = self.time_in_state()
+            && elapsed >= timeout
+        {
+            self.process_timeout().await?;
+            if !self.last_transitioned {
+                let timeout_event = self.current_timeout_event().unwrap_or(timeout_event);
+                self.process_event(&timeout_event).await?;
             }
         }
+        self.process_event(event).await
     }
 
-    // Settings state implementation
-    struct SettingsState;
+    /// Process an event like [`process_event`](Self::process_event), reporting
+    /// the states before and after
+    pub async fn process_event_tracked(
+        &mut self,
+        event: &E,
+    ) -> Result<TransitionOutcome<S>, FsmError<S>> {
+        let from = self.current_state.clone();
+        let count = self.transition_count;
 
-    #[async_trait]
-    impl Stateful<TestState, TestContext, TestEvent> for SettingsState {
-        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
-            context.entries.push("Settings".to_string());
-            Response::Handled
-        }
+        self.process_event(event).await?;
 
-        async fn on_event(
-            &mut self,
-            event: &TestEvent,
-            _context: &mut TestContext,
-        ) -> Response<TestState> {
-            match event {
-                TestEvent::Select => Response::Transition(TestState::Display), // This should trigger the transition
-                TestEvent::Back => Response::Transition(TestState::Menu),
-                _ => Response::Super, // Only delegate unhandled events
+        let to = self
+            .current_state
+            .clone()
+            .ok_or(FsmError::StateMachineNotInitialized)?;
+        Ok(TransitionOutcome {
+            from,
+            to,
+            transitioned: self.transition_count != count,
+        })
+    }
+
+    /// Process an event like [`process_event`](Self::process_event),
+    /// returning the outputs attached with [`Response::with_output`]
+    ///
+    /// Outputs are returned in the order they were attached: from `on_exit`
+    /// and `on_enter` as well as from the state handling the event and any
+    /// superstates it was delegated to, and from queued events processed in
+    /// the same call.
+    ///
+    /// # Errors
+    /// Returns any error from [`process_event`](Self::process_event), or
+    /// [`FsmError::OutputType`] if any output is not an `O`. The event has
+    /// still been processed in that case, and all of its outputs are dropped.
+    pub async fn process_event_with_output<O: Any>(
+        &mut self,
+        event: &E,
+    ) -> Result<Vec<O>, FsmError<S>> {
+        self.process_event(event).await?;
+        self.outputs
+            .drain(..)
+            .map(|output| {
+                let output: Box<dyn Any> = output;
+                output
+                    .downcast()
+                    .map(|output| *output)
+                    .map_err(|_| FsmError::OutputType(std::any::type_name::<O>()))
+            })
+            .collect()
+    }
+
+    /// Get the queue handlers can post follow-up events to
+    ///
+    /// Queued events are processed, highest priority first, after the current
+    /// [`process_event`](Self::process_event) (or [`init`](Self::init)) call
+    /// completes. At most `max_queued_events` are processed per call, to
+    /// guard against handlers that keep posting events forever.
+    ///
+    /// Events pushed while handling an event that then fails are discarded,
+    /// so they are not replayed by a later call. This includes events pushed
+    /// by other holders of the queue during that time.
+    pub fn event_queue(&self) -> &EventQueue<E> {
+        &self.event_queue
+    }
+
+    /// Process queued events until the queue is empty
+    async fn drain_event_queue(&mut self) -> Result<(), FsmError<S>> {
+        let mut processed = 0;
+
+        while let Some(event) = self.event_queue.pop() {
+            processed += 1;
+            if processed > self.max_queued_events {
+                self.event_queue.clear();
+                return Err(FsmError::EventQueueOverflow(self.max_queued_events));
             }
+            self.dispatch_event(&event).await?;
         }
+        Ok(())
+    }
 
-        async fn on_exit(&mut self, context: &mut TestContext) {
-            context.exits.push("Settings".to_string());
+    /// Dispatch a single event, discarding any events it queued if it fails
+    async fn dispatch_event(&mut self, event: &E) -> Result<(), FsmError<S>> {
+        let mark = self.event_queue.mark();
+        let result = self.route_event(event).await;
+        if result.is_err() {
+            self.event_queue.discard_since(mark);
         }
+        result
     }
 
-    // Display state implementation
-    struct DisplayState;
+    /// Route a single event to the current state and its superstates
+    async fn route_event(&mut self, event: &E) -> Result<(), FsmError<S>> {
+        if let Some(pending) = &self.pending_transition {
+            return Err(FsmError::InconsistentState(pending.clone()));
+        }
+        if self.current_state.is_none() {
+            return Err(FsmError::StateMachineNotInitialized);
+        }
+        if self.history.is_enabled() {
+            self.history.current_event = Some(format!("{event:?}"));
+        }
 
-    #[async_trait]
-    impl Stateful<TestState, TestContext, TestEvent> for DisplayState {
-        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
-            context.entries.push("Display".to_string());
-            Response::Handled
+        if self.collect_metrics {
+            self.metrics.record_dispatch();
         }
 
-        async fn on_event(
-            &mut self,
-            event: &TestEvent,
-            context: &mut TestContext,
-        ) -> Response<TestState> {
-            match event {
-                TestEvent::Up => {
-                    context.value += 10;
-                    Response::Handled
-                }
-                TestEvent::Down => {
-                    context.value -= 10;
-                    Response::Handled
-                }
-                _ => Response::Super,
+        // Give every middleware a chance to veto the event
+        for middleware in &mut self.middleware {
+            if let MiddlewareDecision::Reject(reason) =
+                middleware.before(event, &mut self.context).await
+            {
+                return Err(FsmError::Rejected(reason));
             }
         }
 
-        async fn on_exit(&mut self, context: &mut TestContext) {
-            context.exits.push("Display".to_string());
-        }
+        // Number of superstate delegations performed for this event
+        let mut depth = 0;
+        // Response to process instead of asking a state: from the global
+        // handler up front, or from the unhandled-event fallback, which runs
+        // at most once
+        let mut fallback_response = self
+            .global_handler
+            .as_mut()
+            .and_then(|global| global(event, &mut self.context))
+            .map(|response| response.split_outputs(&mut self.outputs))
+            .filter(|response| !matches!(response, Response::Super));
+        let mut fell_back = false;
+        // Superstate the event has been delegated to, if any. The current
+        // state is borrowed rather than cloned, so handling an event clones
+        // no state ids unless it fails.
+        let mut delegated: Option<S> = None;
 
-        async fn get_timeout(&self, _context: &TestContext) -> Option<Duration> {
-            None // No timeout for display state
-        }
-    }
+        loop {
+            let Some(current_state) = delegated.as_ref().or(self.current_state.as_ref()) else {
+                return Err(FsmError::StateMachineNotInitialized);
+            };
+            let from_fallback = fallback_response.is_some();
+            let response = if let Some(response) = fallback_response.take() {
+                // Reported for the active state, whichever state fell back
+                if let Some(active) = &self.current_state {
+                    for observer in &mut self.event_observers {
+                        observer(active, event, &response.kind());
+                    }
+                }
+                response
+            } else {
+                let handler = if let Some(state_handler) = self.states.get_mut(current_state) {
+                    state_handler
+                } else {
+                    return Err(FsmError::StateNotRegistered(current_state.clone()));
+                };
+
+                let response = instrument!(
+                    handler.on_event_in(current_state, event, &mut self.context),
+                    "on_event",
+                    state = ?current_state,
+                    event = ?event
+                )
+                .await;
+                for observer in &mut self.event_observers {
+                    observer(current_state, event, &response.kind());
+                }
+                response
+            }
+            .split_outputs(&mut self.outputs);
 
-    // State that transitions on enter
-    struct TransitionOnEnterState;
+            if self.collect_metrics
+                && !matches!(
+                    response,
+                    Response::Super | Response::Error(_) | Response::Fail(_)
+                )
+            {
+                if from_fallback {
+                    self.metrics.record_fallback_event();
+                } else {
+                    self.metrics.record_event(current_state);
+                }
+            }
 
-    #[async_trait]
-    impl Stateful<TestState, TestContext, TestEvent> for TransitionOnEnterState {
-        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
-            context.entries.push("Volume".to_string());
-            Response::Transition(TestState::Root) // Immediately transition to Root
+            match response {
+                Response::Handled => return Ok(()),
+                Response::HandledInternal => {
+                    self.handled_internally = true;
+                    return Ok(());
+                }
+                Response::SilentTransition(new_state) => {
+                    self.silent_transition(new_state)?;
+                    self.refresh_cached_timeout().await;
+                    return Ok(());
+                }
+                Response::Transition(new_state) => {
+                    // DON'T log here - let transition_to handle all logging
+                    return self.transition_to(new_state).await;
+                }
+                Response::TransitionWith(new_state, p) => {
+                    return self.transition_to_with(new_state, Some(p)).await;
+                }
+                Response::Push(new_state) => {
+                    return self.push_state(new_state).await;
+                }
+                Response::Choice(choices) => {
+                    let Some(new_state) = pick(&mut self.rng_state, choices) else {
+                        return Err(FsmError::InvalidEvent(
+                            current_state.clone(),
+                            EMPTY_CHOICE.to_string(),
+                        ));
+                    };
+                    return self.transition_to(new_state).await;
+                }
+                Response::Pop => {
+                    return self.pop_state().await;
+                }
+                Response::Reenter => {
+                    // Re-enter the active state, even if a superstate asked for it
+                    let active = self
+                        .current_state
+                        .clone()
+                        .ok_or(FsmError::StateMachineNotInitialized)?;
+                    return self.transition_to(active).await;
+                }
+                Response::Super => {
+                    let parent = match &self.async_superstate_fn {
+                        Some(resolve) => resolve(current_state, &self.context).await,
+                        None => self.superstate(current_state),
+                    };
+                    // Try to find superstate and delegate the event to it
+                    if let Some(super_s) = parent {
+                        // A chain can never be longer than the state count
+                        depth += 1;
+                        if depth > self.states.len() {
+                            return Err(FsmError::SuperstateDepthExceeded(current_state.clone()));
+                        }
+                        delegated = Some(super_s);
+                        // Continue the loop to process the same event in the superstate
+                    } else if !fell_back
+                        && let Some(fallback) = &mut self.unhandled_handler
+                        && let Some(active) = &self.current_state
+                    {
+                        fell_back = true;
+                        fallback_response = Some(fallback(active, event, &mut self.context));
+                    } else {
+                        // If no superstate, the event is unhandled
+                        return Err(FsmError::InvalidEvent(
+                            current_state.clone(),
+                            "Unhandled event, no superstate available".to_string(),
+                        ));
+                    }
+                }
+
+                Response::Error(e) => {
+                    return Err(FsmError::InvalidEvent(current_state.clone(), e));
+                }
+                Response::Fail(e) => {
+                    return Err(FsmError::Handler(
+                        current_state.clone(),
+                        ErrorPhase::Event,
+                        e,
+                    ));
+                }
+                Response::Output(..) => unreachable!("outputs are split off event responses"),
+            }
+        }
+    }
+
+    /// Let the current state react to its timeout elapsing
+    ///
+    /// Calls [`Stateful::on_timeout`] on the current state, then on its
+    /// superstates in turn while they return [`Response::Super`], and acts on
+    /// the response as for an event. A timeout no state handles is ignored.
+    /// Timeout drivers such as `tokio_utils::spawn_actor` call this before
+    /// injecting any [timeout event](Self::current_timeout_event), which is
+    /// skipped if `on_timeout` made a transition.
+    ///
+    /// Does nothing while [suspended](Self::suspend). Failures are recovered
+    /// from with [`on_error_goto`](crate::StateMachineBuilder::on_error_goto)
+    /// and counted in the metrics as for an event, but are not reported to
+    /// [`on_rejected`](crate::StateMachineBuilder::on_rejected) listeners,
+    /// since there is no event to report.
+    ///
+    /// # Errors
+    /// Fails with [`FsmError::Terminal`] in a terminal state, or with any
+    /// error from `on_timeout` or the transition it requests.
+    pub async fn process_timeout(&mut self) -> Result<(), FsmError<S>> {
+        self.outputs.clear();
+        self.last_transitioned = false;
+        self.handled_internally = false;
+        if self.suspended.is_some() {
+            return Ok(());
+        }
+        let count = self.transition_count;
+        let result = if let Some(current) = &self.current_state
+            && self.terminal_states.contains(current)
+        {
+            Err(FsmError::Terminal(current.clone()))
+        } else {
+            let mark = self.event_queue.mark();
+            match self.dispatch_timeout().await {
+                Ok(()) => self.drain_event_queue().await,
+                Err(err) => {
+                    self.event_queue.discard_since(mark);
+                    Err(err)
+                }
+            }
+        };
+        let result = self.finish_dispatch(result, count).await;
+
+        #[cfg(feature = "tracing")]
+        if let Err(err) = &result {
+            tracing::error!(error = %err, "timeout failed");
+        }
+        result
+    }
+
+    /// Recover from a failed event or timeout and record the outcome
+    ///
+    /// `count` is the transition count from before the event or timeout.
+    async fn finish_dispatch(
+        &mut self,
+        result: Result<(), FsmError<S>>,
+        count: u64,
+    ) -> Result<(), FsmError<S>> {
+        // Counted once, against the original error, whether or not
+        // recovering from it succeeds
+        if let Err(err) = &result
+            && self.collect_metrics
+            && let Some(state) = error_source(err).or(self.current_state.as_ref())
+        {
+            self.metrics.record_error(state);
+        }
+
+        let result = self.recover(result).await;
+        self.last_transitioned = result.is_ok() && self.transition_count != count;
+        result
+    }
+
+    /// Call `on_timeout` on the current state and its superstates
+    async fn dispatch_timeout(&mut self) -> Result<(), FsmError<S>> {
+        if let Some(pending) = &self.pending_transition {
+            return Err(FsmError::InconsistentState(pending.clone()));
+        }
+        let mut state = self
+            .current_state
+            .clone()
+            .ok_or(FsmError::StateMachineNotInitialized)?;
+        let mut depth = 0;
+
+        loop {
+            let Some(handler) = self.states.get_mut(&state) else {
+                return Err(FsmError::StateNotRegistered(state));
+            };
+            let response = instrument!(
+                handler.on_timeout(&mut self.context),
+                "on_timeout",
+                state = ?state
+            )
+            .await
+            .split_outputs(&mut self.outputs);
+
+            match response {
+                Response::Handled => return Ok(()),
+                Response::HandledInternal => {
+                    self.handled_internally = true;
+                    return Ok(());
+                }
+                Response::Super => {
+                    let Some(parent) = self.superstate(&state) else {
+                        return Ok(());
+                    };
+                    depth += 1;
+                    if depth > self.states.len() {
+                        return Err(FsmError::SuperstateDepthExceeded(state));
+                    }
+                    state = parent;
+                }
+                Response::Transition(new_state) => return self.transition_to(new_state).await,
+                Response::TransitionWith(new_state, p) => {
+                    return self.transition_to_with(new_state, Some(p)).await;
+                }
+                Response::SilentTransition(new_state) => {
+                    self.silent_transition(new_state)?;
+                    self.refresh_cached_timeout().await;
+                    return Ok(());
+                }
+                Response::Push(new_state) => return self.push_state(new_state).await,
+                Response::Pop => return self.pop_state().await,
+                Response::Choice(choices) => {
+                    let Some(new_state) = pick(&mut self.rng_state, choices) else {
+                        return Err(FsmError::InvalidEvent(state, EMPTY_CHOICE.to_string()));
+                    };
+                    return self.transition_to(new_state).await;
+                }
+                Response::Reenter => {
+                    let active = self
+                        .current_state
+                        .clone()
+                        .ok_or(FsmError::StateMachineNotInitialized)?;
+                    return self.transition_to(active).await;
+                }
+                Response::Error(e) => return Err(FsmError::InvalidEvent(state, e)),
+                Response::Fail(e) => {
+                    return Err(FsmError::Handler(state, ErrorPhase::Event, e));
+                }
+                Response::Output(..) => unreachable!("outputs are split off timeout responses"),
+            }
+        }
+    }
+
+    /// Process a sequence of events in order
+    ///
+    /// Stops at the first failure, returning its index in `events` along with
+    /// the error. Events already processed are not rolled back.
+    pub async fn process_events(&mut self, events: &[E]) -> Result<(), (usize, FsmError<S>)> {
+        for (index, event) in events.iter().enumerate() {
+            self.process_event(event)
+                .await
+                .map_err(|err| (index, err))?;
+        }
+        Ok(())
+    }
+
+    /// Process events from the front of `queue` until it is empty
+    ///
+    /// Returns the number of events processed. On failure, returns the
+    /// index of the failing event (the number processed before it) along
+    /// with the error; the failing event has been removed from `queue`, and
+    /// every event after it is left queued so processing can be resumed.
+    /// Events already processed are not rolled back.
+    pub async fn process_queue(
+        &mut self,
+        queue: &mut VecDeque<E>,
+    ) -> Result<usize, (usize, FsmError<S>)> {
+        let mut processed = 0;
+        while let Some(event) = queue.pop_front() {
+            self.process_event(&event)
+                .await
+                .map_err(|err| (processed, err))?;
+            processed += 1;
+        }
+        Ok(processed)
+    }
+
+    /// Stop processing events until [`resume`](Self::resume) is called
+    ///
+    /// While suspended, [`process_event`](Self::process_event) buffers a copy
+    /// of each event and returns `Ok(())` without dispatching it.
+    pub fn suspend(&mut self)
+    where
+        E: Clone,
+    {
+        self.suspended = Some(E::clone);
+    }
+
+    /// Resume processing, first processing the events buffered while
+    /// suspended, in the order they arrived
+    ///
+    /// Does nothing if the machine is not suspended.
+    ///
+    /// # Errors
+    /// Stops at the first buffered event that fails and returns its error.
+    /// The machine then stays suspended, with the events after the failing
+    /// one still buffered; events already processed are not rolled back.
+    pub async fn resume(&mut self) -> Result<(), FsmError<S>> {
+        let Some(copy) = self.suspended.take() else {
+            return Ok(());
+        };
+        let mut events = std::mem::take(&mut self.suspended_events);
+        if let Err((_, err)) = self.process_queue(&mut events).await {
+            self.suspended = Some(copy);
+            self.suspended_events = events;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Check whether event processing is [suspended](Self::suspend)
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Get the current state
+    pub fn current_state(&self) -> Option<S> {
+        self.current_state.clone()
+    }
+
+    /// Check whether the last [`process_event`](Self::process_event) call
+    /// succeeded with a transition, including re-entering the same state
+    ///
+    /// `false` if it failed, was buffered while [suspended](Self::suspend),
+    /// or no event has been processed yet.
+    pub fn last_transitioned(&self) -> bool {
+        self.last_transitioned
+    }
+
+    /// Check whether the last [`process_event`](Self::process_event) call
+    /// was answered with [`Response::HandledInternal`]
+    ///
+    /// Marks an event that was handled without any transition or lifecycle
+    /// hooks. Also set by a queued event processed in the same call.
+    pub fn last_handled_internally(&self) -> bool {
+        self.handled_internally
+    }
+
+    /// Get the number of states saved by [`Response::Push`] awaiting a [`Response::Pop`]
+    pub fn stack_depth(&self) -> usize {
+        self.state_stack.len()
+    }
+
+    /// Get the identifiers of all registered states, in unspecified order
+    pub fn registered_states(&self) -> Vec<S> {
+        self.states.keys().cloned().collect()
+    }
+
+    /// Check whether a state has been registered
+    pub fn is_registered(&self, state: &S) -> bool {
+        self.states.contains_key(state)
+    }
+
+    /// Replace the handler registered for `state`, returning the old handler
+    ///
+    /// The new handler is used from the next time `state` is entered, or
+    /// registers `state` if it was not registered before.
+    ///
+    /// # Errors
+    /// Returns [`FsmError::StateActive`] if `state`, or an alias sharing its
+    /// handler, is the current state or one of its superstates.
+    pub fn replace_state(
+        &mut self,
+        state: S,
+        handler: BoxedState<S, CTX, E>,
+    ) -> Result<Option<BoxedState<S, CTX, E>>, FsmError<S>> {
+        if self.is_handler_active(&state) {
+            return Err(FsmError::StateActive(state));
+        }
+        Ok(self.states.insert(state, handler))
+    }
+
+    /// Deregister `state`, along with any aliases of it
+    ///
+    /// Returns [`FsmError::StateActive`] if `state` or one of its aliases is
+    /// the current state or one of its superstates, or
+    /// [`FsmError::StateNotRegistered`] if it was not registered. Transitions
+    /// to a removed state fail as for any unregistered state.
+    pub fn remove_state(&mut self, state: &S) -> Result<(), FsmError<S>> {
+        if self.is_handler_active(state) {
+            return Err(FsmError::StateActive(state.clone()));
+        }
+        if !self.states.remove(state) {
+            return Err(FsmError::StateNotRegistered(state.clone()));
+        }
+        Ok(())
+    }
+
+    /// Check whether `state` or an alias sharing its handler is active
+    fn is_handler_active(&self, state: &S) -> bool {
+        self.is_in(state) || self.states.aliases_of(state).any(|alias| self.is_in(alias))
+    }
+
+    /// Get the current state followed by each of its superstates, leaf first
+    ///
+    /// Returns an empty `Vec` when the state machine has not been initialized.
+    pub fn ancestors(&self) -> Vec<S> {
+        self.current_state
+            .as_ref()
+            .map(|current| self.ancestors_of(current))
+            .unwrap_or_default()
+    }
+
+    /// Check whether the machine is in a
+    /// [terminal](crate::StateMachineBuilder::terminal) state
+    pub fn is_terminal(&self) -> bool {
+        self.current_state
+            .as_ref()
+            .is_some_and(|current| self.terminal_states.contains(current))
+    }
+
+    /// Check whether `state` is the current state or one of its superstates
+    ///
+    /// Returns `false` when the state machine has not been initialized.
+    pub fn is_in(&self, state: &S) -> bool {
+        self.current_state
+            .as_ref()
+            .is_some_and(|current| self.ancestors_of(current).contains(state))
+    }
+
+    /// Clone the whole machine, for example to run a hypothetical sequence
+    /// of events and discard the result
+    ///
+    /// The clone gets copies of the context, every state handler, and all
+    /// runtime state: the current state, logs, history, state stack, and a
+    /// new event queue holding the events still pending in the original. It
+    /// shares the superstate function and edge guards with the original.
+    /// Transition listeners, event observers, rejection listeners, and the
+    /// state broadcast are not carried over, so the clone runs without
+    /// reporting to them.
+    ///
+    /// Handles to the original event queue stored in the context still post
+    /// to the original; use [`event_queue`](Self::event_queue) on the clone to
+    /// reach its own queue.
+    ///
+    /// This is a fallible method rather than a [`Clone`] impl, because
+    /// handlers registered with [`StateMachineBuilder::state`](crate::StateMachineBuilder::state)
+    /// and `FnMut` callbacks cannot be cloned.
+    ///
+    /// Returns `None` if any state was not added with
+    /// [`StateMachineBuilder::cloneable_state`](crate::StateMachineBuilder::cloneable_state)
+    /// or [`StateMachineBuilder::shared_state`](crate::StateMachineBuilder::shared_state),
+    /// whose handlers the clone shares with the original, or if middleware, a global or unhandled-event handler, or enter/exit
+    /// actions are registered, since those cannot be cloned and the clone
+    /// would otherwise behave differently.
+    pub fn try_clone(&self) -> Option<Self>
+    where
+        CTX: Clone,
+        E: Clone,
+    {
+        if !self.middleware.is_empty()
+            || self.global_handler.is_some()
+            || self.unhandled_handler.is_some()
+            || !self.enter_actions.is_empty()
+            || !self.exit_actions.is_empty()
+        {
+            return None;
+        }
+
+        let mut clone = Self::with_registry(self.context.clone(), self.states.try_clone()?, None);
+        clone.current_state = self.current_state.clone();
+        clone.superstate_fn = Arc::clone(&self.superstate_fn);
+        clone.async_superstate_fn = self.async_superstate_fn.clone();
+        clone.initial_state = self.initial_state.clone();
+        clone.transition_log = self.transition_log.clone();
+        clone.entered_states = self.entered_states.clone();
+        clone.history = self.history.clone();
+        clone.edge_guards = self.edge_guards.clone();
+        clone.invariants = self.invariants.clone();
+        clone.allowed_edges = self.allowed_edges.clone();
+        clone.terminal_states = self.terminal_states.clone();
+        clone.error_recovery = self.error_recovery.clone();
+        clone.event_queue = self.event_queue.deep_copy();
+        clone.max_queued_events = self.max_queued_events;
+        clone.max_enter_chain = self.max_enter_chain;
+        clone.collect_metrics = self.collect_metrics;
+        clone.metrics = self.metrics.clone();
+        clone.deep_history = self.deep_history.clone();
+        clone.state_stack = self.state_stack.clone();
+        clone.previous_state = self.previous_state.clone();
+        clone.pending_transition = self.pending_transition.clone();
+        clone.transition_count = self.transition_count;
+        clone.last_transitioned = self.last_transitioned;
+        clone.handled_internally = self.handled_internally;
+        clone.cached_timeout = self.cached_timeout;
+        clone.entered_at = self.entered_at;
+        clone.prepared = self.prepared;
+        clone.suspended = self.suspended;
+        clone.rng_state = self.rng_state;
+        clone.timeout_event = self
+            .timeout_event
+            .as_ref()
+            .map(|(event, copy)| (copy(event), *copy));
+        if let Some(copy) = self.suspended {
+            clone.suspended_events = self.suspended_events.iter().map(copy).collect();
+        }
+        Some(clone)
+    }
+
+    /// Capture the current state, initial state, and transition log
+    pub fn snapshot(&self) -> FsmSnapshot<S> {
+        FsmSnapshot {
+            current_state: self.current_state.clone(),
+            initial_state: self.initial_state.clone(),
+            transition_log: self.transition_log.clone(),
+        }
+    }
+
+    /// Resume from a snapshot taken with [`snapshot`](Self::snapshot)
+    ///
+    /// This is a resume, not a transition: no `on_exit` or `on_enter` hooks
+    /// run and no transition listeners are notified. The context is left as
+    /// is, and the snapshot's states are not checked against the registered
+    /// states.
+    pub fn restore_snapshot(&mut self, snapshot: FsmSnapshot<S>) {
+        self.current_state = snapshot.current_state;
+        // Cannot await get_timeout here; the next transition refreshes it
+        self.cached_timeout = None;
+        self.entered_at = self.current_state.as_ref().map(|_| Instant::now());
+        self.initial_state = snapshot.initial_state;
+        self.transition_log = snapshot.transition_log;
+    }
+
+    /// Resume from a snapshot, entering its current state
+    ///
+    /// Unlike [`restore_snapshot`](Self::restore_snapshot), the snapshot's
+    /// current state is entered with the usual hooks, so it can re-establish
+    /// any runtime resources. If the machine is already running, its current
+    /// state is exited first.
+    ///
+    /// # Errors
+    /// Returns [`FsmError::StateNotRegistered`] if the snapshot's current
+    /// state is not registered, or [`FsmError::StateMachineNotInitialized`]
+    /// if the snapshot has no current state. The machine is left unchanged
+    /// in both cases.
+    pub async fn restore_and_enter(&mut self, snapshot: FsmSnapshot<S>) -> Result<(), FsmError<S>> {
+        let target = snapshot
+            .current_state
+            .ok_or(FsmError::StateMachineNotInitialized)?;
+        if !self.states.contains_key(&target) {
+            return Err(FsmError::StateNotRegistered(target));
+        }
+
+        self.initial_state = snapshot.initial_state;
+        self.transition_log = snapshot.transition_log;
+        self.transition_to(target).await?;
+        self.drain_event_queue().await
+    }
+
+    /// Get the per-state event, transition, and error counters
+    ///
+    /// Counters stay at zero unless collection was enabled with
+    /// [`StateMachineBuilder::collect_metrics`](crate::StateMachineBuilder::collect_metrics).
+    pub fn metrics(&self) -> &Metrics<S> {
+        &self.metrics
+    }
+
+    /// Get every transition taken, oldest first
+    ///
+    /// Empty unless enabled with
+    /// [`StateMachineBuilder::with_history_log`](crate::StateMachineBuilder::with_history_log).
+    /// Once the configured capacity is reached, the oldest records are dropped.
+    pub fn history(&self) -> &[TransitionRecord<S>] {
+        self.history.records()
+    }
+
+    /// Get the set of unique `(from, to)` transitions taken so far
+    pub fn transition_log(&self) -> &HashSet<(S, S)> {
+        &self.transition_log
+    }
+
+    /// Add the edges of another transition log to this machine's log
+    ///
+    /// Useful for aggregating the transitions seen across several runs into
+    /// one diagram, such as [`to_plantuml`](Self::to_plantuml). Edges already
+    /// in the log are kept once; nothing else about the machine changes.
+    pub fn merge_transition_log(&mut self, other: &HashSet<(S, S)>) {
+        self.transition_log.extend(other.iter().cloned());
+    }
+
+    /// Add the edges recorded in a snapshot to this machine's transition log
+    ///
+    /// Unlike [`restore_snapshot`](Self::restore_snapshot), only the edges are
+    /// taken; the current and initial states are left as they are.
+    pub fn import_snapshot_edges(&mut self, snapshot: &FsmSnapshot<S>) {
+        self.merge_transition_log(&snapshot.transition_log);
+    }
+
+    /// Get every state reachable from the current state by following known
+    /// edges, including the current state itself
+    ///
+    /// Follows the edges declared with
+    /// [`StateMachineBuilder::allowed_edge`](crate::StateMachineBuilder::allowed_edge)
+    /// if there are any, otherwise the edges in the
+    /// [transition log](Self::transition_log). Returns an empty set when the
+    /// state machine has not been initialized.
+    pub fn reachable(&self) -> HashSet<S> {
+        let edges = if self.allowed_edges.is_empty() {
+            &self.transition_log
+        } else {
+            &self.allowed_edges
+        };
+        let mut reached: HashSet<S> = self.current_state.iter().cloned().collect();
+        let mut frontier: VecDeque<&S> = self.current_state.iter().collect();
+        while let Some(state) = frontier.pop_front() {
+            for (_, to) in edges.iter().filter(|(from, _)| from == state) {
+                if reached.insert(to.clone()) {
+                    frontier.push_back(to);
+                }
+            }
+        }
+        reached
+    }
+
+    /// Report which registered states have been entered and which
+    /// transitions have been taken
+    ///
+    /// Silent transitions and restored snapshots do not run `on_enter`, so
+    /// they do not count their target as entered.
+    pub fn coverage(&self) -> Coverage<S> {
+        let unentered_states = self
+            .states
+            .keys()
+            .filter(|state| !self.entered_states.contains(*state))
+            .cloned()
+            .collect();
+        Coverage {
+            entered_states: self.entered_states.clone(),
+            unentered_states,
+            taken_edges: self.transition_log.clone(),
+        }
+    }
+
+    /// Get the states ever transitioned to from `state`, in unspecified order
+    pub fn outgoing(&self, state: &S) -> Vec<S> {
+        self.transition_log
+            .iter()
+            .filter(|(from, _)| from == state)
+            .map(|(_, to)| to.clone())
+            .collect()
+    }
+
+    /// Get the states ever transitioned from into `state`, in unspecified order
+    pub fn incoming(&self, state: &S) -> Vec<S> {
+        self.transition_log
+            .iter()
+            .filter(|(_, to)| to == state)
+            .map(|(from, _)| from.clone())
+            .collect()
+    }
+
+    /// Render the transition log as a PlantUML state diagram
+    pub fn to_plantuml(&self) -> String {
+        generate_plantuml(
+            &self.transition_log,
+            self.current_state.as_ref(),
+            &|state| self.superstate(state),
+        )
+    }
+
+    /// Render the transition log as a Mermaid state diagram
+    pub fn to_mermaid(&self) -> String {
+        generate_mermaid(
+            &self.transition_log,
+            self.initial_state.as_ref(),
+            self.current_state.as_ref(),
+            &|state| self.superstate(state),
+        )
+    }
+
+    /// Get the declared state graph: every registered state, and a
+    /// `(state, superstate)` edge for each registered state that has one
+    ///
+    /// Unlike the [`transition_log`](Self::transition_log) this does not
+    /// depend on which events have been processed. Both lists are in
+    /// unspecified order.
+    pub fn static_graph(&self) -> (Vec<S>, Vec<(S, S)>) {
+        let states = self.registered_states();
+        let edges = states
+            .iter()
+            .filter_map(|state| self.superstate(state).map(|parent| (state.clone(), parent)))
+            .collect();
+        (states, edges)
+    }
+
+    /// Describe the declared state graph together with the live status
+    ///
+    /// Combines [`static_graph`](Self::static_graph), [`ancestors`](Self::ancestors),
+    /// [`time_in_state`](Self::time_in_state) and the
+    /// [`transition_log`](Self::transition_log) into one serializable value.
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub fn describe(&self) -> FsmDescription<S> {
+        let (states, superstate_edges) = self.static_graph();
+        FsmDescription {
+            states,
+            superstate_edges,
+            current_state: self.current_state.clone(),
+            active_states: self.ancestors(),
+            time_in_state: self.time_in_state(),
+            transition_log: self.transition_log.iter().cloned().collect(),
+        }
+    }
+
+    /// Render the [`static_graph`](Self::static_graph) as a PlantUML state diagram
+    pub fn to_plantuml_static(&self) -> String {
+        generate_plantuml_static(self.states.keys(), self.current_state.as_ref(), &|state| {
+            self.superstate(state)
+        })
+    }
+
+    /// Render the [`static_graph`](Self::static_graph) as a Mermaid state diagram
+    pub fn to_mermaid_static(&self) -> String {
+        generate_mermaid_static(
+            self.states.keys(),
+            self.initial_state.as_ref(),
+            self.current_state.as_ref(),
+            &|state| self.superstate(state),
+        )
+    }
+
+    /// Render the registered states and transition log as a Graphviz digraph
+    pub fn to_dot(&self) -> String {
+        generate_dot(
+            self.states.keys(),
+            &self.transition_log,
+            self.current_state.as_ref(),
+            &|state| self.superstate(state),
+        )
+    }
+
+    /// Get a reference to the context
+    pub fn context(&self) -> &CTX {
+        &self.context
+    }
+
+    /// Get a mutable reference to the context
+    pub fn context_mut(&mut self) -> &mut CTX {
+        &mut self.context
+    }
+
+    /// Replace the context, returning the old one
+    ///
+    /// The current state is unchanged and no lifecycle hooks run.
+    pub fn replace_context(&mut self, context: CTX) -> CTX {
+        std::mem::replace(&mut self.context, context)
+    }
+
+    /// Run a closure with mutable access to the context, returning its result
+    pub fn with_context_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut CTX) -> R,
+    {
+        f(&mut self.context)
+    }
+}
+
+/// Wait before retrying `on_enter`, using whichever runtime is enabled
+async fn backoff_sleep(duration: Duration) {
+    #[cfg(feature = "tokio-integration")]
+    tokio::time::sleep(duration).await;
+    #[cfg(all(feature = "async-std-integration", not(feature = "tokio-integration")))]
+    async_std::task::sleep(duration).await;
+    #[cfg(not(any(feature = "tokio-integration", feature = "async-std-integration")))]
+    let _ = duration;
+}
+
+impl<S, CTX, E> StateMachine<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Clone + Send + 'static,
+{
+    /// Preview the state an event would transition to, without committing it
+    ///
+    /// The event is checked and routed as by [`process_event`](Self::process_event):
+    /// it fails in a terminal state, after a cancelled transition, or when
+    /// middleware rejects it, and the global handler is asked before the
+    /// states. It is handled against a clone of the context, so neither the
+    /// context nor the current state change, and no `on_exit`/`on_enter`
+    /// hooks, listeners, or observers run.
+    ///
+    /// The machine is not left entirely untouched, though: middleware, the
+    /// global and unhandled-event handlers, and the state handlers are the
+    /// machine's own instances and are called as usual, so any data they keep
+    /// themselves (a counter in a handler, say) is updated as if the event
+    /// had been processed.
+    ///
+    /// # Returns
+    /// `Some(target)` if the event would cause a transition, `None` if it
+    /// would be handled in place.
+    pub async fn dry_run(&mut self, event: &E) -> Result<Option<S>, FsmError<S>> {
+        if let Some(current) = &self.current_state
+            && self.terminal_states.contains(current)
+        {
+            return Err(FsmError::Terminal(current.clone()));
+        }
+        if let Some(pending) = &self.pending_transition {
+            return Err(FsmError::InconsistentState(pending.clone()));
+        }
+        let active = self
+            .current_state
+            .clone()
+            .ok_or(FsmError::StateMachineNotInitialized)?;
+        let mut context = self.context.clone();
+        for middleware in &mut self.middleware {
+            if let MiddlewareDecision::Reject(reason) = middleware.before(event, &mut context).await
+            {
+                return Err(FsmError::Rejected(reason));
+            }
+        }
+        let mut current_state = active.clone();
+        let mut depth = 0;
+        let mut fallback_response = self
+            .global_handler
+            .as_mut()
+            .and_then(|global| global(event, &mut context))
+            .filter(|response| !matches!(response, Response::Super));
+        let mut fell_back = false;
+
+        loop {
+            let response = if let Some(response) = fallback_response.take() {
+                response
+            } else {
+                let Some(handler) = self.states.get_mut(&current_state) else {
+                    return Err(FsmError::StateNotRegistered(current_state));
+                };
+                handler
+                    .on_event_in(&current_state, event, &mut context)
+                    .await
+            }
+            // Nothing is emitted by a dry run
+            .split_outputs(&mut Vec::new());
+
+            match response {
+                Response::Handled | Response::HandledInternal => return Ok(None),
+                Response::Transition(target)
+                | Response::TransitionWith(target, _)
+                | Response::SilentTransition(target)
+                | Response::Push(target) => return Ok(Some(target)),
+                // Pick with a copy of the generator, so the prediction holds
+                // for the next real event
+                Response::Choice(choices) => {
+                    let mut rng_state = self.rng_state;
+                    return pick(&mut rng_state, choices).map(Some).ok_or_else(|| {
+                        FsmError::InvalidEvent(current_state, EMPTY_CHOICE.to_string())
+                    });
+                }
+                Response::Pop => {
+                    return self
+                        .state_stack
+                        .last()
+                        .cloned()
+                        .map(Some)
+                        .ok_or(FsmError::EmptyStateStack);
+                }
+                Response::Reenter => return Ok(Some(active)),
+                Response::Super => {
+                    let parent = match &self.async_superstate_fn {
+                        Some(resolve) => resolve(&current_state, &context).await,
+                        None => self.superstate(&current_state),
+                    };
+                    let Some(super_s) = parent else {
+                        if !fell_back && let Some(fallback) = &mut self.unhandled_handler {
+                            fell_back = true;
+                            fallback_response = Some(fallback(&active, event, &mut context));
+                            continue;
+                        }
+                        return Err(FsmError::InvalidEvent(
+                            current_state,
+                            "Unhandled event, no superstate available".to_string(),
+                        ));
+                    };
+                    depth += 1;
+                    if depth > self.states.len() {
+                        return Err(FsmError::SuperstateDepthExceeded(current_state));
+                    }
+                    current_state = super_s;
+                }
+                Response::Error(e) => return Err(FsmError::InvalidEvent(current_state, e)),
+                Response::Fail(e) => {
+                    return Err(FsmError::Handler(current_state, ErrorPhase::Event, e));
+                }
+                Response::Output(..) => unreachable!("outputs are split off event responses"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::StateMachineBuilder;
+    use crate::{SharedStateful, StateMachineTemplate};
+    use std::sync::{Arc, Mutex};
+    use tokio::time::Duration;
+
+    // Test state enum
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum TestState {
+        Root,
+        Menu,
+        Settings,
+        Display,
+        Volume,
+    }
+
+    // Test event enum
+    #[derive(Debug, Clone)]
+    enum TestEvent {
+        Enter,
+        Back,
+        Up,
+        Down,
+        Select,
+        Timeout,
+    }
+
+    // Test context
+    #[derive(Debug, Clone)]
+    struct TestContext {
+        pub value: i32,
+        pub transitions: Vec<String>,
+        pub entries: Vec<String>,
+        pub exits: Vec<String>,
+    }
+
+    impl TestContext {
+        fn new() -> Self {
+            Self {
+                value: 0,
+                transitions: Vec::new(),
+                entries: Vec::new(),
+                exits: Vec::new(),
+            }
+        }
+    }
+
+    // Root state implementation
+    struct RootState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for RootState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Root".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Enter => {
+                    context.transitions.push("Root->Menu".to_string());
+                    Response::Transition(TestState::Menu)
+                }
+                _ => Response::Error("Root: Unhandled event".to_string()),
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Root".to_string());
+        }
+
+        async fn get_timeout(&self, _context: &TestContext) -> Option<Duration> {
+            Some(Duration::from_secs(30))
+        }
+    }
+
+    // Menu state implementation
+    struct MenuState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for MenuState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Menu".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Back => {
+                    context.transitions.push("Menu->Root".to_string());
+                    Response::Transition(TestState::Root)
+                }
+                TestEvent::Select => {
+                    context.transitions.push("Menu->Settings".to_string());
+                    Response::Transition(TestState::Settings)
+                }
+                TestEvent::Up | TestEvent::Down => {
+                    context.value += if matches!(event, TestEvent::Up) {
+                        1
+                    } else {
+                        -1
+                    };
+                    Response::Handled
+                }
+                _ => Response::Super, // Delegate to superstate
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Menu".to_string());
+        }
+
+        async fn get_timeout(&self, context: &TestContext) -> Option<Duration> {
+            if context.value > 5 {
+                Some(Duration::from_secs(5)) // Short timeout when value is high
+            } else {
+                Some(Duration::from_secs(10))
+            }
+        }
+    }
+
+    // Settings state implementation
+    struct SettingsState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for SettingsState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Settings".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Select => Response::Transition(TestState::Display), // This should trigger the transition
+                TestEvent::Back => Response::Transition(TestState::Menu),
+                _ => Response::Super, // Only delegate unhandled events
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Settings".to_string());
+        }
+    }
+
+    // Display state implementation
+    struct DisplayState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for DisplayState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Display".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Up => {
+                    context.value += 10;
+                    Response::Handled
+                }
+                TestEvent::Down => {
+                    context.value -= 10;
+                    Response::Handled
+                }
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Display".to_string());
+        }
+
+        async fn get_timeout(&self, _context: &TestContext) -> Option<Duration> {
+            None // No timeout for display state
+        }
+    }
+
+    // State that transitions on enter
+    struct TransitionOnEnterState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for TransitionOnEnterState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Volume".to_string());
+            Response::Transition(TestState::Root) // Immediately transition to Root
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Handled
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Volume".to_string());
+        }
+    }
+
+    // function to chose superstate
+    fn superstate_fn(state: &TestState) -> Option<TestState> {
+        match state {
+            TestState::Menu | TestState::Settings => Some(TestState::Root),
+            TestState::Display => Some(TestState::Settings),
+            _ => None,
+        }
+    }
+
+    fn create_test_fsm() -> StateMachine<TestState, TestContext, TestEvent> {
+        let context = TestContext::new();
+
+        StateMachineBuilder::new(context)
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .state(TestState::Volume, TransitionOnEnterState)
+            .superstate_fn(superstate_fn)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_template_instances_are_independent() {
+        let template = StateMachineTemplate::new(TestContext::new)
+            .state(TestState::Root, || RootState)
+            .state(TestState::Menu, || MenuState)
+            .superstate_fn(superstate_fn);
+
+        let mut first = template.instantiate();
+        let mut second = template.instantiate();
+        first.init(TestState::Root).await.unwrap();
+        second.init(TestState::Root).await.unwrap();
+
+        first.process_event(&TestEvent::Enter).await.unwrap();
+        first.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(first.current_state(), Some(TestState::Menu));
+        assert_eq!(first.context().value, 1);
+
+        assert_eq!(second.current_state(), Some(TestState::Root));
+        assert_eq!(second.context().value, 0);
+        assert_eq!(second.context().entries, vec!["Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_initialization() {
+        let mut fsm = create_test_fsm();
+
+        // Test initial state
+        assert_eq!(fsm.current_state(), None);
+
+        // Initialize the FSM
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Check current state
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+
+        // Check that on_enter was called
+        assert_eq!(fsm.context().entries, vec!["Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_basic_transitions() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Transition from Root to Menu
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+
+        // Check transition tracking
+        assert_eq!(fsm.context().transitions, vec!["Root->Menu"]);
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu"]);
+        // Root is Menu's superstate, so it stays active
+        assert!(fsm.context().exits.is_empty());
+
+        // Transition back to Root
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().transitions, vec!["Root->Menu", "Menu->Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_event_handling() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Test handled events
+        assert_eq!(fsm.context().value, 0);
+
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+        assert_eq!(fsm.current_state(), Some(TestState::Menu)); // Should stay in same state
+
+        fsm.process_event(&TestEvent::Down).await.unwrap();
+        assert_eq!(fsm.context().value, 0);
+    }
+
+    #[tokio::test]
+    async fn test_superstate_delegation() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Send an event that Menu doesn't handle (should delegate to Root)
+        let result = fsm.process_event(&TestEvent::Timeout).await;
+
+        // Should get an error because Root doesn't handle Timeout either
+        assert!(result.is_err());
+        if let Err(FsmError::InvalidEvent(state, msg)) = result {
+            assert_eq!(state, TestState::Root);
+            assert!(msg.contains("Root: Unhandled event"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deep_hierarchy() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Display).await.unwrap();
+
+        // Display handles Up/Down
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 10);
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+
+        // Display doesn't handle Enter, should delegate through Settings to Root
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu)); // Root handles Enter -> Menu
+    }
+
+    #[tokio::test]
+    async fn test_superstate_depth_exceeded() {
+        // Every state is its own superstate, so Super would loop forever
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(|state| Some(state.clone()))
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            fsm.process_event(&TestEvent::Timeout),
+        )
+        .await
+        .expect("process_event should not hang");
+
+        assert!(matches!(
+            result,
+            Err(FsmError::SuperstateDepthExceeded(TestState::Menu))
+        ));
+
+        // The counter resets, so the next event is handled normally
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+    }
+
+    // State that hands a payload to the next state
+    struct PayloadSenderState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for PayloadSenderState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Select => Response::TransitionWith(TestState::Display, Box::new(42u32)),
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
+    }
+
+    // State that reads the payload it was entered with
+    struct PayloadReceiverState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for PayloadReceiverState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Display".to_string());
+            Response::Handled
+        }
+
+        async fn on_enter_with(
+            &mut self,
+            context: &mut TestContext,
+            payload: Box<dyn Any + Send>,
+        ) -> Response<TestState> {
+            match payload.downcast::<u32>() {
+                Ok(value) => {
+                    context.value = *value as i32;
+                    self.on_enter(context).await
+                }
+                Err(_) => Response::Error("Unexpected payload".to_string()),
+            }
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Super
+        }
+
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
+    }
+
+    #[tokio::test]
+    async fn test_transition_with_payload() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Settings, PayloadSenderState)
+            .state(TestState::Display, PayloadReceiverState)
+            .build();
+        fsm.init(TestState::Settings).await.unwrap();
+
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.context().value, 42);
+        assert_eq!(fsm.context().entries, vec!["Display"]);
+    }
+
+    // State that re-enters itself on Enter
+    struct ReenterState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for ReenterState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Menu".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Enter => Response::Reenter,
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Menu".to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reenter() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, ReenterState)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        assert_eq!(fsm.context().entries.len(), 1);
+        assert_eq!(fsm.context().exits.len(), 0);
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().entries.len(), 2);
+        assert_eq!(fsm.context().exits.len(), 1);
+    }
+
+    // State that handles events without running lifecycle hooks
+    struct InternalState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for InternalState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Internal".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Up => {
+                    context.value += 1;
+                    Response::HandledInternal
+                }
+                TestEvent::Select => Response::SilentTransition(TestState::Display),
+                TestEvent::Back => Response::SilentTransition(TestState::Menu),
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push("Internal".to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_internal_and_silent_transitions() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, InternalState)
+            .state(TestState::Display, InternalState)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.context_mut().entries.clear();
+
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert!(fsm.last_handled_internally());
+        assert!(!fsm.last_transitioned());
+
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert!(!fsm.last_handled_internally());
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+
+        // No lifecycle hooks ran
+        assert!(fsm.context().entries.is_empty());
+        assert!(fsm.context().exits.is_empty());
+        assert!(
+            fsm.transition_log()
+                .contains(&(TestState::Menu, TestState::Display))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hierarchical_exit_enter_order() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Display).await.unwrap();
+
+        // Entering a nested state enters its superstates first
+        assert_eq!(fsm.context().entries, vec!["Root", "Settings", "Display"]);
+
+        // Display -> Menu: exit up to the common ancestor Root, then enter Menu
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().exits, vec!["Display", "Settings"]);
+        assert_eq!(
+            fsm.context().entries,
+            vec!["Root", "Settings", "Display", "Menu"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_in() {
+        let mut fsm = create_test_fsm();
+        assert!(!fsm.is_in(&TestState::Root));
+
+        fsm.init(TestState::Display).await.unwrap();
+
+        // Leaf state
+        assert!(fsm.is_in(&TestState::Display));
+        // Direct parent and grandparent
+        assert!(fsm.is_in(&TestState::Settings));
+        assert!(fsm.is_in(&TestState::Root));
+        // Unrelated states
+        assert!(!fsm.is_in(&TestState::Menu));
+        assert!(!fsm.is_in(&TestState::Volume));
+    }
+
+    #[tokio::test]
+    async fn test_superstate() {
+        let fsm = create_test_fsm();
+        assert_eq!(
+            fsm.superstate(&TestState::Display),
+            Some(TestState::Settings)
+        );
+        assert_eq!(fsm.superstate(&TestState::Menu), Some(TestState::Root));
+        assert_eq!(fsm.superstate(&TestState::Root), None);
+    }
+
+    #[tokio::test]
+    async fn test_ancestors() {
+        let mut fsm = create_test_fsm();
+        assert!(fsm.ancestors().is_empty());
+
+        fsm.init(TestState::Display).await.unwrap();
+        assert_eq!(
+            fsm.ancestors(),
+            vec![TestState::Display, TestState::Settings, TestState::Root]
+        );
+        assert_eq!(
+            fsm.ancestors_of(&TestState::Menu),
+            vec![TestState::Menu, TestState::Root]
+        );
+        assert_eq!(
+            fsm.ancestors_of(&TestState::Volume),
+            vec![TestState::Volume]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registered_states() {
+        let fsm = create_test_fsm();
+
+        let registered = fsm.registered_states();
+        assert_eq!(registered.len(), 5);
+        for state in [
+            TestState::Root,
+            TestState::Menu,
+            TestState::Settings,
+            TestState::Display,
+            TestState::Volume,
+        ] {
+            assert!(registered.contains(&state));
+            assert!(fsm.is_registered(&state));
+        }
+
+        let minimal: StateMachine<TestState, TestContext, TestEvent> =
+            StateMachineBuilder::new(TestContext::new())
+                .state(TestState::Root, RootState)
+                .build();
+        assert!(!minimal.is_registered(&TestState::Menu));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+        let entries_before = fsm.context().entries.clone();
+
+        // Transition is reported but not taken
+        let target = fsm.dry_run(&TestEvent::Select).await.unwrap();
+        assert_eq!(target, Some(TestState::Settings));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert!(fsm.context().transitions.is_empty());
+        assert_eq!(fsm.context().entries, entries_before);
+
+        // Handled events report no target and leave the context alone
+        assert_eq!(fsm.dry_run(&TestEvent::Up).await.unwrap(), None);
+        assert_eq!(fsm.context().value, 0);
+
+        // Delegated events are resolved through the superstate
+        assert_eq!(
+            fsm.dry_run(&TestEvent::Enter).await.unwrap(),
+            Some(TestState::Menu)
+        );
+        assert!(fsm.dry_run(&TestEvent::Timeout).await.is_err());
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_matches_process_event_checks() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .superstate_fn(superstate_fn)
+            .global_handler(|event, _ctx| match event {
+                TestEvent::Back => Some(Response::Transition(TestState::Display)),
+                _ => None,
+            })
+            .terminal(TestState::Display)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // The global handler answers before the Menu state would
+        assert_eq!(
+            fsm.dry_run(&TestEvent::Back).await.unwrap(),
+            Some(TestState::Display)
+        );
+        assert_eq!(
+            fsm.dry_run(&TestEvent::Select).await.unwrap(),
+            Some(TestState::Settings)
+        );
+
+        // No event reaches a handler in a terminal state
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert!(matches!(
+            fsm.dry_run(&TestEvent::Select).await,
+            Err(FsmError::Terminal(TestState::Display))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_transition_listener() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_builder = Arc::clone(&seen);
+        let seen_by_machine = Arc::clone(&seen);
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .superstate_fn(superstate_fn)
+            .on_transition(
+                move |from: &TestState, to: &TestState, _ctx: &TestContext| {
+                    seen_by_builder
+                        .lock()
+                        .unwrap()
+                        .push(format!("{from:?}->{to:?}"));
+                },
+            )
+            .build();
+        fsm.on_transition(move |_from, to, ctx| {
+            seen_by_machine
+                .lock()
+                .unwrap()
+                .push(format!("entries={} at {to:?}", ctx.entries.len()));
+        });
+
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                "Root->Menu",
+                "entries=2 at Menu",
+                "Menu->Settings",
+                "entries=3 at Settings",
+                "Settings->Display",
+                "entries=4 at Display",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_declared_transitions() {
+        let mut fsm =
+            StateMachineBuilder::<TestState, TestContext, TestEvent>::new(TestContext::new())
+                .transition(
+                    TestState::Root,
+                    |event| matches!(event, TestEvent::Enter),
+                    TestState::Menu,
+                )
+                .transition(
+                    TestState::Menu,
+                    |event| matches!(event, TestEvent::Select),
+                    TestState::Settings,
+                )
+                .transition(
+                    TestState::Settings,
+                    |event| matches!(event, TestEvent::Back),
+                    TestState::Root,
+                )
+                .build();
+        fsm.init(TestState::Root).await.unwrap();
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+
+        let result = fsm.process_event(&TestEvent::Back).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::InvalidEvent(TestState::Root, _))
+        ));
+
+        // Declared transitions take precedence over a registered handler
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(superstate_fn)
+            .transition(
+                TestState::Menu,
+                |event| matches!(event, TestEvent::Up),
+                TestState::Root,
+            )
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Down).await.unwrap();
+        assert_eq!(fsm.context().value, -1);
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu", "Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_enter_and_exit_actions() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(superstate_fn)
+            .on_enter_action(TestState::Menu, |ctx: &mut TestContext| {
+                ctx.entries.push("action 1".to_string())
+            })
+            .on_enter_action(TestState::Menu, |ctx: &mut TestContext| {
+                ctx.entries.push("action 2".to_string())
+            })
+            .on_exit_action(TestState::Menu, |ctx: &mut TestContext| {
+                ctx.exits.push("action".to_string())
+            })
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        assert_eq!(
+            fsm.context().entries,
+            vec!["Root", "Menu", "action 1", "action 2"]
+        );
+
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root
+        assert_eq!(fsm.context().exits, vec!["action", "Menu", "Root"]);
+
+        // Actions only run for states whose on_enter succeeded
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, ErrorState)
+            .on_enter_action(TestState::Menu, |ctx: &mut TestContext| {
+                ctx.entries.push("action".to_string())
+            })
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+        assert!(fsm.process_event(&TestEvent::Enter).await.is_err());
+        assert_eq!(fsm.context().entries, vec!["Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_event_observer() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_observer = Arc::clone(&seen);
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(|state| match state {
+                TestState::Settings => Some(TestState::Menu),
+                _ => None,
+            })
+            .on_event_observed(move |state, event, kind| {
+                seen_by_observer
+                    .lock()
+                    .unwrap()
+                    .push((state.clone(), format!("{event:?}"), *kind));
+            })
+            .build();
+        fsm.init(TestState::Settings).await.unwrap();
+
+        // Settings bubbles Up to Menu, which handles it
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (TestState::Settings, "Up".to_string(), ResponseKind::Super),
+                (TestState::Menu, "Up".to_string(), ResponseKind::Handled),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_observer_sees_global_and_fallback_responses() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_by_observer = Arc::clone(&seen);
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(|state| match state {
+                TestState::Settings => Some(TestState::Menu),
+                _ => None,
+            })
+            .global_handler(|event, _ctx| match event {
+                TestEvent::Down => Some(Response::Handled),
+                _ => None,
+            })
+            .on_unhandled(|_state, _event, _ctx| Response::HandledInternal)
+            .on_event_observed(move |state, event, kind| {
+                seen_by_observer
+                    .lock()
+                    .unwrap()
+                    .push((state.clone(), format!("{event:?}"), *kind));
+            })
+            .build();
+        fsm.init(TestState::Settings).await.unwrap();
+
+        // The global handler answers before any state is asked
+        fsm.process_event(&TestEvent::Down).await.unwrap();
+        // The fallback answers for Settings once Menu has no superstate
+        fsm.process_event(&TestEvent::Timeout).await.unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (
+                    TestState::Settings,
+                    "Down".to_string(),
+                    ResponseKind::Handled
+                ),
+                (
+                    TestState::Settings,
+                    "Timeout".to_string(),
+                    ResponseKind::Super
+                ),
+                (TestState::Menu, "Timeout".to_string(), ResponseKind::Super),
+                (
+                    TestState::Settings,
+                    "Timeout".to_string(),
+                    ResponseKind::HandledInternal
+                ),
+            ]
+        );
+    }
+
+    // Middleware that counts events and rejects Timeout
+    struct RejectTimeout;
+
+    #[async_trait]
+    impl EventMiddleware<TestContext, TestEvent> for RejectTimeout {
+        async fn before(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> MiddlewareDecision {
+            context.transitions.push(format!("seen {event:?}"));
+            match event {
+                TestEvent::Timeout => MiddlewareDecision::Reject("timeouts disabled".to_string()),
+                _ => MiddlewareDecision::Proceed,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_rejects_event() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .middleware(RejectTimeout)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+
+        let result = fsm.process_event(&TestEvent::Timeout).await;
+        assert!(matches!(result, Err(FsmError::Rejected(reason)) if reason == "timeouts disabled"));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().transitions, vec!["seen Up", "seen Timeout"]);
+    }
+
+    #[test]
+    fn test_timeout_error_variant() {
+        let err: FsmError<TestState> = FsmError::Timeout;
+        assert!(matches!(err, FsmError::Timeout));
+        assert_eq!(err.to_string(), "State machine operation timed out");
+    }
+
+    // Context carrying a handle to the machine's event queue
+    struct QueueContext {
+        queue: EventQueue<TestEvent>,
+        entries: Vec<String>,
+    }
+
+    // State that posts a follow-up event when entered
+    struct PostingState {
+        name: &'static str,
+        post: Option<TestEvent>,
+        next: Option<TestState>,
+    }
+
+    #[async_trait]
+    impl Stateful<TestState, QueueContext, TestEvent> for PostingState {
+        async fn on_enter(&mut self, context: &mut QueueContext) -> Response<TestState> {
+            context.entries.push(self.name.to_string());
+            if let Some(event) = &self.post {
+                context.queue.push(event.clone());
+            }
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut QueueContext,
+        ) -> Response<TestState> {
+            match &self.next {
+                Some(next) => Response::Transition(next.clone()),
+                None => Response::Handled,
+            }
+        }
+
+        async fn on_exit(&mut self, _context: &mut QueueContext) {}
+    }
+
+    #[tokio::test]
+    async fn test_event_queue() {
+        let queue = EventQueue::new();
+        let mut fsm = StateMachineBuilder::new(QueueContext {
+            queue: queue.clone(),
+            entries: Vec::new(),
+        })
+        .event_queue(queue)
+        .state(
+            TestState::Menu,
+            PostingState {
+                name: "Menu",
+                post: None,
+                next: Some(TestState::Settings),
+            },
+        )
+        .state(
+            TestState::Settings,
+            PostingState {
+                name: "Settings",
+                post: Some(TestEvent::Select),
+                next: Some(TestState::Display),
+            },
+        )
+        .state(
+            TestState::Display,
+            PostingState {
+                name: "Display",
+                post: None,
+                next: None,
+            },
+        )
+        .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Menu -> Settings, whose on_enter posts Select: Settings -> Display
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.context().entries, vec!["Menu", "Settings", "Display"]);
+        assert!(fsm.event_queue().is_empty());
+    }
+
+    // State that posts Down while failing to handle Up
+    struct FailingPosterState;
+
+    #[async_trait]
+    impl Stateful<TestState, QueueContext, TestEvent> for FailingPosterState {
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut QueueContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Up => {
+                    context.queue.push(TestEvent::Down);
+                    Response::Error("rejected".to_string())
+                }
+                _ => {
+                    context.entries.push(format!("{event:?}"));
+                    Response::Handled
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_queue_discarded_on_failure() {
+        let queue = EventQueue::new();
+        let mut fsm = StateMachineBuilder::new(QueueContext {
+            queue: queue.clone(),
+            entries: Vec::new(),
+        })
+        .event_queue(queue.clone())
+        .state(TestState::Root, FailingPosterState)
+        .build();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Queued before the failing event, so it is kept
+        queue.push(TestEvent::Back);
+        assert!(fsm.process_event(&TestEvent::Up).await.is_err());
+        assert_eq!(queue.len(), 1);
+
+        // Only Back is replayed, not the Down posted by the failed Up
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.context().entries, vec!["Select", "Back"]);
+    }
+
+    impl crate::EventPriority for TestEvent {
+        fn priority(&self) -> u8 {
+            match self {
+                TestEvent::Back => 10,
+                _ => 0,
+            }
+        }
+    }
+
+    // State that records every event it receives
+    struct RecordingState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for RecordingState {
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            context.transitions.push(format!("{event:?}"));
+            Response::Handled
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_queue_priority() {
+        let queue = EventQueue::new();
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .event_queue(queue.clone())
+            .state(TestState::Root, RecordingState)
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Routine Up is posted first, but the high priority Back runs first
+        queue.push_prioritized(TestEvent::Up);
+        queue.push_prioritized(TestEvent::Back);
+        queue.push_prioritized(TestEvent::Down);
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+
+        assert_eq!(
+            fsm.context().transitions,
+            vec!["Select", "Back", "Up", "Down"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_queue_overflow() {
+        // Settings re-posts an event every time it re-enters itself
+        let queue = EventQueue::new();
+        let mut fsm = StateMachineBuilder::new(QueueContext {
+            queue: queue.clone(),
+            entries: Vec::new(),
+        })
+        .event_queue(queue)
+        .max_queued_events(5)
+        .state(
+            TestState::Settings,
+            PostingState {
+                name: "Settings",
+                post: Some(TestEvent::Select),
+                next: Some(TestState::Settings),
+            },
+        )
+        .build();
+
+        let result = fsm.init(TestState::Settings).await;
+        assert!(matches!(result, Err(FsmError::EventQueueOverflow(5))));
+        assert!(fsm.event_queue().is_empty());
+    }
+
+    // State that navigates to fixed targets on Up/Down, recording entries
+    struct GotoState {
+        name: &'static str,
+        up: Option<TestState>,
+        down: Option<TestState>,
+    }
+
+    impl GotoState {
+        fn new(name: &'static str, up: Option<TestState>, down: Option<TestState>) -> Self {
+            Self { name, up, down }
+        }
+    }
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for GotoState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push(self.name.to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            match (event, &self.up, &self.down) {
+                (TestEvent::Up, Some(up), _) => Response::Transition(up.clone()),
+                (TestEvent::Down, _, Some(down)) => Response::Transition(down.clone()),
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push(self.name.to_string());
+        }
+    }
+
+    // Root > Settings > Display > Volume, with Menu under Root
+    fn create_deep_fsm() -> StateMachine<TestState, TestContext, TestEvent> {
+        use TestState::*;
+
+        StateMachineBuilder::new(TestContext::new())
+            .state(Root, GotoState::new("Root", None, Some(Settings)))
+            .state(Menu, GotoState::new("Menu", None, None))
+            .state(Settings, GotoState::new("Settings", None, Some(Display)))
+            .state(Display, GotoState::new("Display", None, Some(Volume)))
+            .state(Volume, GotoState::new("Volume", Some(Root), None))
+            .superstate_fn(|state| match state {
+                Menu | Settings => Some(Root),
+                Display => Some(Settings),
+                Volume => Some(Display),
+                Root => None,
+            })
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_deep_history() {
+        let mut fsm = create_deep_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // No history yet, so the default is used
+        fsm.restore_deep_history(TestState::Settings, TestState::Settings)
+            .await
+            .unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+
+        // Descend two levels under Settings, then leave for Root
+        fsm.process_event(&TestEvent::Down).await.unwrap(); // Settings -> Display
+        fsm.process_event(&TestEvent::Down).await.unwrap(); // Display -> Volume
+        fsm.process_event(&TestEvent::Up).await.unwrap(); // Volume -> Root
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(
+            fsm.deep_history(&TestState::Settings),
+            Some(&TestState::Volume)
+        );
+
+        fsm.context_mut().entries.clear();
+        fsm.restore_deep_history(TestState::Settings, TestState::Settings)
+            .await
+            .unwrap();
+
+        assert_eq!(fsm.current_state(), Some(TestState::Volume));
+        assert_eq!(fsm.context().entries, vec!["Settings", "Display", "Volume"]);
+    }
+
+    // State that pushes a modal on Select and pops back on Back
+    struct ModalState {
+        name: &'static str,
+        modal: Option<TestState>,
+    }
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for ModalState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push(self.name.to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            match (event, &self.modal) {
+                (TestEvent::Select, Some(modal)) => Response::Push(modal.clone()),
+                (TestEvent::Back, _) => Response::Pop,
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut TestContext) {
+            context.exits.push(self.name.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_pop_state_stack() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Menu,
+                ModalState {
+                    name: "Menu",
+                    modal: Some(TestState::Display),
+                },
+            )
+            .state(
+                TestState::Display,
+                ModalState {
+                    name: "Display",
+                    modal: None,
+                },
+            )
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        assert_eq!(fsm.stack_depth(), 0);
+
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.stack_depth(), 1);
+
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.stack_depth(), 0);
+        assert_eq!(fsm.context().entries, vec!["Menu", "Display", "Menu"]);
+        assert_eq!(fsm.context().exits, vec!["Menu", "Display"]);
+
+        // Nothing left to pop
+        let result = fsm.process_event(&TestEvent::Back).await;
+        assert!(matches!(result, Err(FsmError::EmptyStateStack)));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+    }
+
+    #[tokio::test]
+    async fn test_go_back() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        assert!(matches!(
+            fsm.go_back().await,
+            Err(FsmError::NoPreviousState)
+        ));
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        assert_eq!(fsm.previous_state(), Some(&TestState::Menu));
+
+        fsm.go_back().await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.previous_state(), Some(&TestState::Settings));
+    }
+
+    #[tokio::test]
+    async fn test_previous_state_on_reenter() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, ReenterState)
+            .superstate_fn(superstate_fn)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        assert_eq!(fsm.previous_state(), Some(&TestState::Menu));
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Settings reentered
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+        assert_eq!(fsm.previous_state(), Some(&TestState::Settings));
+    }
+
+    #[tokio::test]
+    async fn test_reset() {
+        let mut fsm = create_deep_fsm();
+        assert!(matches!(fsm.reset().await, Err(FsmError::NoInitialState)));
+
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Down).await.unwrap(); // Root -> Settings
+        fsm.process_event(&TestEvent::Down).await.unwrap(); // Settings -> Display
+        fsm.context_mut().value = 7;
+        fsm.context_mut().entries.clear();
+
+        fsm.reset().await.unwrap();
+
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().exits, vec!["Display", "Settings", "Root"]);
+        assert_eq!(fsm.context().entries, vec!["Root"]);
+        assert_eq!(fsm.context().value, 7);
+    }
+
+    // One handler registered under both Menu and Settings
+    struct SharedMenuState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for SharedMenuState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Error("on_event_in should be called instead".to_string())
+        }
+
+        async fn on_event_in(
+            &mut self,
+            state: &TestState,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            context.transitions.push(format!("{state:?}"));
+            match (state, event) {
+                (TestState::Menu, TestEvent::Select) => Response::Transition(TestState::Settings),
+                (TestState::Settings, TestEvent::Back) => Response::Transition(TestState::Menu),
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
+    }
+
+    #[tokio::test]
+    async fn test_on_event_in_receives_state() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, SharedMenuState)
+            .state(TestState::Settings, SharedMenuState)
+            .superstate_fn(superstate_fn)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().transitions, vec!["Menu", "Settings"]);
+
+        // Root only implements on_event, which the default forwards to
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(
+            fsm.context().transitions,
+            vec!["Menu", "Settings", "Menu", "Root->Menu"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_state_alias() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, SharedMenuState)
+            .state_alias(TestState::Menu, TestState::Settings)
+            .superstate_fn(superstate_fn)
+            .build();
+        assert!(fsm.is_registered(&TestState::Settings));
+        assert_eq!(fsm.registered_states().len(), 3);
+        fsm.init(TestState::Menu).await.unwrap();
+
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().transitions, vec!["Menu", "Settings"]);
+
+        let dangling =
+            StateMachineBuilder::<TestState, TestContext, TestEvent>::new(TestContext::new())
+                .state(TestState::Root, RootState)
+                .state_alias(TestState::Menu, TestState::Settings)
+                .build_validated();
+        assert!(matches!(
+            dangling,
+            Err(FsmError::StateNotRegistered(TestState::Menu))
+        ));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum DeviceError {
+        Overheated(u8),
+    }
+
+    impl std::fmt::Display for DeviceError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                DeviceError::Overheated(temp) => write!(f, "overheated at {temp} degrees"),
+            }
+        }
+    }
+
+    impl Error for DeviceError {}
+
+    // State that fails with a typed error on Up
+    struct OverheatingState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for OverheatingState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Up => Response::Fail(Box::new(DeviceError::Overheated(90))),
+                _ => Response::Handled,
+            }
+        }
+
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
+    }
+
+    #[tokio::test]
+    async fn test_typed_handler_error() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Display, OverheatingState)
+            .build();
+        fsm.init(TestState::Display).await.unwrap();
+
+        let Err(FsmError::Handler(state, phase, err)) = fsm.process_event(&TestEvent::Up).await
+        else {
+            panic!("expected a handler error");
+        };
+        assert_eq!(state, TestState::Display);
+        assert_eq!(phase, ErrorPhase::Event);
+        assert_eq!(
+            err.downcast_ref::<DeviceError>(),
+            Some(&DeviceError::Overheated(90))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unhandled_event_fallback() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .superstate_map(HashMap::from([(TestState::Display, TestState::Settings)]))
+            .on_unhandled(|state, event, context: &mut TestContext| match event {
+                TestEvent::Timeout => {
+                    context.transitions.push(format!("ignored in {state:?}"));
+                    Response::Handled
+                }
+                _ => Response::Super,
+            })
+            .build();
+        fsm.init(TestState::Display).await.unwrap();
+
+        // Display and Settings both delegate Timeout, and Settings has no superstate
+        fsm.process_event(&TestEvent::Timeout).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.context().transitions, vec!["ignored in Display"]);
+
+        // Events the fallback declines still fail as before
+        let result = fsm.process_event(&TestEvent::Enter).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::InvalidEvent(TestState::Settings, _))
+        ));
+    }
+
+    // State that may only be entered while the context value is positive
+    struct PoweredState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for PoweredState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Display".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Handled
+        }
+
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
+
+        async fn can_enter(&self, context: &TestContext) -> bool {
+            context.value > 0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_can_enter_guard() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, PoweredState)
+            .build();
+        fsm.init(TestState::Settings).await.unwrap();
+
+        // Power level is zero, so Display refuses to activate
+        let result = fsm.process_event(&TestEvent::Select).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::GuardRejected(TestState::Display))
+        ));
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+        assert!(fsm.context().exits.is_empty());
+        assert_eq!(fsm.context().entries, vec!["Settings"]);
+
+        fsm.context_mut().value = 1;
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.context().exits, vec!["Settings"]);
+    }
+
+    #[tokio::test]
+    async fn test_edge_guard() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(superstate_fn)
+            .guard(
+                TestState::Menu,
+                TestState::Settings,
+                |context: &TestContext| context.value < 3,
+            )
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.context_mut().value = 3;
+
+        let result = fsm.process_event(&TestEvent::Select).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::EdgeGuardRejected(
+                TestState::Menu,
+                TestState::Settings
+            ))
+        ));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert!(fsm.context().exits.is_empty());
+
+        // Unguarded edges are unaffected
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+
+        fsm.context_mut().value = 2;
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+    }
+
+    // State relying on the default on_enter and on_exit
+    struct PassthroughState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for PassthroughState {
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Up => {
+                    context.value += 1;
+                    Response::Handled
+                }
+                _ => Response::Super,
+            }
+        }
+    }
+
+    // State relying on every default, so all events bubble up
+    struct EmptyState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for EmptyState {}
+
+    #[tokio::test]
+    async fn test_default_lifecycle_methods() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, PassthroughState)
+            .state(TestState::Settings, EmptyState)
+            .superstate_fn(superstate_fn)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+
+        // Menu delegates Enter to Root, which re-enters Menu
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().entries, vec!["Root"]);
+
+        fsm.init(TestState::Settings).await.unwrap();
+        let result = fsm.process_event(&TestEvent::Timeout).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::InvalidEvent(TestState::Root, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_event_with_output() {
+        use crate::fn_state;
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Root,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, _context| {
+                        Box::pin(async {
+                            Response::Transition(TestState::Settings)
+                                .with_output("open")
+                                .with_output("beep")
+                        })
+                    },
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .state(
+                TestState::Menu,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |event, _context| {
+                        Box::pin(async move {
+                            match event {
+                                TestEvent::Up => Response::Handled.with_output("up"),
+                                _ => Response::Super.with_output("menu"),
+                            }
+                        })
+                    },
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(superstate_fn)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        let outputs: Vec<&str> = fsm.process_event_with_output(&TestEvent::Up).await.unwrap();
+        assert_eq!(outputs, vec!["up"]);
+
+        // Delegated outputs accumulate in order alongside the transition
+        let outputs: Vec<&str> = fsm
+            .process_event_with_output(&TestEvent::Select)
+            .await
+            .unwrap();
+        assert_eq!(outputs, vec!["menu", "open", "beep"]);
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+
+        // Outputs of other types are an error, though the event was processed
+        fsm.force_transition(TestState::Menu).await.unwrap();
+        let result = fsm
+            .process_event_with_output::<String>(&TestEvent::Up)
+            .await;
+        assert!(matches!(result, Err(FsmError::OutputType(_))));
+        let outputs: Vec<&str> = fsm.process_event_with_output(&TestEvent::Up).await.unwrap();
+        assert_eq!(outputs, vec!["up"]);
+    }
+
+    #[tokio::test]
+    async fn test_global_handler_output_with_super() {
+        use crate::fn_state;
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Menu,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, _context| Box::pin(async { Response::Handled.with_output("menu") }),
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .global_handler(|_event, _context| Some(Response::Super.with_output("global")))
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Delegating with an output still reaches the current state's handler
+        let outputs: Vec<&str> = fsm.process_event_with_output(&TestEvent::Up).await.unwrap();
+        assert_eq!(outputs, vec!["global", "menu"]);
+    }
+
+    #[tokio::test]
+    async fn test_suspend_and_resume() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        fsm.suspend();
+        assert!(fsm.is_suspended());
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().value, 0);
+
+        fsm.resume().await.unwrap();
+        assert!(!fsm.is_suspended());
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+        assert_eq!(fsm.context().value, 1);
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu", "Settings"]);
+
+        // A failing event leaves the rest buffered and the machine suspended
+        fsm.force_transition(TestState::Root).await.unwrap();
+        fsm.suspend();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert!(fsm.resume().await.is_err());
+        assert!(fsm.is_suspended());
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        fsm.resume().await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+    }
+
+    #[tokio::test]
+    async fn test_last_transitioned() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        assert!(!fsm.last_transitioned());
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert!(fsm.last_transitioned());
+
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert!(!fsm.last_transitioned());
+
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert!(fsm.last_transitioned());
+
+        assert!(fsm.process_event(&TestEvent::Up).await.is_err());
+        assert!(!fsm.last_transitioned());
+    }
+
+    #[tokio::test]
+    async fn test_state_boxed() {
+        type Plugin = Box<dyn Stateful<TestState, TestContext, TestEvent> + Send + Sync>;
+
+        // As produced by a plugin loader
+        let plugins: Vec<(TestState, Plugin)> = vec![
+            (TestState::Root, Box::new(RootState)),
+            (TestState::Menu, Box::new(MenuState)),
+        ];
+
+        let mut builder = StateMachineBuilder::new(TestContext::new()).superstate_fn(superstate_fn);
+        for (state, handler) in plugins {
+            builder = builder.state_boxed(state, handler);
+        }
+        let mut fsm = builder.build();
+
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().value, 1);
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu"]);
+    }
+
+    #[tokio::test]
+    async fn test_response_eq() {
+        assert_eq!(
+            Response::Transition(TestState::Menu),
+            Response::Transition(TestState::Menu)
+        );
+        assert_ne!(
+            Response::Transition(TestState::Menu),
+            Response::Push(TestState::Menu)
+        );
+        assert_eq!(
+            Response::<TestState>::Error("bad".to_string()),
+            Response::Error("bad".to_string())
+        );
+        assert_ne!(
+            Response::TransitionWith(TestState::Menu, Box::new(1u8)),
+            Response::TransitionWith(TestState::Menu, Box::new(1u8))
+        );
+
+        // A handler can be tested on its own against a fresh context
+        let mut context = TestContext::new();
+        assert_eq!(
+            MenuState.on_event(&TestEvent::Select, &mut context).await,
+            Response::Transition(TestState::Settings)
+        );
+        assert_eq!(
+            MenuState.on_event(&TestEvent::Up, &mut context).await,
+            Response::Handled
+        );
+        assert_eq!(
+            MenuState.on_event(&TestEvent::Enter, &mut context).await,
+            Response::Super
+        );
+        assert_eq!(context.value, 1);
+    }
+
+    #[tokio::test]
+    async fn test_choice_is_reproducible_with_seed() {
+        use crate::fn_state;
+
+        async fn picks(seed: u64) -> Vec<TestState> {
+            let mut fsm = StateMachineBuilder::new(TestContext::new())
+                .state(
+                    TestState::Root,
+                    fn_state(
+                        |_context| Box::pin(async { Response::Handled }),
+                        |event, _context| {
+                            Box::pin(async move {
+                                match event {
+                                    TestEvent::Enter => Response::Choice(vec![
+                                        TestState::Menu,
+                                        TestState::Settings,
+                                        TestState::Display,
+                                    ]),
+                                    _ => Response::Choice(Vec::new()),
+                                }
+                            })
+                        },
+                        |_context| Box::pin(async {}),
+                    ),
+                )
+                .state(TestState::Menu, MenuState)
+                .state(TestState::Settings, SettingsState)
+                .state(TestState::Display, DisplayState)
+                .rng_seed(seed)
+                .build();
+            fsm.init(TestState::Root).await.unwrap();
+
+            let mut picks = Vec::new();
+            for _ in 0..20 {
+                let predicted = fsm.dry_run(&TestEvent::Enter).await.unwrap();
+                fsm.process_event(&TestEvent::Enter).await.unwrap();
+                assert_eq!(fsm.current_state(), predicted);
+                picks.push(fsm.current_state().unwrap());
+                fsm.force_transition(TestState::Root).await.unwrap();
+            }
+
+            let result = fsm.process_event(&TestEvent::Back).await;
+            assert!(matches!(
+                result,
+                Err(FsmError::InvalidEvent(TestState::Root, _))
+            ));
+            picks
+        }
+
+        let first = picks(42).await;
+        assert_eq!(first, picks(42).await);
+        // The picks are spread over the choices rather than fixed
+        assert!(first.contains(&TestState::Menu));
+        assert!(first.contains(&TestState::Settings));
+        assert!(first.contains(&TestState::Display));
+    }
+
+    #[tokio::test]
+    async fn test_fn_state() {
+        use crate::fn_state;
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Root,
+                fn_state(
+                    |context: &mut TestContext| {
+                        Box::pin(async move {
+                            context.entries.push("Root".to_string());
+                            Response::Handled
+                        })
+                    },
+                    |event, _context| {
+                        Box::pin(async move {
+                            match event {
+                                TestEvent::Enter => Response::Transition(TestState::Menu),
+                                _ => Response::Handled,
+                            }
+                        })
+                    },
+                    |context| Box::pin(async move { context.exits.push("Root".to_string()) }),
+                ),
+            )
+            .state(
+                TestState::Menu,
+                fn_state(
+                    |context: &mut TestContext| {
+                        Box::pin(async move {
+                            context.entries.push("Menu".to_string());
+                            Response::Handled
+                        })
+                    },
+                    |event, context| {
+                        Box::pin(async move {
+                            match event {
+                                TestEvent::Up => {
+                                    context.value += 1;
+                                    Response::Handled
+                                }
+                                _ => Response::Transition(TestState::Root),
+                            }
+                        })
+                    },
+                    |_context| Box::pin(async {}),
+                )
+                .with_timeout(|context| Some(Duration::from_secs(context.value as u64))),
+            )
+            .build();
+
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(
+            fsm.get_current_timeout().await,
+            Some(Duration::from_secs(1))
+        );
+
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu", "Root"]);
+        assert_eq!(fsm.context().exits, vec!["Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_functionality() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Test timeout for Root state
+        let timeout = fsm.get_current_timeout().await;
+        assert_eq!(timeout, Some(Duration::from_secs(30)));
+
+        // Transition to Menu
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+
+        // Test dynamic timeout based on context
+        let timeout = fsm.get_current_timeout().await;
+        assert_eq!(timeout, Some(Duration::from_secs(10))); // value is 0, so long timeout
+
+        // Change context value
+        fsm.process_event(&TestEvent::Up).await.unwrap(); // value = 1
+        for _ in 0..5 {
+            fsm.process_event(&TestEvent::Up).await.unwrap(); // value = 6
+        }
+
+        let timeout = fsm.get_current_timeout().await;
+        assert_eq!(timeout, Some(Duration::from_secs(5))); // value > 5, so short timeout
+
+        // Transition to Display (no timeout)
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+
+        let timeout = fsm.get_current_timeout().await;
+        assert_eq!(timeout, None);
+    }
+
+    #[tokio::test]
+    async fn test_time_in_state() {
+        let mut fsm = create_test_fsm();
+        assert_eq!(fsm.time_in_state(), None);
+
+        fsm.init(TestState::Root).await.unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let elapsed = fsm.time_in_state().unwrap();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < Duration::from_secs(5));
+
+        // Entering another state restarts the clock
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert!(fsm.time_in_state().unwrap() < elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_remaining_timeout() {
+        use crate::fn_state;
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Root,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, _context| Box::pin(async { Response::Transition(TestState::Menu) }),
+                    |_context| Box::pin(async {}),
+                )
+                .with_timeout(|_context| Some(Duration::from_millis(200))),
+            )
+            .state(
+                TestState::Menu,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, _context| Box::pin(async { Response::Handled }),
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .build();
+        assert_eq!(fsm.remaining_timeout().await, None);
+
+        fsm.init(TestState::Root).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let remaining = fsm.remaining_timeout().await.unwrap();
+        assert!(remaining <= Duration::from_millis(100));
+        assert!(remaining >= Duration::from_millis(50));
+
+        // Saturates at zero once the timeout has passed
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(fsm.remaining_timeout().await, Some(Duration::ZERO));
+
+        // States without a timeout have none remaining
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.remaining_timeout().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_cached_timeout() {
+        let mut fsm = create_test_fsm();
+        assert_eq!(fsm.cached_timeout(), None);
+
+        fsm.init(TestState::Root).await.unwrap();
+        assert_eq!(fsm.cached_timeout(), fsm.get_current_timeout().await);
+        assert_eq!(fsm.cached_timeout(), Some(Duration::from_secs(30)));
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.cached_timeout(), fsm.get_current_timeout().await);
+        assert_eq!(fsm.cached_timeout(), Some(Duration::from_secs(10)));
+
+        // Handled events leave the cache alone until the next transition
+        for _ in 0..6 {
+            fsm.process_event(&TestEvent::Up).await.unwrap();
+        }
+        assert_eq!(fsm.cached_timeout(), Some(Duration::from_secs(10)));
+        assert_eq!(
+            fsm.get_current_timeout().await,
+            Some(Duration::from_secs(5))
+        );
+
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.cached_timeout(), fsm.get_current_timeout().await);
+        assert_eq!(fsm.cached_timeout(), None);
+    }
+
+    #[tokio::test]
+    async fn test_transition_on_enter() {
+        let mut fsm = create_test_fsm();
+
+        // Initialize to Volume state, which transitions to Root on enter
+        fsm.init(TestState::Volume).await.unwrap();
+
+        // Should end up in Root state, not Volume
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+
+        // Check that both on_enter and on_exit were called for Volume
+        assert!(fsm.context().entries.contains(&"Volume".to_string()));
+        assert!(fsm.context().entries.contains(&"Root".to_string()));
+        assert!(fsm.context().exits.contains(&"Volume".to_string()));
+        assert!(
+            fsm.transition_log()
+                .contains(&(TestState::Volume, TestState::Root))
+        );
+    }
+
+    // Redirects every transition into it on to another state
+    struct BounceState(TestState);
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for BounceState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.value += 1;
+            Response::Transition(self.0.clone())
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Handled
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enter_chain_too_long() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, BounceState(TestState::Settings))
+            .state(TestState::Settings, BounceState(TestState::Menu))
+            .build();
+
+        // Two states allow two redirects before giving up
+        let result = fsm.init(TestState::Menu).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::EnterChainTooLong(TestState::Settings))
+        ));
+        assert_eq!(fsm.context().value, 3);
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+
+        // A lower limit rejects even a single redirect
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Volume, TransitionOnEnterState)
+            .max_enter_chain(0)
+            .build();
+        let result = fsm.init(TestState::Volume).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::EnterChainTooLong(TestState::Root))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_error_conditions() {
+        let mut fsm = create_test_fsm();
+
+        // Test processing event without initialization
+        let result = fsm.process_event(&TestEvent::Enter).await;
+        assert!(matches!(result, Err(FsmError::StateMachineNotInitialized)));
+
+        // Initialize and test invalid state
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Test unhandled event in root (should return error)
+        let result = fsm.process_event(&TestEvent::Timeout).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_build_and_init() {
+        let fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(superstate_fn)
+            .initial(TestState::Menu)
+            .build_and_init()
+            .await
+            .unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu"]);
+
+        let result =
+            StateMachineBuilder::<TestState, TestContext, TestEvent>::new(TestContext::new())
+                .state(TestState::Root, RootState)
+                .build_and_init()
+                .await;
+        assert!(matches!(result, Err(FsmError::NoInitialState)));
+    }
+
+    #[tokio::test]
+    async fn test_context_access() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Test context access
+        assert_eq!(fsm.context().value, 0);
+
+        // Modify through event
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 1);
+
+        // Test mutable context access
+        fsm.context_mut().value = 100;
+        assert_eq!(fsm.context().value, 100);
+    }
+
+    #[tokio::test]
+    async fn test_replace_context() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+
+        let mut fresh = TestContext::new();
+        fresh.value = 50;
+        let old = fsm.replace_context(fresh);
+        assert_eq!(old.value, 1);
+        assert_eq!(old.entries, vec!["Root", "Menu"]);
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+
+        // The machine keeps running against the new context
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 51);
+        assert!(fsm.context().entries.is_empty());
+
+        let doubled = fsm.with_context_mut(|context| {
+            context.value *= 2;
+            context.value
+        });
+        assert_eq!(doubled, 102);
+        assert_eq!(fsm.context().value, 102);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(superstate_fn)
+            .collect_metrics()
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Up).await.unwrap(); // Handled by Menu
+        fsm.process_event(&TestEvent::Timeout).await.unwrap_err(); // Root errors
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Settings -> Menu
+
+        let metrics = fsm.metrics();
+        assert_eq!(metrics.total_events(), 5);
+        assert_eq!(metrics.total_errors(), 1);
+
+        assert_eq!(metrics.events_handled(&TestState::Root), 1);
+        assert_eq!(metrics.events_handled(&TestState::Menu), 2);
+        assert_eq!(metrics.events_handled(&TestState::Settings), 1);
+
+        assert_eq!(metrics.transitions_from(&TestState::Root), 1);
+        assert_eq!(metrics.transitions_from(&TestState::Menu), 1);
+        assert_eq!(metrics.transitions_from(&TestState::Settings), 1);
+
+        // Menu delegated Timeout to Root, whose handler failed
+        assert_eq!(metrics.errors_in(&TestState::Root), 1);
+        assert_eq!(metrics.errors_in(&TestState::Menu), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_failed_recovery_once() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(superstate_fn)
+            .on_error_goto(TestState::Root, TestState::Display)
+            .collect_metrics()
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Root fails, and recovering to the unregistered Display fails too
+        let err = fsm.process_event(&TestEvent::Timeout).await.unwrap_err();
+        assert!(matches!(
+            err,
+            FsmError::StateNotRegistered(TestState::Display)
+        ));
+        let metrics = fsm.metrics();
+        assert_eq!(metrics.total_errors(), 1);
+        assert_eq!(metrics.errors_in(&TestState::Root), 1);
+        assert_eq!(metrics.errors_in(&TestState::Menu), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_fallback_events_separately() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .global_handler(|event, _ctx| match event {
+                TestEvent::Up => Some(Response::Handled),
+                _ => None,
+            })
+            .on_unhandled(|_state, _event, _ctx| Response::Handled)
+            .collect_metrics()
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        fsm.process_event(&TestEvent::Up).await.unwrap(); // Global handler
+        fsm.process_event(&TestEvent::Timeout).await.unwrap(); // Fallback
+        fsm.process_event(&TestEvent::Down).await.unwrap(); // Handled by Menu
+
+        let metrics = fsm.metrics();
+        assert_eq!(metrics.total_events(), 3);
+        assert_eq!(metrics.fallback_events_handled(), 2);
+        assert_eq!(metrics.events_handled(&TestState::Menu), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_disabled_by_default() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+
+        assert_eq!(fsm.metrics().total_events(), 0);
+        assert_eq!(fsm.metrics().transitions_from(&TestState::Root), 0);
+    }
+
+    #[tokio::test]
+    async fn test_restore_and_enter() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        let snapshot = fsm.snapshot();
+
+        let mut restored = create_test_fsm();
+        restored.restore_and_enter(snapshot).await.unwrap();
+
+        assert_eq!(restored.current_state(), Some(TestState::Menu));
+        assert_eq!(restored.context().entries, vec!["Root", "Menu"]);
+        assert!(
+            restored
+                .transition_log()
+                .contains(&(TestState::Root, TestState::Menu))
+        );
+
+        let unregistered = FsmSnapshot {
+            current_state: Some(TestState::Volume),
+            initial_state: None,
+            transition_log: HashSet::new(),
+        };
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .build();
+        let result = fsm.restore_and_enter(unregistered).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::StateNotRegistered(TestState::Volume))
+        ));
+        assert_eq!(fsm.current_state(), None);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_diff() {
+        let mut reference = create_test_fsm();
+        reference.init(TestState::Root).await.unwrap();
+        reference.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+
+        let mut same = create_test_fsm();
+        same.init(TestState::Root).await.unwrap();
+        same.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(reference.snapshot(), same.snapshot());
+        assert!(reference.snapshot().diff(&same.snapshot()).is_empty());
+
+        let mut divergent = create_test_fsm();
+        divergent.init(TestState::Root).await.unwrap();
+        divergent.process_event(&TestEvent::Enter).await.unwrap();
+        divergent.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        assert_ne!(reference.snapshot(), divergent.snapshot());
+
+        let diff = reference.snapshot().diff(&divergent.snapshot());
+        assert!(diff.current_state_differs);
+        assert!(diff.only_in_self.is_empty());
+        assert_eq!(
+            diff.only_in_other,
+            HashSet::from([(TestState::Menu, TestState::Settings)])
+        );
+        assert!(!diff.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_history_log() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(superstate_fn)
+            .with_history_log()
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Up).await.unwrap(); // handled, no record
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Settings -> Menu
+
+        let steps: Vec<_> = fsm
+            .history()
+            .iter()
+            .map(|record| (record.from.clone(), record.to.clone(), record.event.clone()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (None, TestState::Root, None),
+                (
+                    Some(TestState::Root),
+                    TestState::Menu,
+                    Some("Enter".to_string())
+                ),
+                (
+                    Some(TestState::Menu),
+                    TestState::Settings,
+                    Some("Select".to_string())
+                ),
+                (
+                    Some(TestState::Settings),
+                    TestState::Menu,
+                    Some("Back".to_string())
+                ),
+            ]
+        );
+        assert!(
+            fsm.history()
+                .iter()
+                .zip(fsm.history().iter().skip(1))
+                .all(|(earlier, later)| earlier.at <= later.at)
+        );
+
+        // Capped histories keep only the most recent records
+        let mut fsm = create_test_fsm();
+        assert!(fsm.history().is_empty());
+        fsm.init(TestState::Root).await.unwrap();
+        assert!(fsm.history().is_empty());
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(superstate_fn)
+            .history_capacity(1)
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.history().len(), 1);
+        assert_eq!(fsm.history()[0].to, TestState::Menu);
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(fsm.history().len(), 1);
+        assert_eq!(fsm.history()[0].to, TestState::Root);
+    }
+
+    #[tokio::test]
+    async fn test_coverage() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+
+        let coverage = fsm.coverage();
+        assert_eq!(
+            coverage.entered_states,
+            HashSet::from([TestState::Root, TestState::Menu, TestState::Settings])
+        );
+        assert_eq!(
+            coverage.unentered_states,
+            HashSet::from([TestState::Display, TestState::Volume])
+        );
+        assert_eq!(
+            coverage.taken_edges,
+            HashSet::from([
+                (TestState::Root, TestState::Menu),
+                (TestState::Menu, TestState::Settings),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_event_tracked() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        let outcome = fsm.process_event_tracked(&TestEvent::Up).await.unwrap();
+        assert_eq!(
+            outcome,
+            TransitionOutcome {
+                from: Some(TestState::Menu),
+                to: TestState::Menu,
+                transitioned: false,
+            }
+        );
+
+        let outcome = fsm.process_event_tracked(&TestEvent::Select).await.unwrap();
+        assert_eq!(
+            outcome,
+            TransitionOutcome {
+                from: Some(TestState::Menu),
+                to: TestState::Settings,
+                transitioned: true,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_builder_pattern() {
+        let context = TestContext::new();
+
+        // Test builder with minimal setup
+        let fsm = StateMachineBuilder::new(context)
+            .state(TestState::Root, RootState)
+            .build();
+
+        assert_eq!(fsm.current_state(), None);
+
+        // Test builder with superstate function
+        let context2 = TestContext::new();
+        let _fsm2 = StateMachineBuilder::new(context2)
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .superstate_fn(|state| match state {
+                TestState::Menu => Some(TestState::Root),
+                _ => None,
+            })
+            .build();
+    }
+
+    #[tokio::test]
+    async fn test_builder_states_from_iterator() {
+        let entries: Vec<(TestState, Box<dyn Stateful<_, _, _> + Send + Sync>)> = vec![
+            (TestState::Root, Box::new(RootState)),
+            (TestState::Menu, Box::new(MenuState)),
+            (TestState::Settings, Box::new(SettingsState)),
+        ];
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .states(entries)
+            .build();
+
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu", "Settings"]);
+    }
+
+    #[tokio::test]
+    async fn test_builder_superstate_map() {
+        let hierarchy = HashMap::from([
+            (TestState::Menu, TestState::Root),
+            (TestState::Settings, TestState::Root),
+            (TestState::Display, TestState::Settings),
+        ]);
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .superstate_map(hierarchy)
+            .build_validated()
+            .unwrap();
+        fsm.init(TestState::Display).await.unwrap();
+
+        // Display delegates Enter through Settings to Root
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.ancestors(), vec![TestState::Menu, TestState::Root]);
+    }
+
+    #[tokio::test]
+    async fn test_build_validated() {
+        let fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .superstate_fn(superstate_fn)
+            .build_validated();
+        assert!(fsm.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_build_validated_no_states() {
+        let result = StateMachineBuilder::<TestState, _, TestEvent>::new(TestContext::new())
+            .build_validated();
+        assert!(matches!(result, Err(FsmError::NoStatesRegistered)));
+
+        // `build` stays permissive
+        let fsm = StateMachineBuilder::<TestState, _, TestEvent>::new(TestContext::new()).build();
+        assert!(fsm.registered_states().is_empty());
+
+        let result = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .initial(TestState::Menu)
+            .build_validated();
+        assert!(matches!(
+            result,
+            Err(FsmError::StateNotRegistered(TestState::Menu))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_build_validated_dangling_superstate() {
+        // Display's superstate is Settings, which is not registered
+        let result = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Display, DisplayState)
+            .superstate_fn(superstate_fn)
+            .build_validated();
+
+        assert!(matches!(
+            result,
+            Err(FsmError::StateNotRegistered(TestState::Settings))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_build_validated_superstate_cycle() {
+        // Menu and Settings are each other's superstate
+        let result = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .superstate_fn(|state| match state {
+                TestState::Menu => Some(TestState::Settings),
+                TestState::Settings => Some(TestState::Menu),
+                _ => None,
+            })
+            .build_validated();
+
+        match result {
+            Err(FsmError::SuperstateCycle(cycle)) => {
+                assert_eq!(cycle.len(), 2);
+                assert!(cycle.contains(&TestState::Menu));
+                assert!(cycle.contains(&TestState::Settings));
+            }
+            _ => panic!("Expected SuperstateCycle error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_validated_self_superstate() {
+        let result = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .superstate_fn(|state| Some(state.clone()))
+            .build_validated();
+
+        match result {
+            Err(FsmError::SuperstateCycle(cycle)) => assert_eq!(cycle, vec![TestState::Root]),
+            _ => panic!("Expected SuperstateCycle error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multiple_transitions() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Test a sequence of transitions
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+
+        // Check all transitions were recorded
+        let expected_transitions: HashSet<(TestState, TestState)> = [
+            (TestState::Root, TestState::Menu),
+            (TestState::Menu, TestState::Settings),
+            (TestState::Settings, TestState::Display),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(fsm.transition_log(), &expected_transitions);
+
+        // Check all entries and exits
+        let expected_entries = vec!["Root", "Menu", "Settings", "Display"];
+        let expected_exits = vec!["Menu"];
+        assert_eq!(fsm.context().entries, expected_entries);
+        assert_eq!(fsm.context().exits, expected_exits);
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_and_incoming() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Settings -> Menu
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root
+
+        assert_eq!(fsm.outgoing(&TestState::Root), vec![TestState::Menu]);
+
+        let mut from_menu = fsm.outgoing(&TestState::Menu);
+        from_menu.sort_by_key(|state| format!("{state:?}"));
+        assert_eq!(from_menu, vec![TestState::Root, TestState::Settings]);
+
+        let mut into_menu = fsm.incoming(&TestState::Menu);
+        into_menu.sort_by_key(|state| format!("{state:?}"));
+        assert_eq!(into_menu, vec![TestState::Root, TestState::Settings]);
+
+        assert!(fsm.outgoing(&TestState::Volume).is_empty());
+        assert!(fsm.incoming(&TestState::Volume).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_to_plantuml() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+
+        let diagram = fsm.to_plantuml();
+        assert!(diagram.starts_with("@startuml"));
+        assert!(diagram.contains("state Menu <<Current>>"));
+        assert!(diagram.contains("Root --> Menu"));
+        assert!(diagram.trim_end().ends_with("@enduml"));
+    }
+
+    #[tokio::test]
+    async fn test_merge_transition_logs() {
+        let mut first = create_test_fsm();
+        first.init(TestState::Root).await.unwrap();
+        first.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+
+        let mut second = create_test_fsm();
+        second.init(TestState::Settings).await.unwrap();
+        second.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+
+        let mut merged = create_test_fsm();
+        merged.merge_transition_log(first.transition_log());
+        merged.import_snapshot_edges(&second.snapshot());
+        // Importing the same edges again adds nothing
+        merged.merge_transition_log(first.transition_log());
+        assert_eq!(merged.transition_log().len(), 2);
+        assert_eq!(merged.current_state(), None);
+
+        let diagram = merged.to_plantuml();
+        assert!(diagram.contains("Root --> Menu"));
+        assert!(diagram.contains("Settings --> Display"));
+    }
+
+    #[test]
+    fn test_static_graph_before_any_event() {
+        let fsm = create_test_fsm();
+
+        let (mut states, mut edges) = fsm.static_graph();
+        states.sort_by_key(|s| format!("{s:?}"));
+        edges.sort_by_key(|e| format!("{e:?}"));
+        assert_eq!(
+            states,
+            vec![
+                TestState::Display,
+                TestState::Menu,
+                TestState::Root,
+                TestState::Settings,
+                TestState::Volume,
+            ]
+        );
+        assert_eq!(
+            edges,
+            vec![
+                (TestState::Display, TestState::Settings),
+                (TestState::Menu, TestState::Root),
+                (TestState::Settings, TestState::Root),
+            ]
+        );
+
+        // The runtime diagram is empty, the static one has every state
+        assert!(!fsm.to_plantuml().contains("state Root"));
+        let diagram = fsm.to_plantuml_static();
+        for name in ["Root", "Menu", "Settings", "Display", "Volume"] {
+            assert!(diagram.contains(&format!("state {name}")));
+        }
+        assert!(!diagram.contains("-->"));
+
+        let diagram = fsm.to_mermaid_static();
+        assert!(diagram.contains("state Settings {\n            Display\n        }"));
+        assert!(diagram.contains("Volume"));
+    }
+
+    #[tokio::test]
+    async fn test_to_mermaid() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
+        fsm.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+
+        let diagram = fsm.to_mermaid();
+        assert!(diagram.starts_with("stateDiagram-v2\n"));
+        assert!(diagram.contains("[*] --> Root"));
+        assert!(diagram.contains("Settings --> Display"));
+        assert!(diagram.contains("state Settings {\n            Display\n        }"));
+        assert!(diagram.contains("class Display current"));
+    }
+
+    #[tokio::test]
+    async fn test_to_dot() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+
+        let diagram = fsm.to_dot();
+        assert!(diagram.starts_with("digraph"));
+        assert!(diagram.contains("\"Root\" -> \"Menu\";"));
+        assert!(diagram.contains("\"Menu\" [style=filled,fillcolor=greenyellow];"));
+        assert!(diagram.contains("subgraph cluster_"));
+        // Registered but never visited states still get a node
+        assert!(diagram.contains("\"Volume\";"));
+    }
+
+    #[tokio::test]
+    async fn test_state_reentry() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Go Root -> Menu -> Root -> Menu
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu again
+
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+
+        // Should have multiple entries/exits for the same states
+        assert_eq!(fsm.context().entries, vec!["Root", "Menu", "Root", "Menu"]);
+        // Returning to the superstate Root exits and re-enters it
+        assert_eq!(fsm.context().exits, vec!["Menu", "Root"]);
+    }
+
+    #[tokio::test]
+    async fn test_unique_transitions_only() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Perform the same transition multiple times
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root
+        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu (again)
+        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root (again)
+
+        // Only two unique edges should be stored
+        assert_eq!(fsm.transition_log().len(), 2);
+        assert!(
+            fsm.transition_log()
+                .contains(&(TestState::Root, TestState::Menu))
+        );
+        assert!(
+            fsm.transition_log()
+                .contains(&(TestState::Menu, TestState::Root))
+        );
+    }
+
+    // Test concurrent access (if the FSM needs to be thread-safe)
+    #[tokio::test]
+    async fn test_context_modification() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        // Test that context modifications persist across events
+        fsm.context_mut().value = 42;
+
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 43); // 42 + 1
+
+        fsm.process_event(&TestEvent::Down).await.unwrap();
+        assert_eq!(fsm.context().value, 42); // 43 - 1
+    }
+
+    #[tokio::test]
+    async fn test_error_propagation() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Test that errors from states are properly propagated
+        let result = fsm.process_event(&TestEvent::Timeout).await;
+
+        match result {
+            Err(FsmError::InvalidEvent(state, msg)) => {
+                assert_eq!(state, TestState::Root);
+                assert!(msg.contains("Root: Unhandled event"));
+            }
+            _ => panic!("Expected InvalidEvent error"),
+        }
+
+        // FSM should still be in a valid state after error
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+    }
+
+    // Test with a more complex state that uses Arc<Mutex<>> for shared state
+    #[derive(Debug)]
+    struct SharedContext {
+        pub counter: Arc<Mutex<i32>>,
+        pub log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SharedContext {
+        fn new() -> Self {
+            Self {
+                counter: Arc::new(Mutex::new(0)),
+                log: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    struct SharedState;
+
+    #[async_trait]
+    impl Stateful<TestState, SharedContext, TestEvent> for SharedState {
+        async fn on_enter(&mut self, context: &mut SharedContext) -> Response<TestState> {
+            let mut log = context.log.lock().unwrap();
+            log.push("SharedState entered".to_string());
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut SharedContext,
+        ) -> Response<TestState> {
+            match event {
+                TestEvent::Up => {
+                    let mut counter = context.counter.lock().unwrap();
+                    *counter += 1;
+                    Response::Handled
+                }
+                _ => Response::Super,
+            }
+        }
+
+        async fn on_exit(&mut self, context: &mut SharedContext) {
+            let mut log = context.log.lock().unwrap();
+            log.push("SharedState exited".to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_context() {
+        let context = SharedContext::new();
+        let counter_clone = Arc::clone(&context.counter);
+        let log_clone = Arc::clone(&context.log);
+
+        let mut fsm = StateMachineBuilder::new(context)
+            .state(TestState::Root, SharedState)
+            .build();
+
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Test that shared state works
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+
+        assert_eq!(*counter_clone.lock().unwrap(), 1);
+
+        let log = log_clone.lock().unwrap();
+        assert!(log.contains(&"SharedState entered".to_string()));
+    }
+
+    // Benchmark-style test for performance
+    #[tokio::test]
+    async fn test_performance() {
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+
+        let start = std::time::Instant::now();
+
+        // Process many events
+        for _ in 0..1000 {
+            fsm.process_event(&TestEvent::Up).await.unwrap();
+            fsm.process_event(&TestEvent::Down).await.unwrap();
+        }
+
+        let duration = start.elapsed();
+        println!("Processed 2000 events in {:?}", duration);
+
+        // Should still be in correct state
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().value, 0); // Up and Down should cancel out
+    }
+
+    // Test edge case: state that returns Error response
+    struct ErrorState;
+
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for ErrorState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            Response::Error("ErrorState always fails on enter".to_string())
         }
 
         async fn on_event(
@@ -452,439 +5535,961 @@ mod tests {
             _event: &TestEvent,
             _context: &mut TestContext,
         ) -> Response<TestState> {
-            Response::Handled
+            Response::Error("ErrorState always fails on event".to_string())
         }
 
-        async fn on_exit(&mut self, context: &mut TestContext) {
-            context.exits.push("Volume".to_string());
-        }
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
     }
 
-    // function to chose superstate
-    fn superstate_fn(state: &TestState) -> Option<TestState> {
-        match state {
-            TestState::Menu | TestState::Settings => Some(TestState::Root),
-            TestState::Display => Some(TestState::Settings),
-            _ => None,
+    #[tokio::test]
+    async fn test_error_state() {
+        let context = TestContext::new();
+        let mut fsm = StateMachineBuilder::new(context)
+            .state(TestState::Root, ErrorState)
+            .build();
+
+        // Test that error on enter is handled
+        let err = fsm.init(TestState::Root).await.unwrap_err();
+        assert_eq!(err.phase(), Some(ErrorPhase::Enter));
+        if let FsmError::StateInvalid(state, msg) = err {
+            assert_eq!(state, TestState::Root);
+            assert!(msg.contains("ErrorState always fails on enter"));
         }
-    }
 
-    fn create_test_fsm() -> StateMachine<TestState, TestContext, TestEvent> {
-        let context = TestContext::new();
+        // The failed state was never committed, so the machine is still
+        // uninitialized and can be initialized again
+        assert_eq!(fsm.current_state(), None);
+        assert!(matches!(
+            fsm.process_event(&TestEvent::Enter).await,
+            Err(FsmError::StateMachineNotInitialized)
+        ));
+        assert!(fsm.init(TestState::Root).await.is_err());
+        assert_eq!(fsm.current_state(), None);
 
-        StateMachineBuilder::new(context)
+        // Errors from on_event are tagged with the event phase
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
             .state(TestState::Root, RootState)
-            .state(TestState::Menu, MenuState)
-            .state(TestState::Settings, SettingsState)
-            .state(TestState::Display, DisplayState)
-            .state(TestState::Volume, TransitionOnEnterState)
-            .superstate_fn(superstate_fn)
-            .build()
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+        let err = fsm.process_event(&TestEvent::Back).await.unwrap_err();
+        assert_eq!(err.phase(), Some(ErrorPhase::Event));
     }
 
     #[tokio::test]
-    async fn test_initialization() {
-        let mut fsm = create_test_fsm();
+    async fn test_failed_enter_falls_back_to_active_state() {
+        // Menu and Settings share Root, which stays active when Settings fails
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, ErrorState)
+            .superstate_fn(superstate_fn)
+            .collect_metrics()
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
 
-        // Test initial state
-        assert_eq!(fsm.current_state(), None);
+        let err = fsm.process_event(&TestEvent::Select).await.unwrap_err();
+        assert!(matches!(
+            err,
+            FsmError::StateInvalid(TestState::Settings, _)
+        ));
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        // The failed transition is neither logged nor counted
+        assert!(fsm.transition_log().is_empty());
+        assert_eq!(fsm.metrics().transitions_from(&TestState::Menu), 0);
 
-        // Initialize the FSM
+        // Events go to Root, and Menu is not exited a second time
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().exits, vec!["Menu"]);
+
+        // Without a common ancestor no state is left active
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, ErrorState)
+            .build();
         fsm.init(TestState::Root).await.unwrap();
 
-        // Check current state
-        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        let err = fsm.process_event(&TestEvent::Enter).await.unwrap_err();
+        assert!(matches!(err, FsmError::StateInvalid(TestState::Menu, _)));
+        assert_eq!(fsm.current_state(), None);
+        assert!(matches!(
+            fsm.process_event(&TestEvent::Back).await,
+            Err(FsmError::InconsistentState(TestState::Menu))
+        ));
 
-        // Check that on_enter was called
-        assert_eq!(fsm.context().entries, vec!["Root"]);
+        fsm.reset().await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().exits, vec!["Root"]);
     }
 
     #[tokio::test]
-    async fn test_basic_transitions() {
+    async fn test_replace_and_remove_state() {
+        use crate::fn_state;
+
         let mut fsm = create_test_fsm();
         fsm.init(TestState::Root).await.unwrap();
 
-        // Transition from Root to Menu
+        let new_menu = || {
+            Box::new(fn_state(
+                |context: &mut TestContext| {
+                    Box::pin(async move {
+                        context.entries.push("New Menu".to_string());
+                        Response::Handled
+                    })
+                },
+                |_, _| Box::pin(async { Response::Super }),
+                |_| Box::pin(async {}),
+            ))
+        };
+        let old = fsm.replace_state(TestState::Menu, new_menu()).unwrap();
+        assert!(old.is_some());
+
         fsm.process_event(&TestEvent::Enter).await.unwrap();
         assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().entries.last().unwrap(), "New Menu");
+
+        // Active states can be neither replaced nor removed
+        assert!(matches!(
+            fsm.replace_state(TestState::Menu, new_menu()),
+            Err(FsmError::StateActive(TestState::Menu))
+        ));
+        assert!(matches!(
+            fsm.remove_state(&TestState::Menu),
+            Err(FsmError::StateActive(TestState::Menu))
+        ));
+        assert!(matches!(
+            fsm.remove_state(&TestState::Root),
+            Err(FsmError::StateActive(TestState::Root))
+        ));
+
+        fsm.remove_state(&TestState::Display).unwrap();
+        assert!(!fsm.is_registered(&TestState::Display));
+        assert!(matches!(
+            fsm.remove_state(&TestState::Display),
+            Err(FsmError::StateNotRegistered(TestState::Display))
+        ));
+
+        // An active alias keeps the state it shares a handler with in place
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, MenuState)
+            .state_alias(TestState::Menu, TestState::Settings)
+            .build();
+        fsm.init(TestState::Settings).await.unwrap();
+        assert!(matches!(
+            fsm.replace_state(TestState::Menu, new_menu()),
+            Err(FsmError::StateActive(TestState::Menu))
+        ));
+        assert!(matches!(
+            fsm.remove_state(&TestState::Menu),
+            Err(FsmError::StateActive(TestState::Menu))
+        ));
+        assert!(matches!(
+            fsm.remove_state(&TestState::Settings),
+            Err(FsmError::StateActive(TestState::Settings))
+        ));
+        assert!(fsm.is_registered(&TestState::Settings));
+    }
 
-        // Check transition tracking
-        assert_eq!(fsm.context().transitions, vec!["Root->Menu"]);
-        assert_eq!(fsm.context().entries, vec!["Root", "Menu"]);
-        assert_eq!(fsm.context().exits, vec!["Root"]);
+    // Counts how often it is registered, entered and exited
+    struct RegisteredState;
 
-        // Transition back to Root
-        fsm.process_event(&TestEvent::Back).await.unwrap();
-        assert_eq!(fsm.current_state(), Some(TestState::Root));
-        assert_eq!(fsm.context().transitions, vec!["Root->Menu", "Menu->Root"]);
-    }
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for RegisteredState {
+        async fn on_register(&mut self, context: &mut TestContext) {
+            context.value += 1;
+        }
 
-    #[tokio::test]
-    async fn test_event_handling() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Menu).await.unwrap();
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.entries.push("Registered".to_string());
+            Response::Handled
+        }
 
-        // Test handled events
-        assert_eq!(fsm.context().value, 0);
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Transition(TestState::Menu)
+        }
+    }
 
-        fsm.process_event(&TestEvent::Up).await.unwrap();
+    #[tokio::test]
+    async fn test_prepare_runs_on_register_once() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RegisteredState)
+            .state(TestState::Menu, MenuState)
+            .initial(TestState::Root)
+            .build_and_init()
+            .await
+            .unwrap();
+        assert!(fsm.is_prepared());
         assert_eq!(fsm.context().value, 1);
-        assert_eq!(fsm.current_state(), Some(TestState::Menu)); // Should stay in same state
 
-        fsm.process_event(&TestEvent::Down).await.unwrap();
-        assert_eq!(fsm.context().value, 0);
+        for _ in 0..3 {
+            fsm.prepare().await;
+            fsm.process_event(&TestEvent::Enter).await.unwrap();
+            fsm.reset().await.unwrap();
+            fsm.init(TestState::Root).await.unwrap();
+        }
+        assert_eq!(fsm.context().value, 1);
+        // on_enter still runs on every entry
+        let entered = fsm.context().entries.iter().filter(|e| *e == "Registered");
+        assert_eq!(entered.count(), 7);
     }
 
-    #[tokio::test]
-    async fn test_superstate_delegation() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Menu).await.unwrap();
+    // Counts the events it handles in its own fields, so clones diverge
+    #[derive(Clone)]
+    struct CountingState {
+        handled: u32,
+    }
 
-        // Send an event that Menu doesn't handle (should delegate to Root)
-        let result = fsm.process_event(&TestEvent::Timeout).await;
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for CountingState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            Response::Handled
+        }
 
-        // Should get an error because Root doesn't handle Timeout either
-        assert!(result.is_err());
-        if let Err(FsmError::InvalidEvent(state, msg)) = result {
-            assert_eq!(state, TestState::Root);
-            assert!(msg.contains("Root: Unhandled event"));
+        async fn on_event(
+            &mut self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            self.handled += 1;
+            context.value = self.handled as i32;
+            match event {
+                TestEvent::Enter => Response::Transition(TestState::Menu),
+                TestEvent::Back => Response::Transition(TestState::Root),
+                _ => Response::Handled,
+            }
         }
     }
 
     #[tokio::test]
-    async fn test_deep_hierarchy() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Display).await.unwrap();
-
-        // Display handles Up/Down
+    async fn test_try_clone_diverges_independently() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .cloneable_state(TestState::Root, CountingState { handled: 0 })
+            .cloneable_state(TestState::Menu, CountingState { handled: 0 })
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
         fsm.process_event(&TestEvent::Up).await.unwrap();
-        assert_eq!(fsm.context().value, 10);
-        assert_eq!(fsm.current_state(), Some(TestState::Display));
 
-        // Display doesn't handle Enter, should delegate through Settings to Root
-        fsm.process_event(&TestEvent::Enter).await.unwrap();
-        assert_eq!(fsm.current_state(), Some(TestState::Menu)); // Root handles Enter -> Menu
+        let mut clone = fsm.try_clone().unwrap();
+        assert_eq!(clone.current_state(), Some(TestState::Root));
+        assert_eq!(clone.context().value, 1);
+
+        // Handler fields were cloned, not shared
+        clone.process_event(&TestEvent::Up).await.unwrap();
+        clone.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(clone.current_state(), Some(TestState::Menu));
+        assert_eq!(clone.context().value, 3);
+
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().value, 1);
+        assert!(fsm.transition_log().is_empty());
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(fsm.context().value, 2);
+
+        // Pending events are copied into a separate queue
+        fsm.event_queue().push(TestEvent::Up);
+        let clone = fsm.try_clone().unwrap();
+        clone.event_queue().push(TestEvent::Enter);
+        assert_eq!(fsm.event_queue().len(), 1);
+        assert_eq!(clone.event_queue().len(), 2);
+        fsm.event_queue().clear();
+        assert_eq!(clone.event_queue().len(), 2);
+
+        // A state added without `cloneable_state` makes the machine uncloneable
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .cloneable_state(TestState::Root, CountingState { handled: 0 })
+            .state(TestState::Menu, MenuState)
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
+        assert!(fsm.try_clone().is_none());
     }
 
     #[tokio::test]
-    async fn test_timeout_functionality() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Root).await.unwrap();
+    async fn test_build_with_context_reuses_builder() {
+        let builder = StateMachineBuilder::new(TestContext::new())
+            .cloneable_state(TestState::Root, CountingState { handled: 0 })
+            .cloneable_state(TestState::Menu, CountingState { handled: 0 })
+            .superstate_fn(|state| match state {
+                TestState::Menu => Some(TestState::Root),
+                _ => None,
+            });
+
+        let mut first = builder.build_with_context(TestContext::new()).unwrap();
+        let mut second = builder.build_with_context(TestContext::new()).unwrap();
+        first.init(TestState::Root).await.unwrap();
+        second.init(TestState::Root).await.unwrap();
+
+        first.process_event(&TestEvent::Enter).await.unwrap();
+        first.process_event(&TestEvent::Select).await.unwrap();
+        second.process_event(&TestEvent::Select).await.unwrap();
+
+        // Each machine counts with its own handler copies
+        assert_eq!(first.current_state(), Some(TestState::Menu));
+        assert_eq!(first.context().value, 1);
+        assert_eq!(second.current_state(), Some(TestState::Root));
+        assert_eq!(second.context().value, 1);
+        assert!(first.is_in(&TestState::Root));
+
+        // The builder is still usable for a consuming build
+        let mut third = builder.build();
+        third.init(TestState::Menu).await.unwrap();
+        assert_eq!(third.current_state(), Some(TestState::Menu));
+
+        let uncloneable = StateMachineBuilder::new(TestContext::new())
+            .cloneable_state(TestState::Root, CountingState { handled: 0 })
+            .state(TestState::Menu, MenuState);
+        assert!(uncloneable.build_with_context(TestContext::new()).is_none());
+        assert!(uncloneable.try_clone().is_none());
+    }
 
-        // Test timeout for Root state
-        let timeout = fsm.get_current_timeout().await;
-        assert_eq!(timeout, Some(Duration::from_secs(30)));
+    // Counts events across every machine sharing it
+    struct SharedCountingState {
+        handled: std::sync::atomic::AtomicU32,
+    }
 
-        // Transition to Menu
+    #[async_trait]
+    impl SharedStateful<TestState, TestContext, TestEvent> for SharedCountingState {
+        async fn on_event(
+            &self,
+            event: &TestEvent,
+            context: &mut TestContext,
+        ) -> Response<TestState> {
+            let handled = self
+                .handled
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                + 1;
+            context.value = handled as i32;
+            match event {
+                TestEvent::Enter => Response::Transition(TestState::Menu),
+                TestEvent::Back => Response::Transition(TestState::Root),
+                _ => Response::Handled,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cloned_builder_shares_handlers() {
+        use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+        let transitions = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&transitions);
+        let root = Arc::new(SharedCountingState {
+            handled: AtomicU32::new(0),
+        });
+        let builder = StateMachineBuilder::new(TestContext::new())
+            .shared_state(TestState::Root, Arc::clone(&root))
+            .cloneable_state(TestState::Menu, CountingState { handled: 0 })
+            .on_transition(move |_, _, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+
+        let mut first = builder.try_clone().unwrap().build();
+        let mut second = builder.build_with_context(TestContext::new()).unwrap();
+        first.init(TestState::Root).await.unwrap();
+        second.init(TestState::Root).await.unwrap();
+
+        // Both machines run the same shared handler, concurrently
+        let (a, b) = tokio::join!(
+            first.process_event(&TestEvent::Select),
+            second.process_event(&TestEvent::Select)
+        );
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(first.context().value + second.context().value, 3);
+        first.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(first.context().value, 3);
+        assert_eq!(root.handled.load(Ordering::SeqCst), 3);
+        assert_eq!(first.current_state(), Some(TestState::Menu));
+        assert_eq!(second.current_state(), Some(TestState::Root));
+
+        // Cloneable handlers are still copied, and listeners are shared
+        let mut third = builder.build();
+        third.init(TestState::Menu).await.unwrap();
+        third.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(third.context().value, 1);
+        assert_eq!(transitions.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_edges() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .allowed_edge(TestState::Root, TestState::Menu)
+            .build();
+        // Initializing is not a transition between states, so it is allowed
+        fsm.init(TestState::Root).await.unwrap();
         fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
 
-        // Test dynamic timeout based on context
-        let timeout = fsm.get_current_timeout().await;
-        assert_eq!(timeout, Some(Duration::from_secs(10))); // value is 0, so long timeout
+        let err = fsm.process_event(&TestEvent::Select).await.unwrap_err();
+        assert!(matches!(
+            err,
+            FsmError::IllegalTransition(TestState::Menu, TestState::Settings)
+        ));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().exits, vec!["Root"]);
+    }
 
-        // Change context value
-        fsm.process_event(&TestEvent::Up).await.unwrap(); // value = 1
-        for _ in 0..5 {
-            fsm.process_event(&TestEvent::Up).await.unwrap(); // value = 6
-        }
+    #[tokio::test]
+    async fn test_reachable() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Volume, TransitionOnEnterState)
+            .allowed_edge(TestState::Root, TestState::Menu)
+            .allowed_edge(TestState::Menu, TestState::Settings)
+            .allowed_edge(TestState::Settings, TestState::Menu)
+            // Volume is an island that only leads out
+            .allowed_edge(TestState::Volume, TestState::Root)
+            .build();
+        assert!(fsm.reachable().is_empty());
 
-        let timeout = fsm.get_current_timeout().await;
-        assert_eq!(timeout, Some(Duration::from_secs(5))); // value > 5, so short timeout
+        fsm.init(TestState::Root).await.unwrap();
+        assert_eq!(
+            fsm.reachable(),
+            HashSet::from([TestState::Root, TestState::Menu, TestState::Settings])
+        );
 
-        // Transition to Display (no timeout)
-        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
-        fsm.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(
+            fsm.reachable(),
+            HashSet::from([TestState::Menu, TestState::Settings])
+        );
 
-        let timeout = fsm.get_current_timeout().await;
-        assert_eq!(timeout, None);
+        // Without declared edges, the transition log is followed
+        let mut fsm = create_test_fsm();
+        fsm.init(TestState::Root).await.unwrap();
+        assert_eq!(fsm.reachable(), HashSet::from([TestState::Root]));
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        fsm.process_event(&TestEvent::Back).await.unwrap();
+        assert_eq!(
+            fsm.reachable(),
+            HashSet::from([TestState::Root, TestState::Menu])
+        );
     }
 
     #[tokio::test]
-    async fn test_transition_on_enter() {
-        let mut fsm = create_test_fsm();
+    async fn test_async_superstate_fn() {
+        use crate::fn_state;
+
+        fn recorder(name: &'static str) -> impl Stateful<TestState, TestContext, TestEvent> {
+            fn_state(
+                |_context| Box::pin(async { Response::Handled }),
+                move |_event, context: &mut TestContext| {
+                    Box::pin(async move {
+                        context.transitions.push(format!("{name} handled"));
+                        Response::Handled
+                    })
+                },
+                |_context| Box::pin(async {}),
+            )
+        }
 
-        // Initialize to Volume state, which transitions to Root on enter
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, recorder("Root"))
+            .state(TestState::Settings, recorder("Settings"))
+            .state(
+                TestState::Volume,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, _context| Box::pin(async { Response::Super }),
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .async_superstate_fn(|state, context| {
+                Box::pin(async move {
+                    // Stands in for a lookup in an external store
+                    tokio::task::yield_now().await;
+                    match state {
+                        TestState::Volume if context.value > 0 => Some(TestState::Settings),
+                        TestState::Volume => Some(TestState::Root),
+                        _ => None,
+                    }
+                })
+            })
+            .build();
         fsm.init(TestState::Volume).await.unwrap();
 
-        // Should end up in Root state, not Volume
-        assert_eq!(fsm.current_state(), Some(TestState::Root));
-
-        // Check that both on_enter and on_exit were called for Volume
-        assert!(fsm.context().entries.contains(&"Volume".to_string()));
-        assert!(fsm.context().entries.contains(&"Root".to_string()));
-        //assert!(fsm.context().exits.contains(&"Volume".to_string()));
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        fsm.context_mut().value = 1;
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        assert_eq!(
+            fsm.context().transitions,
+            vec!["Root handled", "Settings handled"]
+        );
     }
 
-    #[tokio::test]
-    async fn test_error_conditions() {
-        let mut fsm = create_test_fsm();
+    // Declares Root as its parent itself and delegates every event there
+    struct ChildOfRootState;
 
-        // Test processing event without initialization
-        let result = fsm.process_event(&TestEvent::Enter).await;
-        assert!(matches!(result, Err(FsmError::StateMachineNotInitialized)));
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for ChildOfRootState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            Response::Handled
+        }
 
-        // Initialize and test invalid state
-        fsm.init(TestState::Root).await.unwrap();
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Super
+        }
 
-        // Test unhandled event in root (should return error)
-        let result = fsm.process_event(&TestEvent::Timeout).await;
-        assert!(result.is_err());
+        fn superstate(&self) -> Option<TestState> {
+            Some(TestState::Root)
+        }
     }
 
     #[tokio::test]
-    async fn test_context_access() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Menu).await.unwrap();
+    async fn test_superstate_declared_by_state() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, MenuState)
+            .state(TestState::Volume, ChildOfRootState)
+            .build_validated()
+            .unwrap();
+        assert_eq!(fsm.superstate(&TestState::Volume), Some(TestState::Root));
 
-        // Test context access
-        assert_eq!(fsm.context().value, 0);
+        fsm.init(TestState::Volume).await.unwrap();
+        assert_eq!(fsm.ancestors(), vec![TestState::Volume, TestState::Root]);
+        assert_eq!(fsm.context().entries, vec!["Root"]);
 
-        // Modify through event
-        fsm.process_event(&TestEvent::Up).await.unwrap();
-        assert_eq!(fsm.context().value, 1);
+        // Volume delegates to Root, which handles Enter
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.context().transitions, vec!["Root->Menu"]);
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
 
-        // Test mutable context access
-        fsm.context_mut().value = 100;
-        assert_eq!(fsm.context().value, 100);
+        // The state's own declaration wins over the superstate function
+        let fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Volume, ChildOfRootState)
+            .superstate_fn(|_| Some(TestState::Settings))
+            .build();
+        assert_eq!(fsm.superstate(&TestState::Volume), Some(TestState::Root));
     }
 
     #[tokio::test]
-    async fn test_builder_pattern() {
-        let context = TestContext::new();
+    async fn test_terminal_state() {
+        use crate::fn_state;
 
-        // Test builder with minimal setup
-        let fsm = StateMachineBuilder::new(context)
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
             .state(TestState::Root, RootState)
+            .state(
+                TestState::Menu,
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, context: &mut TestContext| {
+                        Box::pin(async move {
+                            context.value += 1;
+                            Response::Transition(TestState::Root)
+                        })
+                    },
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .terminal(TestState::Menu)
             .build();
+        fsm.init(TestState::Root).await.unwrap();
+        assert!(!fsm.is_terminal());
 
-        assert_eq!(fsm.current_state(), None);
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert!(fsm.is_terminal());
 
-        // Test builder with superstate function
-        let context2 = TestContext::new();
-        let _fsm2 = StateMachineBuilder::new(context2)
+        let err = fsm.process_event(&TestEvent::Back).await.unwrap_err();
+        assert!(matches!(err, FsmError::Terminal(TestState::Menu)));
+        assert_eq!(fsm.context().value, 0);
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+
+        // Resetting is not an event, so it still leaves the terminal state
+        fsm.reset().await.unwrap();
+        assert!(!fsm.is_terminal());
+    }
+
+    #[tokio::test]
+    async fn test_on_rejected() {
+        use std::sync::{Arc, Mutex};
+
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&rejected);
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
             .state(TestState::Root, RootState)
             .state(TestState::Menu, MenuState)
-            .superstate_fn(|state| match state {
-                TestState::Menu => Some(TestState::Root),
-                _ => None,
+            .on_rejected(move |state, event, err| {
+                seen.lock()
+                    .unwrap()
+                    .push((state.clone(), format!("{event:?}"), err.to_string()));
             })
             .build();
+        fsm.init(TestState::Root).await.unwrap();
+
+        // Handled events are not reported
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert!(rejected.lock().unwrap().is_empty());
+
+        // Menu delegates Timeout to a superstate it doesn't have
+        let err = fsm.process_event(&TestEvent::Timeout).await.unwrap_err();
+        assert!(matches!(err, FsmError::InvalidEvent(TestState::Menu, _)));
+        assert_eq!(
+            *rejected.lock().unwrap(),
+            vec![(TestState::Menu, "Timeout".to_string(), err.to_string())]
+        );
     }
 
     #[tokio::test]
-    async fn test_multiple_transitions() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Root).await.unwrap();
+    async fn test_events_do_not_clone_state_ids() {
+        use crate::fn_state;
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-        // Test a sequence of transitions
-        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
-        fsm.process_event(&TestEvent::Select).await.unwrap(); // Menu -> Settings
-        fsm.process_event(&TestEvent::Select).await.unwrap(); // Settings -> Display
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
 
-        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        // A state id that is expensive to clone, counting every clone
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Named(String);
 
-        // Check all transitions were recorded
-        //TODO: Uncomment when transition logging is implemented right
-        //    let expected_transitions = vec!["Root->Menu", "Menu->Settings", "Settings->Display"];
-        //    let real_transitions: Vec<String> = fsm.context().transitions.iter().cloned().collect();
-        //    assert_eq!(real_transitions, expected_transitions);
+        impl Clone for Named {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                Named(self.0.clone())
+            }
+        }
 
-        // Check all entries and exits
-        let expected_entries = vec!["Root", "Menu", "Settings", "Display"];
-        let expected_exits = vec!["Root", "Menu", "Settings"];
-        assert_eq!(fsm.context().entries, expected_entries);
-        assert_eq!(fsm.context().exits, expected_exits);
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                Named("parent".to_string()),
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |_event, context: &mut TestContext| {
+                        Box::pin(async move {
+                            context.value -= 1;
+                            Response::Handled
+                        })
+                    },
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .state(
+                Named("leaf".to_string()),
+                fn_state(
+                    |_context| Box::pin(async { Response::Handled }),
+                    |event, context: &mut TestContext| {
+                        Box::pin(async move {
+                            match event {
+                                TestEvent::Up => {
+                                    context.value += 1;
+                                    Response::Handled
+                                }
+                                _ => Response::Super,
+                            }
+                        })
+                    },
+                    |_context| Box::pin(async {}),
+                ),
+            )
+            .superstate_fn(|state: &Named| (state.0 == "leaf").then(|| Named("parent".to_string())))
+            .build();
+        fsm.init(Named("leaf".to_string())).await.unwrap();
+
+        let before = CLONES.load(Ordering::Relaxed);
+        for _ in 0..10_000 {
+            fsm.process_event(&TestEvent::Up).await.unwrap();
+            fsm.process_event(&TestEvent::Down).await.unwrap();
+            fsm.process_event(&TestEvent::Up).await.unwrap();
+        }
+        assert_eq!(CLONES.load(Ordering::Relaxed), before);
+        assert_eq!(fsm.context().value, 10_000);
+        assert_eq!(fsm.current_state(), Some(Named("leaf".to_string())));
     }
 
-    #[tokio::test]
-    async fn test_state_reentry() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Root).await.unwrap();
+    // Fails on enter until it has been tried `failures + 1` times
+    struct FlakyState {
+        failures: u32,
+        attempts: u32,
+    }
 
-        // Go Root -> Menu -> Root -> Menu
-        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
-        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root
-        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu again
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for FlakyState {
+        async fn on_enter(&mut self, context: &mut TestContext) -> Response<TestState> {
+            self.attempts += 1;
+            context.entries.push(format!("attempt {}", self.attempts));
+            if self.attempts <= self.failures {
+                Response::Error("resource unavailable".to_string())
+            } else {
+                Response::Handled
+            }
+        }
 
-        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
 
-        // Should have multiple entries/exits for the same states
-        assert_eq!(fsm.context().entries, vec!["Root", "Menu", "Root", "Menu"]);
-        assert_eq!(fsm.context().exits, vec!["Root", "Menu", "Root"]);
+        async fn enter_retry(&self) -> RetryPolicy {
+            RetryPolicy {
+                attempts: 2,
+                backoff: Duration::from_millis(1),
+            }
+        }
     }
 
     #[tokio::test]
-    async fn test_unique_transitions_only() {
-        let mut fsm = create_test_fsm();
+    async fn test_enter_retry() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Root,
+                FlakyState {
+                    failures: 2,
+                    attempts: 0,
+                },
+            )
+            .build();
         fsm.init(TestState::Root).await.unwrap();
-
-        // Perform the same transition multiple times
-        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu
-        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root
-        fsm.process_event(&TestEvent::Enter).await.unwrap(); // Root -> Menu (again)
-        fsm.process_event(&TestEvent::Back).await.unwrap(); // Menu -> Root (again)
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(
+            fsm.context().entries,
+            vec!["attempt 1", "attempt 2", "attempt 3"]
+        );
+
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(
+                TestState::Root,
+                FlakyState {
+                    failures: 3,
+                    attempts: 0,
+                },
+            )
+            .build();
+        let result = fsm.init(TestState::Root).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::StateInvalid(TestState::Root, _))
+        ));
+        assert_eq!(fsm.context().entries.len(), 3);
     }
 
-    // Test concurrent access (if the FSM needs to be thread-safe)
-    #[tokio::test]
-    async fn test_context_modification() {
-        let mut fsm = create_test_fsm();
-        fsm.init(TestState::Menu).await.unwrap();
-
-        // Test that context modifications persist across events
-        fsm.context_mut().value = 42;
+    // Never finishes entering, so a transition into it can only be cancelled
+    struct HangingEnterState;
 
-        fsm.process_event(&TestEvent::Up).await.unwrap();
-        assert_eq!(fsm.context().value, 43); // 42 + 1
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for HangingEnterState {
+        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
+            std::future::pending().await
+        }
 
-        fsm.process_event(&TestEvent::Down).await.unwrap();
-        assert_eq!(fsm.context().value, 42); // 43 - 1
+        async fn on_exit(&mut self, _context: &mut TestContext) {}
     }
 
     #[tokio::test]
-    async fn test_error_propagation() {
-        let mut fsm = create_test_fsm();
+    async fn test_cancelled_transition_is_inconsistent() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, HangingEnterState)
+            .build();
         fsm.init(TestState::Root).await.unwrap();
 
-        // Test that errors from states are properly propagated
-        let result = fsm.process_event(&TestEvent::Timeout).await;
-
-        match result {
-            Err(FsmError::InvalidEvent(state, msg)) => {
-                assert_eq!(state, TestState::Root);
-                assert!(msg.contains("Root: Unhandled event"));
-            }
-            _ => panic!("Expected InvalidEvent error"),
-        }
+        // Root is exited, then the future is dropped while entering Menu
+        let result = tokio::time::timeout(
+            Duration::from_millis(10),
+            fsm.process_event(&TestEvent::Enter),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(fsm.context().exits, vec!["Root"]);
 
-        // FSM should still be in a valid state after error
+        let result = fsm.process_event(&TestEvent::Enter).await;
+        assert!(matches!(
+            result,
+            Err(FsmError::InconsistentState(TestState::Menu))
+        ));
+        assert!(matches!(
+            fsm.process_timeout().await,
+            Err(FsmError::InconsistentState(TestState::Menu))
+        ));
+
+        // Root was already exited, so it is only entered again
+        fsm.reset().await.unwrap();
         assert_eq!(fsm.current_state(), Some(TestState::Root));
+        assert_eq!(fsm.context().exits, vec!["Root"]);
     }
 
-    // Test with a more complex state that uses Arc<Mutex<>> for shared state
-    #[derive(Debug)]
-    struct SharedContext {
-        pub counter: Arc<Mutex<i32>>,
-        pub log: Arc<Mutex<Vec<String>>>,
-    }
+    #[tokio::test]
+    async fn test_init_after_cancelled_transition() {
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, HangingEnterState)
+            .state(TestState::Settings, SettingsState)
+            .build();
+        fsm.init(TestState::Root).await.unwrap();
 
-    impl SharedContext {
-        fn new() -> Self {
-            Self {
-                counter: Arc::new(Mutex::new(0)),
-                log: Arc::new(Mutex::new(Vec::new())),
-            }
-        }
+        let result = tokio::time::timeout(
+            Duration::from_millis(10),
+            fsm.process_event(&TestEvent::Enter),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(fsm.context().exits, vec!["Root"]);
+
+        // init enters Settings without exiting Root a second time
+        fsm.init(TestState::Settings).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
+        assert_eq!(fsm.context().exits, vec!["Root"]);
+        assert_eq!(
+            fsm.context().entries.last().map(String::as_str),
+            Some("Settings")
+        );
     }
 
-    struct SharedState;
+    // Redirects transitions out of it to Display once value is positive,
+    // and refuses to exit while value is negative
+    struct CheckedExitState;
 
     #[async_trait]
-    impl Stateful<TestState, SharedContext, TestEvent> for SharedState {
-        async fn on_enter(&mut self, context: &mut SharedContext) -> Response<TestState> {
-            let mut log = context.log.lock().unwrap();
-            log.push("SharedState entered".to_string());
-            Response::Handled
-        }
-
+    impl Stateful<TestState, TestContext, TestEvent> for CheckedExitState {
         async fn on_event(
             &mut self,
             event: &TestEvent,
-            context: &mut SharedContext,
+            context: &mut TestContext,
         ) -> Response<TestState> {
             match event {
                 TestEvent::Up => {
-                    let mut counter = context.counter.lock().unwrap();
-                    *counter += 1;
+                    context.value += 1;
                     Response::Handled
                 }
-                _ => Response::Super,
+                TestEvent::Down => {
+                    context.value -= 1;
+                    Response::Handled
+                }
+                _ => Response::Transition(TestState::Settings),
             }
         }
 
-        async fn on_exit(&mut self, context: &mut SharedContext) {
-            let mut log = context.log.lock().unwrap();
-            log.push("SharedState exited".to_string());
+        async fn on_exit_checked(&mut self, context: &mut TestContext) -> Response<TestState> {
+            context.exits.push("Menu".to_string());
+            match context.value {
+                v if v < 0 => Response::Error("still busy".to_string()),
+                v if v > 0 => Response::Transition(TestState::Display),
+                _ => Response::Handled,
+            }
         }
     }
 
-    #[tokio::test]
-    async fn test_shared_context() {
-        let context = SharedContext::new();
-        let counter_clone = Arc::clone(&context.counter);
-        let log_clone = Arc::clone(&context.log);
-
-        let mut fsm = StateMachineBuilder::new(context)
-            .state(TestState::Root, SharedState)
-            .build();
-
-        fsm.init(TestState::Root).await.unwrap();
-
-        // Test that shared state works
-        fsm.process_event(&TestEvent::Up).await.unwrap();
+    // State that refuses every attempt to enter it
+    struct LockedState;
 
-        assert_eq!(*counter_clone.lock().unwrap(), 1);
+    #[async_trait]
+    impl Stateful<TestState, TestContext, TestEvent> for LockedState {
+        async fn on_event(
+            &mut self,
+            _event: &TestEvent,
+            _context: &mut TestContext,
+        ) -> Response<TestState> {
+            Response::Handled
+        }
 
-        let log = log_clone.lock().unwrap();
-        assert!(log.contains(&"SharedState entered".to_string()));
+        async fn can_enter(&self, _context: &TestContext) -> bool {
+            false
+        }
     }
 
-    // Benchmark-style test for performance
     #[tokio::test]
-    async fn test_performance() {
-        let mut fsm = create_test_fsm();
+    async fn test_on_exit_redirect_rejected() {
+        // Menu is exited and redirects to Display, which refuses entry, so
+        // no state is left active
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, CheckedExitState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, LockedState)
+            .build();
         fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
 
-        let start = std::time::Instant::now();
-
-        // Process many events
-        for _ in 0..1000 {
-            fsm.process_event(&TestEvent::Up).await.unwrap();
-            fsm.process_event(&TestEvent::Down).await.unwrap();
-        }
-
-        let duration = start.elapsed();
-        println!("Processed 2000 events in {:?}", duration);
+        let err = fsm.process_event(&TestEvent::Select).await.unwrap_err();
+        assert!(matches!(err, FsmError::GuardRejected(TestState::Display)));
+        assert_eq!(fsm.current_state(), None);
+        assert!(matches!(
+            fsm.process_event(&TestEvent::Select).await,
+            Err(FsmError::InconsistentState(TestState::Settings))
+        ));
 
-        // Should still be in correct state
+        // Resetting enters Menu again without exiting it a second time
+        fsm.reset().await.unwrap();
         assert_eq!(fsm.current_state(), Some(TestState::Menu));
-        assert_eq!(fsm.context().value, 0); // Up and Down should cancel out
-    }
+        assert_eq!(fsm.context().exits, vec!["Menu"]);
 
-    // Test edge case: state that returns Error response
-    struct ErrorState;
+        // With Root above Menu, the machine falls back to Root
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Root, RootState)
+            .state(TestState::Menu, CheckedExitState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, LockedState)
+            .superstate_fn(superstate_fn)
+            .build();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
 
-    #[async_trait]
-    impl Stateful<TestState, TestContext, TestEvent> for ErrorState {
-        async fn on_enter(&mut self, _context: &mut TestContext) -> Response<TestState> {
-            Response::Error("ErrorState always fails on enter".to_string())
-        }
+        let err = fsm.process_event(&TestEvent::Select).await.unwrap_err();
+        assert!(matches!(err, FsmError::GuardRejected(TestState::Display)));
+        assert_eq!(fsm.current_state(), Some(TestState::Root));
+        fsm.process_event(&TestEvent::Enter).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().exits, vec!["Menu"]);
 
-        async fn on_event(
-            &mut self,
-            _event: &TestEvent,
-            _context: &mut TestContext,
-        ) -> Response<TestState> {
-            Response::Error("ErrorState always fails on event".to_string())
-        }
+        // A superstate vetoing its exit stays active below its exited child
+        let mut fsm = StateMachineBuilder::new(TestContext::new())
+            .state(TestState::Menu, CheckedExitState)
+            .state(TestState::Settings, SettingsState)
+            .state(TestState::Display, DisplayState)
+            .superstate_map(HashMap::from([(TestState::Display, TestState::Menu)]))
+            .build();
+        fsm.init(TestState::Display).await.unwrap();
+        fsm.process_event(&TestEvent::Down).await.unwrap();
 
-        async fn on_exit(&mut self, _context: &mut TestContext) {}
+        let err = fsm.process_event(&TestEvent::Select).await.unwrap_err();
+        assert!(matches!(
+            err,
+            FsmError::Handler(TestState::Menu, ErrorPhase::Exit, _)
+        ));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
+        assert_eq!(fsm.context().exits, vec!["Display", "Menu"]);
     }
 
     #[tokio::test]
-    async fn test_error_state() {
-        let context = TestContext::new();
-        let mut fsm = StateMachineBuilder::new(context)
-            .state(TestState::Root, ErrorState)
-            .build();
+    async fn test_on_exit_checked() {
+        let new_fsm = || {
+            StateMachineBuilder::new(TestContext::new())
+                .state(TestState::Menu, CheckedExitState)
+                .state(TestState::Settings, SettingsState)
+                .state(TestState::Display, DisplayState)
+                .build()
+        };
+
+        let mut fsm = new_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Settings));
 
-        // Test that error on enter is handled
-        let result = fsm.init(TestState::Root).await;
-        assert!(result.is_err());
+        // Exit redirects the transition, and Menu is exited only once
+        let mut fsm = new_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Up).await.unwrap();
+        fsm.process_event(&TestEvent::Select).await.unwrap();
+        assert_eq!(fsm.current_state(), Some(TestState::Display));
+        assert_eq!(fsm.context().exits, vec!["Menu"]);
+        assert!(!fsm.context().entries.contains(&"Settings".to_string()));
 
-        if let Err(FsmError::StateInvalid(state, msg)) = result {
-            assert_eq!(state, TestState::Root);
-            assert!(msg.contains("ErrorState always fails on enter"));
-        }
+        // Exit vetoes the transition
+        let mut fsm = new_fsm();
+        fsm.init(TestState::Menu).await.unwrap();
+        fsm.process_event(&TestEvent::Down).await.unwrap();
+        let err = fsm.process_event(&TestEvent::Select).await.unwrap_err();
+        assert!(matches!(
+            err,
+            FsmError::Handler(TestState::Menu, ErrorPhase::Exit, _)
+        ));
+        assert_eq!(fsm.current_state(), Some(TestState::Menu));
     }
 }
@@ -0,0 +1,38 @@
+//! State enums that describe their own hierarchy
+
+/// A state type that knows every value and each value's superstate
+///
+/// Usually derived with `#[derive(FsmState)]` (enable the `derive` feature),
+/// marking variants with `#[superstate(Parent)]`. The superstate function can
+/// then be passed straight to the builder:
+///
+/// ```rust
+/// use async_hierarchical_fsm::{FsmState, StateMachineBuilder};
+///
+/// #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// enum State {
+///     Root,
+///     Menu,
+/// }
+///
+/// impl FsmState for State {
+///     const ALL: &'static [Self] = &[State::Root, State::Menu];
+///
+///     fn superstate(&self) -> Option<Self> {
+///         match self {
+///             State::Menu => Some(State::Root),
+///             State::Root => None,
+///         }
+///     }
+/// }
+///
+/// let builder = StateMachineBuilder::<State, (), ()>::new(()).superstate_fn(State::superstate);
+/// # let _ = builder;
+/// ```
+pub trait FsmState: Sized + 'static {
+    /// Every state, in declaration order
+    const ALL: &'static [Self];
+
+    /// Get the superstate of this state, or `None` if it has none
+    fn superstate(&self) -> Option<Self>;
+}
@@ -0,0 +1,69 @@
+//! Ordered, timestamped record of every transition taken
+
+use crate::fsm::Instant;
+
+/// Default cap on records kept by
+/// [`StateMachineBuilder::with_history_log`](crate::StateMachineBuilder::with_history_log)
+pub const DEFAULT_HISTORY_CAPACITY: usize = 1000;
+
+/// One transition in the history returned by
+/// [`StateMachine::history`](crate::StateMachine::history)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionRecord<S> {
+    /// The state transitioned from, or `None` for the initial transition
+    pub from: Option<S>,
+    /// The state transitioned to
+    pub to: S,
+    /// When the target state was entered, on Tokio's clock with the
+    /// `tokio-integration` feature and the system clock otherwise
+    pub at: Instant,
+    /// `Debug` output of the event that caused the transition, if any
+    pub event: Option<String>,
+}
+
+/// Bounded history of transitions, dropping the oldest record when full
+///
+/// A capacity of zero disables recording.
+#[derive(Clone)]
+pub(crate) struct TransitionHistory<S> {
+    // The last `capacity` records are the history. Older ones are dropped in
+    // batches once twice that many are stored, so recording stays cheap and
+    // the history is always one slice.
+    records: Vec<TransitionRecord<S>>,
+    capacity: usize,
+    // Debug output of the event being dispatched, attached to new records
+    pub(crate) current_event: Option<String>,
+}
+
+impl<S> TransitionHistory<S> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            records: Vec::new(),
+            capacity,
+            current_event: None,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub(crate) fn record(&mut self, from: Option<S>, to: S) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity.saturating_mul(2) {
+            self.records.drain(..self.capacity);
+        }
+        self.records.push(TransitionRecord {
+            from,
+            to,
+            at: Instant::now(),
+            event: self.current_event.clone(),
+        });
+    }
+
+    pub(crate) fn records(&self) -> &[TransitionRecord<S>] {
+        &self.records[self.records.len().saturating_sub(self.capacity)..]
+    }
+}
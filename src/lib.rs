@@ -10,6 +10,7 @@
 //! - ⏰ **Dynamic Timeouts**: Context-aware timeout management per state
 //! - 🛡️ **Type Safety**: Leverages Rust's type system for compile-time guarantees
 //! - 🧵 **Thread Safe**: Designed for concurrent environments
+//! - 🔍 **Tracing**: Optional `tracing` spans around lifecycle hooks (`tracing` feature)
 //!
 //! ## Quick Start
 //!
@@ -59,25 +60,95 @@
 
 // Use your original FSM implementation here - don't change it!
 mod builder;
+mod coverage;
+#[cfg(feature = "serde")]
+mod description;
+mod diagram;
+mod dot;
 mod error;
+mod fn_state;
 mod fsm;
+mod fsm_state;
+mod history;
+mod mermaid;
+mod metrics;
+mod middleware;
+mod parallel;
+mod plantuml;
+mod queue;
+mod registry;
+mod shared;
+mod snapshot;
+mod table;
+mod template;
+#[cfg(feature = "testing")]
+mod testing;
+mod trace;
 
 pub use async_trait::async_trait;
 pub use builder::StateMachineBuilder;
-pub use error::{FsmError, FsmResult};
-pub use fsm::{Response, StateMachine, Stateful};
+pub use coverage::Coverage;
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+pub use description::FsmDescription;
+pub use dot::generate_dot;
+pub use error::{ErrorPhase, FsmError, FsmResult};
+pub use fn_state::{BoxFuture, FnState, fn_state};
+pub use fsm::{
+    CloneableStateful, Response, ResponseKind, RetryPolicy, StateMachine, Stateful,
+    TransitionOutcome,
+};
+pub use fsm_state::FsmState;
+pub use history::{DEFAULT_HISTORY_CAPACITY, TransitionRecord};
+pub use mermaid::{generate_mermaid, generate_mermaid_static};
+pub use metrics::Metrics;
+pub use middleware::{EventMiddleware, MiddlewareDecision};
+pub use parallel::{ParallelStateMachine, RegionId};
+pub use plantuml::{generate_plantuml, generate_plantuml_static};
+pub use queue::{EventPriority, EventQueue};
+pub use shared::SharedStateful;
+pub use snapshot::{FsmSnapshot, SnapshotDiff};
 pub use std::time::Duration;
+pub use template::StateMachineTemplate;
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub use testing::FsmTester;
+
+#[cfg(feature = "derive")]
+#[cfg_attr(docsrs, doc(cfg(feature = "derive")))]
+pub use async_hierarchical_fsm_derive::FsmState;
 
 #[cfg(feature = "tokio-integration")]
 #[cfg_attr(docsrs, doc(cfg(feature = "tokio-integration")))]
 /// Tokio-specific timeout utilities
+#[allow(clippy::mixed_attributes_style)]
 pub mod tokio_utils {
     //! Tokio utilities for timeout management and async operations
 
     use crate::{FsmError, StateMachine};
     use std::fmt::Debug;
     use std::hash::Hash;
-    use tokio::time::{Duration, timeout};
+    use std::sync::Arc;
+    use tokio::runtime::{Builder, Handle};
+    use tokio::sync::{Mutex, mpsc, oneshot};
+    use tokio::time::{Duration, Instant, timeout, timeout_at};
+
+    /// Context shared between a state machine and the tasks its handlers spawn
+    ///
+    /// Handlers receive `&mut SharedContext<CTX>`, so they can clone the `Arc`
+    /// into a task started in `on_enter` that keeps working on the context
+    /// after the transition completes. Handlers lock it like any other
+    /// [`tokio::sync::Mutex`]; holding the lock across an `.await` blocks those
+    /// tasks until it is released.
+    pub type SharedContext<CTX> = Arc<Mutex<CTX>>;
+
+    /// State machine whose context is a [`SharedContext`]
+    pub type SharedStateMachine<S, CTX, E> = StateMachine<S, SharedContext<CTX>, E>;
+
+    /// Wrap a context for use as a [`SharedContext`]
+    pub fn shared_context<CTX>(context: CTX) -> SharedContext<CTX> {
+        Arc::new(Mutex::new(context))
+    }
 
     /// Process an event with a timeout
     pub async fn process_event_with_timeout<S, CTX, E>(
@@ -94,6 +165,209 @@ pub mod tokio_utils {
             .await
             .map_err(|_| FsmError::Timeout)?
     }
+
+    /// Process an event from synchronous code, blocking until it completes
+    ///
+    /// A fresh current-thread runtime drives
+    /// [`StateMachine::process_event`] to completion, so this can be used from
+    /// callbacks that cannot be made async.
+    ///
+    /// Blocking on a runtime from inside another async runtime would panic,
+    /// so that case is detected up front and reported as an error instead.
+    ///
+    /// # Errors
+    /// Returns [`FsmError::InsideAsyncRuntime`] when called from within a
+    /// Tokio runtime, [`FsmError::Custom`] if the runtime cannot be created,
+    /// or any error returned by `process_event`.
+    pub fn block_on_event<S, CTX, E>(
+        fsm: &mut StateMachine<S, CTX, E>,
+        event: &E,
+    ) -> Result<(), FsmError<S>>
+    where
+        S: Hash + Eq + Clone + Send + Debug + 'static,
+        E: Debug + Send + 'static,
+        CTX: Send + 'static,
+    {
+        if Handle::try_current().is_ok() {
+            return Err(FsmError::InsideAsyncRuntime);
+        }
+
+        let runtime = Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .map_err(|e| FsmError::Custom(e.to_string()))?;
+        runtime.block_on(fsm.process_event(event))
+    }
+
+    /// Capacity of the command channel between an [`FsmHandle`] and its task
+    const ACTOR_CHANNEL_CAPACITY: usize = 32;
+
+    enum Command<S: Debug, E> {
+        Event(E, oneshot::Sender<Result<S, FsmError<S>>>),
+        CurrentState(oneshot::Sender<Option<S>>),
+    }
+
+    /// Handle to a state machine running on its own task, see [`spawn_actor`]
+    ///
+    /// Handles are cheap to clone; the task stops once every handle is dropped.
+    pub struct FsmHandle<S: Debug, E> {
+        commands: mpsc::Sender<Command<S, E>>,
+    }
+
+    impl<S: Debug, E> Clone for FsmHandle<S, E> {
+        fn clone(&self) -> Self {
+            Self {
+                commands: self.commands.clone(),
+            }
+        }
+    }
+
+    impl<S: Debug, E> FsmHandle<S, E> {
+        /// Process an event on the machine's task, returning the resulting state
+        ///
+        /// # Errors
+        /// Returns any error from [`StateMachine::process_event`], or
+        /// [`FsmError::ActorStopped`] if the task is no longer running.
+        pub async fn send(&self, event: E) -> Result<S, FsmError<S>> {
+            let (reply, response) = oneshot::channel();
+            self.commands
+                .send(Command::Event(event, reply))
+                .await
+                .map_err(|_| FsmError::ActorStopped)?;
+            response.await.map_err(|_| FsmError::ActorStopped)?
+        }
+
+        /// Get the machine's current state, or `None` if the task has stopped
+        pub async fn current_state(&self) -> Option<S> {
+            let (reply, response) = oneshot::channel();
+            self.commands
+                .send(Command::CurrentState(reply))
+                .await
+                .ok()?;
+            response.await.ok().flatten()
+        }
+    }
+
+    /// Move a state machine onto its own Tokio task, controlled through a handle
+    ///
+    /// The task owns the machine exclusively and processes commands one at a
+    /// time, so no locking is needed. The machine should already be
+    /// initialized with [`StateMachine::init`].
+    ///
+    /// If the current state has a [`get_timeout`](crate::Stateful::get_timeout)
+    /// that elapses before the next transition, the machine
+    /// [processes the timeout](StateMachine::process_timeout). If that does
+    /// not cause a transition, the [timeout event](StateMachine::current_timeout_event),
+    /// if any, is processed too. Errors from timeouts are discarded, as there
+    /// is no caller to report them to.
+    ///
+    /// The timeout is measured from when the current state was entered, or
+    /// from when it last timed out, so commands that do not change the state
+    /// do not postpone it.
+    pub fn spawn_actor<S, CTX, E>(mut fsm: StateMachine<S, CTX, E>) -> FsmHandle<S, E>
+    where
+        S: Hash + Eq + Clone + Send + Sync + Debug + 'static,
+        E: Debug + Send + Sync + 'static,
+        CTX: Send + Sync + 'static,
+    {
+        let (commands, mut receiver) = mpsc::channel(ACTOR_CHANNEL_CAPACITY);
+
+        tokio::spawn(async move {
+            // When the timeout started running, reset only when the state changes
+            let mut since = Instant::now();
+            loop {
+                let command = match fsm.get_current_timeout().await {
+                    Some(duration) => match timeout_at(since + duration, receiver.recv()).await {
+                        Ok(command) => command,
+                        Err(_) => {
+                            let _ = fsm.process_timeout().await;
+                            if !fsm.last_transitioned()
+                                && let Some(event) = fsm.current_timeout_event()
+                            {
+                                let _ = fsm.process_event(&event).await;
+                            }
+                            since = Instant::now();
+                            continue;
+                        }
+                    },
+                    None => receiver.recv().await,
+                };
+                let Some(command) = command else {
+                    break;
+                };
+
+                match command {
+                    Command::Event(event, reply) => {
+                        let before = fsm.current_state();
+                        let result = fsm.process_event(&event).await.and_then(|()| {
+                            fsm.current_state()
+                                .ok_or(FsmError::StateMachineNotInitialized)
+                        });
+                        if fsm.last_transitioned() || fsm.current_state() != before {
+                            since = Instant::now();
+                        }
+                        // The caller may have stopped waiting for the reply
+                        let _ = reply.send(result);
+                    }
+                    Command::CurrentState(reply) => {
+                        let _ = reply.send(fsm.current_state());
+                    }
+                }
+            }
+        });
+
+        FsmHandle { commands }
+    }
+
+    /// Drive a state machine from a channel of events, firing timeout events
+    ///
+    /// Waits for the next event on `events`, but only for as long as the
+    /// current state's [`get_timeout`](crate::Stateful::get_timeout) allows.
+    /// If the timeout elapses first, the machine
+    /// [processes the timeout](StateMachine::process_timeout), and unless that
+    /// causes a transition, the event produced by `timeout_event` is
+    /// processed too, or the machine's own
+    /// [timeout event](StateMachine::current_timeout_event) if it has one. The timeout is
+    /// recomputed after every event, since it may depend on the new state and
+    /// context.
+    ///
+    /// Returns `Ok(())` once the channel is closed, or the first error
+    /// returned by [`StateMachine::process_event`].
+    pub async fn run_with_timeouts<S, CTX, E, F>(
+        fsm: &mut StateMachine<S, CTX, E>,
+        mut events: mpsc::Receiver<E>,
+        mut timeout_event: F,
+    ) -> Result<(), FsmError<S>>
+    where
+        S: Hash + Eq + Clone + Send + Debug + 'static,
+        E: Debug + Send + 'static,
+        CTX: Send + 'static,
+        F: FnMut() -> E,
+    {
+        loop {
+            let next = match fsm.get_current_timeout().await {
+                Some(duration) => match timeout(duration, events.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        fsm.process_timeout().await?;
+                        if fsm.last_transitioned() {
+                            continue;
+                        }
+                        Some(
+                            fsm.current_timeout_event()
+                                .unwrap_or_else(&mut timeout_event),
+                        )
+                    }
+                },
+                None => events.recv().await,
+            };
+
+            match next {
+                Some(event) => fsm.process_event(&event).await?,
+                None => return Ok(()),
+            }
+        }
+    }
 }
 
 #[cfg(not(feature = "tokio-integration"))]
@@ -102,15 +376,51 @@ pub mod tokio_utils {
     // Tokio utilities are not available without the `tokio-integration` feature
 }
 
+#[cfg(feature = "async-std-integration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-std-integration")))]
+pub mod async_std_utils {
+    //! async-std utilities for timeout management, mirroring `tokio_utils`
+
+    use crate::{FsmError, StateMachine};
+    use async_std::future::timeout;
+    use std::fmt::Debug;
+    use std::hash::Hash;
+    use std::time::Duration;
+
+    /// Process an event with a timeout
+    pub async fn process_event_with_timeout<S, CTX, E>(
+        fsm: &mut StateMachine<S, CTX, E>,
+        event: &E,
+        timeout_duration: Duration,
+    ) -> Result<(), FsmError<S>>
+    where
+        S: Hash + Eq + Clone + Send + Debug + 'static,
+        E: Debug + Send + 'static,
+        CTX: Send + 'static,
+    {
+        timeout(timeout_duration, fsm.process_event(event))
+            .await
+            .map_err(|_| FsmError::Timeout)?
+    }
+}
+
 pub mod prelude {
     //! Prelude module for convenient imports
 
     pub use crate::{
-        Duration, FsmError, FsmResult, Response, StateMachine, StateMachineBuilder, Stateful,
-        async_trait,
+        BoxFuture, CloneableStateful, Coverage, Duration, ErrorPhase, EventMiddleware,
+        EventPriority, EventQueue, FnState, FsmError, FsmResult, FsmSnapshot, FsmState, Metrics,
+        MiddlewareDecision, ParallelStateMachine, RegionId, Response, ResponseKind, RetryPolicy,
+        SharedStateful, SnapshotDiff, StateMachine, StateMachineBuilder, StateMachineTemplate,
+        Stateful, TransitionOutcome, TransitionRecord, async_trait, fn_state,
     };
 
     #[cfg(feature = "tokio-integration")]
     #[cfg_attr(docsrs, doc(cfg(feature = "tokio-integration")))]
     pub use crate::tokio_utils::*;
+
+    // The tokio utilities take precedence when both integrations are enabled
+    #[cfg(all(feature = "async-std-integration", not(feature = "tokio-integration")))]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-std-integration")))]
+    pub use crate::async_std_utils::*;
 }
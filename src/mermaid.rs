@@ -0,0 +1,128 @@
+//! Mermaid diagram generation for state machines
+
+use crate::diagram::{Hierarchy, collect_edges, collect_hierarchy};
+use std::collections::HashSet;
+use std::fmt::{Debug, Write};
+use std::hash::Hash;
+
+/// Generate a Mermaid `stateDiagram-v2` diagram from a transition log.
+///
+/// States are nested inside their superstates as composite states, and the
+/// current state (if any) is styled with the `current` class.
+///
+/// # Arguments
+/// * `transition_log` - Unique `(from, to)` transitions to draw as edges.
+/// * `initial_state` - The state to draw the `[*]` start edge into, if any.
+/// * `current_state` - The state to highlight, if any.
+/// * `superstate_fn` - Function returning the superstate of a state.
+///
+/// # Returns
+/// The diagram source, starting with `stateDiagram-v2`.
+pub fn generate_mermaid<S>(
+    transition_log: &HashSet<(S, S)>,
+    initial_state: Option<&S>,
+    current_state: Option<&S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone,
+{
+    render(
+        transition_log,
+        current_state,
+        initial_state,
+        current_state,
+        superstate_fn,
+    )
+}
+
+/// Generate a Mermaid `stateDiagram-v2` diagram of the declared states,
+/// without transition edges.
+///
+/// Every state in `states` is drawn, nested inside its superstates, whether
+/// or not it was ever entered.
+///
+/// # Arguments
+/// * `states` - The states to draw.
+/// * `initial_state` - The state to draw the `[*]` start edge into, if any.
+/// * `current_state` - The state to highlight, if any.
+/// * `superstate_fn` - Function returning the superstate of a state.
+///
+/// # Returns
+/// The diagram source, starting with `stateDiagram-v2`.
+pub fn generate_mermaid_static<'a, S>(
+    states: impl IntoIterator<Item = &'a S>,
+    initial_state: Option<&'a S>,
+    current_state: Option<&'a S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone + 'a,
+{
+    render(
+        &HashSet::new(),
+        states.into_iter().chain(initial_state).chain(current_state),
+        initial_state,
+        current_state,
+        superstate_fn,
+    )
+}
+
+fn render<'a, S>(
+    transition_log: &HashSet<(S, S)>,
+    states: impl IntoIterator<Item = &'a S>,
+    initial_state: Option<&S>,
+    current_state: Option<&S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone + 'a,
+{
+    let children = collect_hierarchy(transition_log, states, superstate_fn, state_name);
+
+    let mut out = String::new();
+    out.push_str("stateDiagram-v2\n");
+
+    if let Some(initial) = initial_state {
+        let _ = writeln!(out, "    [*] --> {}", state_name(initial));
+    }
+
+    write_states(&mut out, &children, None, 1);
+
+    for (from, to) in collect_edges(transition_log, state_name) {
+        let _ = writeln!(out, "    {from} --> {to}");
+    }
+
+    if let Some(current) = current_state {
+        out.push_str("    classDef current fill:#adff2f\n");
+        let _ = writeln!(out, "    class {} current", state_name(current));
+    }
+
+    out
+}
+
+/// Write the states that share `parent`, recursing into composite states.
+fn write_states(out: &mut String, children: &Hierarchy, parent: Option<String>, depth: usize) {
+    let Some(states) = children.get(&parent) else {
+        return;
+    };
+    let indent = "    ".repeat(depth);
+
+    for name in states {
+        if children.contains_key(&Some(name.clone())) {
+            let _ = writeln!(out, "{indent}state {name} {{");
+            write_states(out, children, Some(name.clone()), depth + 1);
+            let _ = writeln!(out, "{indent}}}");
+        } else {
+            let _ = writeln!(out, "{indent}{name}");
+        }
+    }
+}
+
+/// Turn a state's `Debug` output into a Mermaid node label.
+fn state_name<S: Debug>(state: &S) -> String {
+    format!("{state:?}")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
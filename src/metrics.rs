@@ -0,0 +1,104 @@
+//! Per-state counters collected while the state machine runs
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Counters for events, transitions, and errors, keyed by state.
+///
+/// Collection is opt-in via
+/// [`StateMachineBuilder::collect_metrics`](crate::StateMachineBuilder::collect_metrics);
+/// otherwise every counter stays at zero.
+#[derive(Debug, Clone)]
+pub struct Metrics<S> {
+    events: HashMap<S, u64>,
+    transitions: HashMap<S, u64>,
+    errors: HashMap<S, u64>,
+    fallback_events: u64,
+    total_events: u64,
+    total_errors: u64,
+}
+
+impl<S: Hash + Eq + Clone> Metrics<S> {
+    /// Create an empty set of counters
+    pub fn new() -> Self {
+        Self {
+            events: HashMap::new(),
+            transitions: HashMap::new(),
+            errors: HashMap::new(),
+            fallback_events: 0,
+            total_events: 0,
+            total_errors: 0,
+        }
+    }
+
+    /// Get the number of events `state` handled without delegating or returning an error
+    pub fn events_handled(&self, state: &S) -> u64 {
+        self.events.get(state).copied().unwrap_or(0)
+    }
+
+    /// Get the number of events handled by the
+    /// [global handler](crate::StateMachineBuilder::global_handler) or the
+    /// [unhandled-event fallback](crate::StateMachineBuilder::on_unhandled)
+    ///
+    /// These are not counted in [`events_handled`](Self::events_handled) for
+    /// any state.
+    pub fn fallback_events_handled(&self) -> u64 {
+        self.fallback_events
+    }
+
+    /// Get the number of transitions out of `state`
+    pub fn transitions_from(&self, state: &S) -> u64 {
+        self.transitions.get(state).copied().unwrap_or(0)
+    }
+
+    /// Get the number of failed events produced by `state`
+    ///
+    /// An error from a state's handler counts against that state, which may
+    /// be a superstate the event was delegated to. Any other error counts
+    /// against the current state.
+    pub fn errors_in(&self, state: &S) -> u64 {
+        self.errors.get(state).copied().unwrap_or(0)
+    }
+
+    /// Get the total number of events dispatched, including queued events
+    pub fn total_events(&self) -> u64 {
+        self.total_events
+    }
+
+    /// Get the total number of failed events
+    pub fn total_errors(&self) -> u64 {
+        self.total_errors
+    }
+
+    /// Count an event dispatched to the state machine
+    pub(crate) fn record_dispatch(&mut self) {
+        self.total_events += 1;
+    }
+
+    /// Count an event handled by `state`
+    pub(crate) fn record_event(&mut self, state: &S) {
+        *self.events.entry(state.clone()).or_insert(0) += 1;
+    }
+
+    /// Count an event handled by the global handler or the fallback
+    pub(crate) fn record_fallback_event(&mut self) {
+        self.fallback_events += 1;
+    }
+
+    /// Count a transition out of `state`
+    pub(crate) fn record_transition(&mut self, state: &S) {
+        *self.transitions.entry(state.clone()).or_insert(0) += 1;
+    }
+
+    /// Count a failed event in `state`
+    pub(crate) fn record_error(&mut self, state: &S) {
+        self.total_errors += 1;
+        *self.errors.entry(state.clone()).or_insert(0) += 1;
+    }
+}
+
+impl<S: Hash + Eq + Clone> Default for Metrics<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
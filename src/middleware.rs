@@ -0,0 +1,26 @@
+//! Event middleware run before events reach the current state
+
+use async_trait::async_trait;
+
+/// Outcome of an [`EventMiddleware`] check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiddlewareDecision {
+    /// Let the event continue to the next middleware and then the state
+    Proceed,
+    /// Stop processing the event, with a reason
+    Reject(String),
+}
+
+#[async_trait]
+/// Trait for middleware that observes or vetoes events before `on_event`.
+pub trait EventMiddleware<CTX, E>: Send + Sync {
+    /// Called for every event before it is dispatched to the current state.
+    ///
+    /// # Arguments
+    /// * `event` - Reference to the event about to be processed.
+    /// * `context` - Mutable reference to the shared context.
+    ///
+    /// # Returns
+    /// A [`MiddlewareDecision`] indicating whether the event may proceed.
+    async fn before(&mut self, event: &E, context: &mut CTX) -> MiddlewareDecision;
+}
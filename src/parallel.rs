@@ -0,0 +1,116 @@
+//! Orthogonal regions: several state machines driven by the same events
+
+use crate::{FsmError, StateMachine};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+/// Identifies a region of a [`ParallelStateMachine`], in the order regions were added
+pub type RegionId = usize;
+
+/// Independent state machines ("regions") that share one context and
+/// receive every event
+///
+/// Each region is an ordinary [`StateMachine`] whose context is the shared
+/// `Arc<Mutex<CTX>>` returned by [`context`](Self::context), so build regions
+/// with `StateMachineBuilder::new(parallel.context())`. Handlers should hold
+/// the lock only briefly and never across an `.await`.
+///
+/// ```rust
+/// use async_hierarchical_fsm::{ParallelStateMachine, StateMachineBuilder, fn_state};
+/// # use async_hierarchical_fsm::Response;
+///
+/// # tokio_test::block_on(async {
+/// let mut parallel = ParallelStateMachine::<&str, u32, ()>::new(0);
+/// let mut power = StateMachineBuilder::new(parallel.context())
+///     .state(
+///         "on",
+///         fn_state(
+///             |_| Box::pin(async { Response::Handled }),
+///             |_, _| Box::pin(async { Response::Handled }),
+///             |_| Box::pin(async {}),
+///         ),
+///     )
+///     .build();
+/// power.init("on").await.unwrap();
+/// let region = parallel.add_region(power);
+///
+/// assert_eq!(parallel.current_states(), vec![(region, Some("on"))]);
+/// # });
+/// ```
+pub struct ParallelStateMachine<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Send + 'static,
+{
+    context: Arc<Mutex<CTX>>,
+    regions: Vec<StateMachine<S, Arc<Mutex<CTX>>, E>>,
+}
+
+impl<S, CTX, E> ParallelStateMachine<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Send + 'static,
+{
+    /// Create a parallel state machine with no regions, sharing `context`
+    pub fn new(context: CTX) -> Self {
+        Self {
+            context: Arc::new(Mutex::new(context)),
+            regions: Vec::new(),
+        }
+    }
+
+    /// Get a handle to the shared context, to build regions with
+    pub fn context(&self) -> Arc<Mutex<CTX>> {
+        Arc::clone(&self.context)
+    }
+
+    /// Add a region, returning its id
+    ///
+    /// The region should have been built with [`context`](Self::context) so
+    /// that it shares state with the other regions. It may be initialized
+    /// before being added, or later through [`region_mut`](Self::region_mut).
+    pub fn add_region(&mut self, region: StateMachine<S, Arc<Mutex<CTX>>, E>) -> RegionId {
+        self.regions.push(region);
+        self.regions.len() - 1
+    }
+
+    /// Get a region by id
+    pub fn region(&self, id: RegionId) -> Option<&StateMachine<S, Arc<Mutex<CTX>>, E>> {
+        self.regions.get(id)
+    }
+
+    /// Get a region mutably by id
+    pub fn region_mut(&mut self, id: RegionId) -> Option<&mut StateMachine<S, Arc<Mutex<CTX>>, E>> {
+        self.regions.get_mut(id)
+    }
+
+    /// Get the number of regions
+    pub fn region_count(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Get the current state of every region
+    pub fn current_states(&self) -> Vec<(RegionId, Option<S>)> {
+        self.regions
+            .iter()
+            .enumerate()
+            .map(|(id, region)| (id, region.current_state()))
+            .collect()
+    }
+
+    /// Dispatch an event to every region in turn, collecting each region's result
+    ///
+    /// A failure in one region does not stop the event reaching the others.
+    /// Regions with no handler for the event report
+    /// [`FsmError::InvalidEvent`] as usual.
+    pub async fn process_event(&mut self, event: &E) -> Vec<(RegionId, Result<(), FsmError<S>>)> {
+        let mut results = Vec::with_capacity(self.regions.len());
+        for (id, region) in self.regions.iter_mut().enumerate() {
+            results.push((id, region.process_event(event).await));
+        }
+        results
+    }
+}
@@ -0,0 +1,123 @@
+//! PlantUML diagram generation for state machines
+
+use crate::diagram::{Hierarchy, collect_edges, collect_hierarchy};
+use std::collections::HashSet;
+use std::fmt::{Debug, Write};
+use std::hash::Hash;
+
+/// Generate a PlantUML state diagram from a transition log.
+///
+/// States are nested inside their superstates as composite states, and the
+/// current state (if any) is tagged with the `<<Current>>` stereotype.
+///
+/// # Arguments
+/// * `transition_log` - Unique `(from, to)` transitions to draw as edges.
+/// * `current_state` - The state to highlight, if any.
+/// * `superstate_fn` - Function returning the superstate of a state.
+///
+/// # Returns
+/// The diagram source, starting with `@startuml` and ending with `@enduml`.
+pub fn generate_plantuml<S>(
+    transition_log: &HashSet<(S, S)>,
+    current_state: Option<&S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone,
+{
+    render(transition_log, current_state, current_state, superstate_fn)
+}
+
+/// Generate a PlantUML state diagram of the declared states, without edges.
+///
+/// Every state in `states` is drawn, nested inside its superstates, whether
+/// or not it was ever entered.
+///
+/// # Arguments
+/// * `states` - The states to draw.
+/// * `current_state` - The state to highlight, if any.
+/// * `superstate_fn` - Function returning the superstate of a state.
+///
+/// # Returns
+/// The diagram source, starting with `@startuml` and ending with `@enduml`.
+pub fn generate_plantuml_static<'a, S>(
+    states: impl IntoIterator<Item = &'a S>,
+    current_state: Option<&'a S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone + 'a,
+{
+    render(
+        &HashSet::new(),
+        states.into_iter().chain(current_state),
+        current_state,
+        superstate_fn,
+    )
+}
+
+fn render<'a, S>(
+    transition_log: &HashSet<(S, S)>,
+    states: impl IntoIterator<Item = &'a S>,
+    current_state: Option<&S>,
+    superstate_fn: &dyn Fn(&S) -> Option<S>,
+) -> String
+where
+    S: Debug + Eq + Hash + Clone + 'a,
+{
+    let children = collect_hierarchy(transition_log, states, superstate_fn, state_name);
+    let current_name = current_state.map(state_name);
+
+    let mut out = String::new();
+    out.push_str("@startuml\n");
+    out.push_str("skinparam state {\n");
+    out.push_str("  BackgroundColor<<Current>> LightGreen\n");
+    out.push_str("}\n");
+
+    write_states(&mut out, &children, None, current_name.as_deref(), 0);
+
+    for (from, to) in collect_edges(transition_log, state_name) {
+        let _ = writeln!(out, "{from} --> {to}");
+    }
+
+    out.push_str("@enduml\n");
+    out
+}
+
+/// Write the states that share `parent`, recursing into composite states.
+fn write_states(
+    out: &mut String,
+    children: &Hierarchy,
+    parent: Option<String>,
+    current: Option<&str>,
+    depth: usize,
+) {
+    let Some(states) = children.get(&parent) else {
+        return;
+    };
+    let indent = "  ".repeat(depth);
+
+    for name in states {
+        let stereotype = if current == Some(name.as_str()) {
+            " <<Current>>"
+        } else {
+            ""
+        };
+
+        if children.contains_key(&Some(name.clone())) {
+            let _ = writeln!(out, "{indent}state {name}{stereotype} {{");
+            write_states(out, children, Some(name.clone()), current, depth + 1);
+            let _ = writeln!(out, "{indent}}}");
+        } else {
+            let _ = writeln!(out, "{indent}state {name}{stereotype}");
+        }
+    }
+}
+
+/// Turn a state's `Debug` output into a valid PlantUML identifier.
+fn state_name<S: Debug>(state: &S) -> String {
+    format!("{state:?}")
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
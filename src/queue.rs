@@ -0,0 +1,172 @@
+//! Event queue that lets handlers post follow-up events
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Priority of an event posted with [`EventQueue::push_prioritized`].
+///
+/// Higher priorities are processed first. Events posted with plain
+/// [`EventQueue::push`] have priority 0.
+pub trait EventPriority {
+    /// Get the priority of this event
+    fn priority(&self) -> u8;
+}
+
+/// A shared queue of events waiting to be processed by a state machine.
+///
+/// The queue is a cheap, cloneable handle. Store a clone in the context so
+/// handlers can [`push`](Self::push) follow-up events; the state machine
+/// drains the queue after each event it processes. Events are taken highest
+/// priority first, and in the order they were pushed within a priority.
+pub struct EventQueue<E> {
+    inner: Arc<Mutex<Inner<E>>>,
+}
+
+struct Inner<E> {
+    events: BinaryHeap<Queued<E>>,
+    // Incremented per push, so equal priorities come out first-in first-out
+    next_seq: u64,
+}
+
+struct Queued<E> {
+    priority: u8,
+    seq: u64,
+    event: E,
+}
+
+impl<E: Clone> Clone for Queued<E> {
+    fn clone(&self) -> Self {
+        Self {
+            priority: self.priority,
+            seq: self.seq,
+            event: self.event.clone(),
+        }
+    }
+}
+
+impl<E> Ord for Queued<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<E> PartialOrd for Queued<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> PartialEq for Queued<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<E> Eq for Queued<E> {}
+
+impl<E> EventQueue<E> {
+    /// Create a new, empty event queue
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                events: BinaryHeap::new(),
+                next_seq: 0,
+            })),
+        }
+    }
+
+    /// Add an event with the default priority of 0
+    pub fn push(&self, event: E) {
+        self.push_with_priority(event, 0);
+    }
+
+    /// Add an event with the priority reported by [`EventPriority`]
+    pub fn push_prioritized(&self, event: E)
+    where
+        E: EventPriority,
+    {
+        let priority = event.priority();
+        self.push_with_priority(event, priority);
+    }
+
+    /// Add an event with an explicit priority
+    pub fn push_with_priority(&self, event: E, priority: u8) {
+        let mut inner = self.lock();
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.events.push(Queued {
+            priority,
+            seq,
+            event,
+        });
+    }
+
+    /// Get the number of queued events
+    pub fn len(&self) -> usize {
+        self.lock().events.len()
+    }
+
+    /// Check whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.lock().events.is_empty()
+    }
+
+    /// Remove all queued events
+    pub fn clear(&self) {
+        self.lock().events.clear();
+    }
+
+    /// Create a separate queue holding copies of the queued events
+    ///
+    /// Unlike [`Clone::clone`], which returns another handle to this queue,
+    /// events pushed to the copy are not seen here, and vice versa.
+    pub(crate) fn deep_copy(&self) -> Self
+    where
+        E: Clone,
+    {
+        let inner = self.lock();
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                events: inner.events.iter().cloned().collect(),
+                next_seq: inner.next_seq,
+            })),
+        }
+    }
+
+    /// Take the highest priority event
+    pub(crate) fn pop(&self) -> Option<E> {
+        self.lock().events.pop().map(|queued| queued.event)
+    }
+
+    /// Get a mark to pass to [`discard_since`](Self::discard_since)
+    pub(crate) fn mark(&self) -> u64 {
+        self.lock().next_seq
+    }
+
+    /// Remove every event pushed after `mark` was taken
+    pub(crate) fn discard_since(&self, mark: u64) {
+        self.lock().events.retain(|queued| queued.seq < mark);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<E>> {
+        // A panic while holding the lock can't leave the heap inconsistent
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<E> Clone for EventQueue<E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<E> Default for EventQueue<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,172 @@
+//! Registered state handlers, including keys aliased to another state's handler
+
+use crate::{CloneableStateful, SharedStateful, Stateful};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+
+type Handler<S, CTX, E> = Box<dyn Stateful<S, CTX, E> + Send + Sync>;
+
+type DynState<S, CTX, E> = dyn Stateful<S, CTX, E> + Send + Sync;
+
+/// A registered handler, remembering whether it can be cloned or shared
+pub(crate) enum StateEntry<S, CTX, E> {
+    Plain(Handler<S, CTX, E>),
+    Cloneable(Box<dyn CloneableStateful<S, CTX, E> + Send + Sync>),
+    Shared(Arc<dyn SharedStateful<S, CTX, E> + Send + Sync>),
+}
+
+impl<S, CTX, E> StateEntry<S, CTX, E>
+where
+    S: Hash + Eq + Clone + 'static,
+    CTX: 'static,
+    E: Debug + 'static,
+{
+    pub(crate) fn handler(&self) -> &DynState<S, CTX, E> {
+        match self {
+            StateEntry::Plain(handler) => handler.as_ref(),
+            StateEntry::Cloneable(handler) => handler.as_ref(),
+            StateEntry::Shared(handler) => handler,
+        }
+    }
+
+    fn handler_mut(&mut self) -> &mut DynState<S, CTX, E> {
+        match self {
+            StateEntry::Plain(handler) => handler.as_mut(),
+            StateEntry::Cloneable(handler) => handler.as_mut(),
+            StateEntry::Shared(handler) => handler,
+        }
+    }
+
+    pub(crate) fn into_handler(self) -> Handler<S, CTX, E> {
+        match self {
+            StateEntry::Plain(handler) => handler,
+            StateEntry::Cloneable(handler) => handler,
+            StateEntry::Shared(handler) => Box::new(handler),
+        }
+    }
+
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        match self {
+            StateEntry::Plain(_) => None,
+            StateEntry::Cloneable(handler) => Some(StateEntry::Cloneable(handler.clone_box())),
+            StateEntry::Shared(handler) => Some(StateEntry::Shared(Arc::clone(handler))),
+        }
+    }
+}
+
+/// State handlers keyed by state, where an alias key shares the handler of
+/// the state it points to.
+///
+/// Mirrors the `HashMap` methods the state machine uses, so an alias behaves
+/// exactly like a separately registered state.
+pub(crate) struct StateRegistry<S, CTX, E> {
+    handlers: HashMap<S, StateEntry<S, CTX, E>>,
+    // Alias key -> key of the state whose handler it shares
+    aliases: HashMap<S, S>,
+}
+
+impl<S, CTX, E> StateRegistry<S, CTX, E>
+where
+    S: Hash + Eq + Clone + 'static,
+    CTX: 'static,
+    E: Debug + 'static,
+{
+    pub(crate) fn new(handlers: HashMap<S, StateEntry<S, CTX, E>>) -> Self {
+        Self {
+            handlers,
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// Clone every handler, or `None` if any was not registered as cloneable
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        let handlers = self
+            .handlers
+            .iter()
+            .map(|(state, entry)| Some((state.clone(), entry.try_clone()?)))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            handlers,
+            aliases: self.aliases.clone(),
+        })
+    }
+
+    /// Make `alias` resolve to the handler registered for `existing`
+    ///
+    /// Any handler registered directly under `alias` is dropped.
+    pub(crate) fn alias(&mut self, existing: S, alias: S) {
+        self.handlers.remove(&alias);
+        self.aliases.insert(alias, existing);
+    }
+
+    /// Get every alias of `state`
+    pub(crate) fn aliases_of<'a>(&'a self, state: &'a S) -> impl Iterator<Item = &'a S> {
+        self.aliases
+            .iter()
+            .filter(move |(_, existing)| *existing == state)
+            .map(|(alias, _)| alias)
+    }
+
+    fn resolve<'a>(&'a self, state: &'a S) -> &'a S {
+        self.aliases.get(state).unwrap_or(state)
+    }
+
+    pub(crate) fn get(&self, state: &S) -> Option<&DynState<S, CTX, E>> {
+        self.handlers
+            .get(self.resolve(state))
+            .map(StateEntry::handler)
+    }
+
+    pub(crate) fn get_mut(&mut self, state: &S) -> Option<&mut DynState<S, CTX, E>> {
+        let key = self.aliases.get(state).unwrap_or(state);
+        self.handlers.get_mut(key).map(StateEntry::handler_mut)
+    }
+
+    pub(crate) fn contains_key(&self, state: &S) -> bool {
+        self.handlers.contains_key(self.resolve(state))
+    }
+
+    /// Register `handler` for `state`, returning the handler it replaced
+    ///
+    /// If `state` was an alias it stops being one; the handler it shared is
+    /// left with its original state and is not returned.
+    pub(crate) fn insert(
+        &mut self,
+        state: S,
+        handler: Handler<S, CTX, E>,
+    ) -> Option<Handler<S, CTX, E>> {
+        self.aliases.remove(&state);
+        self.handlers
+            .insert(state, StateEntry::Plain(handler))
+            .map(StateEntry::into_handler)
+    }
+
+    /// Remove `state`, returning whether it was registered
+    ///
+    /// Removing a state also removes any aliases of it.
+    pub(crate) fn remove(&mut self, state: &S) -> bool {
+        if self.aliases.remove(state).is_some() {
+            return true;
+        }
+        if self.handlers.remove(state).is_none() {
+            return false;
+        }
+        self.aliases.retain(|_, existing| existing != state);
+        true
+    }
+
+    /// Get every handler once, skipping aliases
+    pub(crate) fn values_mut(&mut self) -> impl Iterator<Item = &mut DynState<S, CTX, E>> {
+        self.handlers.values_mut().map(StateEntry::handler_mut)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.handlers.len() + self.aliases.len()
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &S> {
+        self.handlers.keys().chain(self.aliases.keys())
+    }
+}
@@ -0,0 +1,173 @@
+//! State handlers shared between state machines
+
+use crate::{Response, Stateful};
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[async_trait]
+/// Trait for state handlers shared between several state machines.
+///
+/// Like [`Stateful`], but every method takes `&self`, so one handler can be
+/// registered with
+/// [`StateMachineBuilder::shared_state`](crate::StateMachineBuilder::shared_state)
+/// behind an [`Arc`] and used by every machine built from clones of that
+/// builder. Machines call it concurrently and without any locking, so the
+/// handler must be stateless or synchronize its own data, for example with
+/// atomics or a mutex.
+pub trait SharedStateful<S: Hash + Eq + Clone, CTX, E: Debug>: Send + Sync {
+    /// Called when entering the state, see [`Stateful::on_enter`].
+    async fn on_enter(&self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        let _ = context;
+        Response::Handled
+    }
+
+    /// Called when an event occurs in the state, see [`Stateful::on_event`].
+    ///
+    /// The default implementation returns [`Response::Super`].
+    //
+    // Written out as `#[async_trait]` would expand it, so the default body
+    // does not require `E: Sync`, as for `Stateful::on_event`.
+    fn on_event<'life0, 'life1, 'life2, 'async_trait>(
+        &'life0 self,
+        event: &'life1 E,
+        context: &'life2 mut CTX,
+    ) -> Pin<Box<dyn Future<Output = Response<S>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        Self: 'async_trait,
+    {
+        let _ = (event, context);
+        Box::pin(async { Response::Super })
+    }
+
+    /// Called when exiting the state, see [`Stateful::on_exit`].
+    async fn on_exit(&self, context: &mut CTX)
+    where
+        CTX: Send,
+    {
+        let _ = context;
+    }
+
+    /// The state's superstate, see [`Stateful::superstate`].
+    fn superstate(&self) -> Option<S> {
+        None
+    }
+
+    /// Guard checked before the state is entered, see [`Stateful::can_enter`].
+    async fn can_enter(&self, context: &CTX) -> bool {
+        let _ = context;
+        true
+    }
+
+    /// Optionally returns a timeout duration for the state, see
+    /// [`Stateful::get_timeout`].
+    async fn get_timeout(&self, context: &CTX) -> Option<Duration> {
+        let _ = context;
+        None
+    }
+
+    /// Optionally returns the event to inject on timeout, see
+    /// [`Stateful::timeout_event`].
+    fn timeout_event(&self) -> Option<E> {
+        None
+    }
+
+    /// Called when the state's timeout elapses, see [`Stateful::on_timeout`].
+    async fn on_timeout(&self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        let _ = context;
+        Response::Handled
+    }
+}
+
+#[async_trait]
+impl<S, CTX, E, T> Stateful<S, CTX, E> for Arc<T>
+where
+    S: Hash + Eq + Clone,
+    E: Debug,
+    T: SharedStateful<S, CTX, E> + ?Sized,
+{
+    async fn on_enter(&mut self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        self.as_ref().on_enter(context).await
+    }
+
+    // Written out to hand back the shared handler's future, without
+    // requiring `E: Sync`
+    fn on_event<'life0, 'life1, 'life2, 'async_trait>(
+        &'life0 mut self,
+        event: &'life1 E,
+        context: &'life2 mut CTX,
+    ) -> Pin<Box<dyn Future<Output = Response<S>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        Self: 'async_trait,
+    {
+        let shared: &'life0 Self = self;
+        shared.as_ref().on_event(event, context)
+    }
+
+    async fn on_exit(&mut self, context: &mut CTX)
+    where
+        CTX: Send,
+    {
+        self.as_ref().on_exit(context).await;
+    }
+
+    fn superstate(&self) -> Option<S> {
+        self.as_ref().superstate()
+    }
+
+    // Written out to hand back the shared handler's future, without
+    // requiring `CTX: Sync`
+    fn can_enter<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        context: &'life1 CTX,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.as_ref().can_enter(context)
+    }
+
+    fn get_timeout<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        context: &'life1 CTX,
+    ) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.as_ref().get_timeout(context)
+    }
+
+    fn timeout_event(&self) -> Option<E> {
+        self.as_ref().timeout_event()
+    }
+
+    async fn on_timeout(&mut self, context: &mut CTX) -> Response<S>
+    where
+        CTX: Send,
+    {
+        self.as_ref().on_timeout(context).await
+    }
+}
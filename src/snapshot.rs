@@ -0,0 +1,62 @@
+//! Snapshots of where a state machine is, for persisting and resuming
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The current state, initial state, and transition log of a state machine.
+///
+/// Taken with [`StateMachine::snapshot`](crate::StateMachine::snapshot) and
+/// applied with [`StateMachine::restore_snapshot`](crate::StateMachine::restore_snapshot).
+/// With the `serde` feature enabled, snapshots can be serialized whenever `S` can.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FsmSnapshot<S: Hash + Eq> {
+    /// The active state, or `None` if the machine was not initialized
+    pub current_state: Option<S>,
+    /// The state passed to [`init`](crate::StateMachine::init)
+    pub initial_state: Option<S>,
+    /// The unique `(from, to)` transitions taken so far
+    pub transition_log: HashSet<(S, S)>,
+}
+
+impl<S: Hash + Eq + Clone> FsmSnapshot<S> {
+    /// Compare this snapshot against `other`
+    ///
+    /// Useful for checking a run against a reference run, e.g. a golden file.
+    pub fn diff(&self, other: &Self) -> SnapshotDiff<S> {
+        SnapshotDiff {
+            only_in_self: self
+                .transition_log
+                .difference(&other.transition_log)
+                .cloned()
+                .collect(),
+            only_in_other: other
+                .transition_log
+                .difference(&self.transition_log)
+                .cloned()
+                .collect(),
+            current_state_differs: self.current_state != other.current_state,
+        }
+    }
+}
+
+/// Differences between two snapshots, returned by [`FsmSnapshot::diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotDiff<S: Hash + Eq> {
+    /// Transitions taken only in the snapshot `diff` was called on
+    pub only_in_self: HashSet<(S, S)>,
+    /// Transitions taken only in the snapshot passed to `diff`
+    pub only_in_other: HashSet<(S, S)>,
+    /// Whether the two snapshots have different current states
+    pub current_state_differs: bool,
+}
+
+impl<S: Hash + Eq> SnapshotDiff<S> {
+    /// Check whether the snapshots agree on current state and transitions
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && !self.current_state_differs
+    }
+}
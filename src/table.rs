@@ -0,0 +1,182 @@
+//! Handlers generated from transitions declared on the builder
+
+use crate::fsm::RetryPolicy;
+use crate::registry::StateEntry;
+use crate::{Response, Stateful};
+use async_trait::async_trait;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::{self, Future};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Predicate selecting the events a declared transition applies to
+pub(crate) type EventPredicate<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
+pub(crate) type Handler<S, CTX, E> = Box<dyn Stateful<S, CTX, E> + Send + Sync>;
+
+/// Handler for a state with transitions declared via
+/// [`StateMachineBuilder::transition`](crate::StateMachineBuilder::transition)
+///
+/// Events matching a declared transition take it; any other event goes to
+/// the state's own handler if one was registered, and otherwise bubbles up
+/// with [`Response::Super`]. All other hooks are forwarded to the own handler.
+pub(crate) struct TableState<S, CTX, E> {
+    transitions: Vec<(EventPredicate<E>, S)>,
+    inner: Option<Handler<S, CTX, E>>,
+}
+
+impl<S, CTX, E> TableState<S, CTX, E> {
+    pub(crate) fn new(
+        transitions: Vec<(EventPredicate<E>, S)>,
+        inner: Option<Handler<S, CTX, E>>,
+    ) -> Self {
+        Self { transitions, inner }
+    }
+}
+
+#[async_trait]
+impl<S, CTX, E> Stateful<S, CTX, E> for TableState<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Sync + Debug + 'static,
+    CTX: Send + 'static,
+    E: Debug + Send + 'static,
+{
+    async fn on_enter(&mut self, context: &mut CTX) -> Response<S> {
+        match &mut self.inner {
+            Some(inner) => inner.on_enter(context).await,
+            None => Response::Handled,
+        }
+    }
+
+    async fn on_enter_with(
+        &mut self,
+        context: &mut CTX,
+        payload: Box<dyn Any + Send>,
+    ) -> Response<S> {
+        match &mut self.inner {
+            Some(inner) => inner.on_enter_with(context, payload).await,
+            None => Response::Handled,
+        }
+    }
+
+    // Written out so the declared transitions are matched before any await,
+    // without requiring `E: Sync`
+    fn on_event_in<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 mut self,
+        state: &'life1 S,
+        event: &'life2 E,
+        context: &'life3 mut CTX,
+    ) -> Pin<Box<dyn Future<Output = Response<S>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        if let Some((_, target)) = self.transitions.iter().find(|(matches, _)| matches(event)) {
+            return Box::pin(future::ready(Response::Transition(target.clone())));
+        }
+        match &mut self.inner {
+            Some(inner) => inner.on_event_in(state, event, context),
+            None => Box::pin(future::ready(Response::Super)),
+        }
+    }
+
+    async fn on_exit_checked(&mut self, context: &mut CTX) -> Response<S> {
+        match &mut self.inner {
+            Some(inner) => inner.on_exit_checked(context).await,
+            None => Response::Handled,
+        }
+    }
+
+    async fn on_register(&mut self, context: &mut CTX) {
+        if let Some(inner) = &mut self.inner {
+            inner.on_register(context).await;
+        }
+    }
+
+    // Written out to hand back the own handler's future, without requiring `CTX: Sync`
+    fn can_enter<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        context: &'life1 CTX,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        match &self.inner {
+            Some(inner) => inner.can_enter(context),
+            None => Box::pin(future::ready(true)),
+        }
+    }
+
+    fn get_timeout<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        context: &'life1 CTX,
+    ) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        match &self.inner {
+            Some(inner) => inner.get_timeout(context),
+            None => Box::pin(future::ready(None)),
+        }
+    }
+
+    fn superstate(&self) -> Option<S> {
+        self.inner.as_ref().and_then(|inner| inner.superstate())
+    }
+
+    fn timeout_event(&self) -> Option<E> {
+        self.inner.as_ref().and_then(|inner| inner.timeout_event())
+    }
+
+    async fn on_timeout(&mut self, context: &mut CTX) -> Response<S> {
+        match &mut self.inner {
+            Some(inner) => inner.on_timeout(context).await,
+            None => Response::Handled,
+        }
+    }
+
+    async fn enter_retry(&self) -> RetryPolicy {
+        match &self.inner {
+            Some(inner) => inner.enter_retry().await,
+            None => RetryPolicy::default(),
+        }
+    }
+}
+
+/// Wrap every state with declared transitions in a [`TableState`], and
+/// register a plain one for targets that have no handler
+pub(crate) fn apply_transition_table<S, CTX, E>(
+    states: &mut HashMap<S, StateEntry<S, CTX, E>>,
+    table: HashMap<S, Vec<(EventPredicate<E>, S)>>,
+) where
+    S: Hash + Eq + Clone + Send + Sync + Debug + 'static,
+    CTX: Send + 'static,
+    E: Debug + Send + 'static,
+{
+    let targets: Vec<S> = table
+        .values()
+        .flat_map(|transitions| transitions.iter().map(|(_, target)| target.clone()))
+        .collect();
+
+    for (state, transitions) in table {
+        let inner = states.remove(&state).map(StateEntry::into_handler);
+        let handler = TableState::new(transitions, inner);
+        states.insert(state, StateEntry::Plain(Box::new(handler)));
+    }
+    for target in targets {
+        states
+            .entry(target)
+            .or_insert_with(|| StateEntry::Plain(Box::new(TableState::new(Vec::new(), None))));
+    }
+}
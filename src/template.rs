@@ -0,0 +1,105 @@
+//! Reusable recipes for building many identical state machines
+
+use crate::{StateMachine, StateMachineBuilder, Stateful};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::Arc;
+
+type StateFactory<S, CTX, E> =
+    Box<dyn Fn() -> Box<dyn Stateful<S, CTX, E> + Send + Sync> + Send + Sync>;
+type ContextFactory<CTX> = Box<dyn Fn() -> CTX + Send + Sync>;
+type SharedSuperstateFn<S> = Arc<dyn Fn(&S) -> Option<S> + Send + Sync>;
+
+/// A recipe for state machines, producing a fresh machine on every
+/// [`instantiate`](Self::instantiate)
+///
+/// Handlers can't be cloned, so states are registered as factories that
+/// create a new handler per machine, and the context comes from a factory too.
+///
+/// ```rust
+/// use async_hierarchical_fsm::{Response, StateMachineTemplate, fn_state};
+///
+/// let template = StateMachineTemplate::<&str, u32, ()>::new(|| 0).state("idle", || {
+///     fn_state(
+///         |_| Box::pin(async { Response::Handled }),
+///         |_, _| Box::pin(async { Response::Handled }),
+///         |_| Box::pin(async {}),
+///     )
+/// });
+///
+/// let first = template.instantiate();
+/// let second = template.instantiate();
+/// assert!(first.is_registered(&"idle") && second.is_registered(&"idle"));
+/// ```
+pub struct StateMachineTemplate<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Send + 'static,
+{
+    context_factory: ContextFactory<CTX>,
+    states: HashMap<S, StateFactory<S, CTX, E>>,
+    superstate_fn: Option<SharedSuperstateFn<S>>,
+}
+
+impl<S, CTX, E> StateMachineTemplate<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Send + 'static,
+{
+    /// Create a template whose machines get their context from `context_factory`
+    pub fn new<F>(context_factory: F) -> Self
+    where
+        F: Fn() -> CTX + Send + Sync + 'static,
+    {
+        Self {
+            context_factory: Box::new(context_factory),
+            states: HashMap::new(),
+            superstate_fn: None,
+        }
+    }
+
+    /// Add a state, created by `factory` for each machine
+    pub fn state<F, T>(mut self, state_id: S, factory: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Stateful<S, CTX, E> + 'static,
+    {
+        self.states
+            .insert(state_id, Box::new(move || Box::new(factory())));
+        self
+    }
+
+    /// Set the superstate function shared by every machine
+    pub fn superstate_fn<F>(mut self, func: F) -> Self
+    where
+        F: Fn(&S) -> Option<S> + Send + Sync + 'static,
+    {
+        self.superstate_fn = Some(Arc::new(func));
+        self
+    }
+
+    /// Create a builder with a fresh context and fresh states, for adding
+    /// per-machine options before building
+    pub fn builder(&self) -> StateMachineBuilder<S, CTX, E> {
+        let states = self
+            .states
+            .iter()
+            .map(|(state, factory)| (state.clone(), factory()));
+        let builder = StateMachineBuilder::new((self.context_factory)()).states(states);
+        match &self.superstate_fn {
+            Some(superstate_fn) => {
+                let superstate_fn = Arc::clone(superstate_fn);
+                builder.superstate_fn(move |state| superstate_fn(state))
+            }
+            None => builder,
+        }
+    }
+
+    /// Build a new, uninitialized state machine
+    pub fn instantiate(&self) -> StateMachine<S, CTX, E> {
+        self.builder().build()
+    }
+}
@@ -0,0 +1,134 @@
+//! Assertion helpers for driving a state machine through expected transitions
+
+use crate::StateMachine;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Wraps a state machine and checks the outcome of each event it processes
+///
+/// Every `expect_*` method processes one event and panics with the event,
+/// the states involved, and the actual outcome if it does not match. The
+/// methods return the tester, so expectations can be chained:
+///
+/// ```rust
+/// # use async_hierarchical_fsm::{FsmTester, StateMachine};
+/// # #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// # enum State { Off, On }
+/// # #[derive(Debug)]
+/// # enum Event { PowerOn, Ping }
+/// # async fn example(fsm: StateMachine<State, (), Event>) {
+/// FsmTester::new(fsm)
+///     .expect_transition(&Event::PowerOn, State::On)
+///     .await
+///     .expect_handled(&Event::Ping)
+///     .await
+///     .expect_error(&Event::PowerOn)
+///     .await;
+/// # }
+/// ```
+pub struct FsmTester<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Send + 'static,
+{
+    fsm: StateMachine<S, CTX, E>,
+}
+
+impl<S, CTX, E> FsmTester<S, CTX, E>
+where
+    S: Hash + Eq + Clone + Send + Debug + 'static,
+    E: Debug + Send + 'static,
+    CTX: Send + 'static,
+{
+    /// Wrap a state machine, which should already be initialized
+    pub fn new(fsm: StateMachine<S, CTX, E>) -> Self {
+        Self { fsm }
+    }
+
+    /// Process `event` and expect it to succeed with a transition into `to`
+    ///
+    /// Re-entering the current state counts as a transition.
+    ///
+    /// # Panics
+    /// Panics if processing fails, no transition takes place, or the machine
+    /// ends up in a state other than `to`.
+    pub async fn expect_transition(&mut self, event: &E, to: S) -> &mut Self {
+        let from = self.fsm.current_state();
+        match self.fsm.process_event_tracked(event).await {
+            Ok(outcome) if outcome.transitioned && outcome.to == to => self,
+            Ok(outcome) if outcome.transitioned => panic!(
+                "expected {event:?} to move {from:?} to {to:?}, but it moved to {:?}",
+                outcome.to
+            ),
+            Ok(_) => panic!(
+                "expected {event:?} to move {from:?} to {to:?}, but it was handled without a transition"
+            ),
+            Err(error) => {
+                panic!("expected {event:?} to move {from:?} to {to:?}, but it failed: {error}")
+            }
+        }
+    }
+
+    /// Process `event` and expect it to succeed without any transition
+    ///
+    /// # Panics
+    /// Panics if processing fails or a transition takes place.
+    pub async fn expect_handled(&mut self, event: &E) -> &mut Self {
+        let from = self.fsm.current_state();
+        match self.fsm.process_event_tracked(event).await {
+            Ok(outcome) if !outcome.transitioned => self,
+            Ok(outcome) => panic!(
+                "expected {event:?} to be handled in {from:?}, but it moved to {:?}",
+                outcome.to
+            ),
+            Err(error) => {
+                panic!("expected {event:?} to be handled in {from:?}, but it failed: {error}")
+            }
+        }
+    }
+
+    /// Process `event` and expect it to return an error
+    ///
+    /// # Panics
+    /// Panics if processing succeeds.
+    pub async fn expect_error(&mut self, event: &E) -> &mut Self {
+        let from = self.fsm.current_state();
+        if let Ok(outcome) = self.fsm.process_event_tracked(event).await {
+            panic!(
+                "expected {event:?} to fail in {from:?}, but it succeeded in {:?}",
+                outcome.to
+            );
+        }
+        self
+    }
+
+    /// Expect the machine to be in `state` without processing an event
+    ///
+    /// # Panics
+    /// Panics if the current state is not `state`.
+    pub fn expect_state(&mut self, state: S) -> &mut Self {
+        let current = self.fsm.current_state();
+        assert_eq!(
+            current.as_ref(),
+            Some(&state),
+            "expected the machine to be in {state:?}, but it is in {current:?}"
+        );
+        self
+    }
+
+    /// Get the wrapped state machine, for example to check the context
+    pub fn fsm(&self) -> &StateMachine<S, CTX, E> {
+        &self.fsm
+    }
+
+    /// Get mutable access to the wrapped state machine
+    pub fn fsm_mut(&mut self) -> &mut StateMachine<S, CTX, E> {
+        &mut self.fsm
+    }
+
+    /// Unwrap the state machine
+    pub fn into_inner(self) -> StateMachine<S, CTX, E> {
+        self.fsm
+    }
+}
@@ -0,0 +1,23 @@
+//! Optional `tracing` instrumentation, compiled out without the `tracing` feature
+
+/// Run a future inside an `info_span!` built from the remaining arguments
+///
+/// The span is created before the future expression is evaluated, so its
+/// fields may borrow values the future later takes by move.
+#[cfg(feature = "tracing")]
+macro_rules! instrument {
+    ($fut:expr, $($span:tt)+) => {{
+        let span = tracing::info_span!($($span)+);
+        tracing::Instrument::instrument($fut, span)
+    }};
+}
+
+/// Run a future as-is; span arguments are never evaluated
+#[cfg(not(feature = "tracing"))]
+macro_rules! instrument {
+    ($fut:expr, $($span:tt)+) => {
+        $fut
+    };
+}
+
+pub(crate) use instrument;
@@ -0,0 +1,48 @@
+#![cfg(feature = "async-std-integration")]
+
+use async_hierarchical_fsm::async_std_utils::process_event_with_timeout;
+use async_hierarchical_fsm::{
+    Duration, FsmError, Response, StateMachineBuilder, Stateful, async_trait,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum State {
+    Busy,
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Work,
+}
+
+struct SlowState;
+
+#[async_trait]
+impl Stateful<State, (), Event> for SlowState {
+    async fn on_enter(&mut self, _context: &mut ()) -> Response<State> {
+        Response::Handled
+    }
+
+    async fn on_event(&mut self, _event: &Event, _context: &mut ()) -> Response<State> {
+        async_std::task::sleep(Duration::from_secs(5)).await;
+        Response::Handled
+    }
+
+    async fn on_exit(&mut self, _context: &mut ()) {}
+}
+
+#[test]
+fn test_process_event_with_timeout_elapses() {
+    async_std::task::block_on(async {
+        let mut fsm = StateMachineBuilder::new(())
+            .state(State::Busy, SlowState)
+            .build();
+        fsm.init(State::Busy).await.unwrap();
+
+        let result =
+            process_event_with_timeout(&mut fsm, &Event::Work, Duration::from_millis(10)).await;
+
+        assert!(matches!(result, Err(FsmError::Timeout)));
+        assert_eq!(fsm.current_state(), Some(State::Busy));
+    });
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "derive")]
+
+use async_hierarchical_fsm::{FsmError, FsmState, StateMachineBuilder, Stateful, async_trait};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, FsmState)]
+enum State {
+    Root,
+    #[superstate(Root)]
+    Menu,
+    #[superstate(Menu)]
+    Settings,
+}
+
+// The closure the derive replaces
+fn hand_written(state: &State) -> Option<State> {
+    match state {
+        State::Menu => Some(State::Root),
+        State::Settings => Some(State::Menu),
+        State::Root => None,
+    }
+}
+
+#[test]
+fn test_derived_superstate_matches_closure() {
+    assert_eq!(State::ALL, &[State::Root, State::Menu, State::Settings]);
+    for state in State::ALL {
+        assert_eq!(state.superstate(), hand_written(state));
+    }
+}
+
+struct Leaf;
+
+#[async_trait]
+impl Stateful<State, (), ()> for Leaf {}
+
+#[tokio::test]
+async fn test_derived_superstate_drives_builder() {
+    let mut fsm = StateMachineBuilder::new(())
+        .states(State::ALL.iter().map(|state| {
+            (
+                state.clone(),
+                Box::new(Leaf) as Box<dyn Stateful<State, (), ()> + Send + Sync>,
+            )
+        }))
+        .superstate_fn(State::superstate)
+        .build_validated()
+        .unwrap();
+    fsm.init(State::Settings).await.unwrap();
+
+    assert_eq!(
+        fsm.ancestors_of(&State::Settings),
+        vec![State::Settings, State::Menu, State::Root]
+    );
+    assert!(fsm.is_in(&State::Root));
+    assert!(matches!(
+        fsm.process_event(&()).await,
+        Err(FsmError::InvalidEvent(State::Root, _))
+    ));
+}
+
+#[test]
+fn test_derive_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -1,5 +1,5 @@
 use async_hierarchical_fsm::{
-    Duration, Response, StateMachine, StateMachineBuilder, Stateful, async_trait,
+    Duration, FsmError, Response, StateMachine, StateMachineBuilder, Stateful, async_trait,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -18,6 +18,7 @@ enum DeviceEvent {
     Deactivate,
     ErrorOccurred,
     Reset,
+    EmergencyStop,
 }
 
 #[derive(Debug)]
@@ -207,6 +208,102 @@ async fn test_device_lifecycle() {
     assert_eq!(device.context().power_level, 0);
 }
 
+#[cfg(feature = "testing")]
+#[tokio::test]
+async fn test_device_lifecycle_with_tester() {
+    use async_hierarchical_fsm::FsmTester;
+
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let mut tester = FsmTester::new(device);
+    tester
+        .expect_error(&DeviceEvent::Activate)
+        .await
+        .expect_transition(&DeviceEvent::PowerOn, DeviceState::Standby)
+        .await
+        .expect_handled(&DeviceEvent::Deactivate)
+        .await
+        .expect_transition(&DeviceEvent::Activate, DeviceState::Active)
+        .await
+        .expect_transition(&DeviceEvent::ErrorOccurred, DeviceState::Error)
+        .await
+        .expect_handled(&DeviceEvent::Activate)
+        .await
+        .expect_transition(&DeviceEvent::Reset, DeviceState::Standby)
+        .await
+        .expect_transition(&DeviceEvent::PowerOff, DeviceState::Off)
+        .await
+        .expect_state(DeviceState::Off);
+    assert_eq!(tester.fsm().context().error_count, 1);
+}
+
+#[cfg(feature = "testing")]
+#[tokio::test]
+#[should_panic(expected = "but it moved to Standby")]
+async fn test_tester_reports_wrong_target() {
+    use async_hierarchical_fsm::FsmTester;
+
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    FsmTester::new(device)
+        .expect_transition(&DeviceEvent::PowerOn, DeviceState::Active)
+        .await;
+}
+
+// Invariant: each state runs at its own power level
+fn power_matches_state(state: &DeviceState, context: &DeviceContext) -> Result<(), String> {
+    let expected = match state {
+        DeviceState::Off | DeviceState::Error => 0,
+        DeviceState::Standby => 25,
+        DeviceState::Active => 100,
+    };
+    if context.power_level == expected {
+        Ok(())
+    } else {
+        Err(format!("power level {}", context.power_level))
+    }
+}
+
+#[tokio::test]
+async fn test_invariant_violated() {
+    let mut device = StateMachineBuilder::new(DeviceContext::new())
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, StandbyState)
+        .state(DeviceState::Active, ActiveState)
+        .state(DeviceState::Error, ErrorState)
+        .invariant(power_matches_state)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+    device.process_event(&DeviceEvent::Activate).await.unwrap();
+
+    // Error keeps minimal power on, which the invariant forbids
+    let result = device.process_event(&DeviceEvent::ErrorOccurred).await;
+    assert!(matches!(
+        result,
+        Err(FsmError::InvariantViolated(DeviceState::Error, reason)) if reason == "power level 10"
+    ));
+    assert_eq!(device.current_state(), Some(DeviceState::Error));
+}
+
+#[tokio::test]
+async fn test_invariants_run_in_order() {
+    let mut device = StateMachineBuilder::new(DeviceContext::new())
+        .state(DeviceState::Off, OffState)
+        .invariant(power_matches_state)
+        .invariant(|_state, _context| Err("first failure".to_string()))
+        .invariant(|_state, _context| Err("second failure".to_string()))
+        .build();
+
+    let result = device.init(DeviceState::Off).await;
+    assert!(matches!(
+        result,
+        Err(FsmError::InvariantViolated(DeviceState::Off, reason)) if reason == "first failure"
+    ));
+}
+
 #[tokio::test]
 async fn test_error_recovery_limit() {
     let mut device = create_device_fsm();
@@ -296,6 +393,63 @@ async fn test_invalid_transitions() {
     assert_eq!(device.current_state(), Some(DeviceState::Off));
 }
 
+#[tokio::test]
+async fn test_process_events_reports_failing_index() {
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let events = [
+        DeviceEvent::PowerOn,
+        DeviceEvent::Activate,
+        DeviceEvent::PowerOff,
+        DeviceEvent::Activate, // Invalid while Off
+        DeviceEvent::PowerOn,
+    ];
+
+    match device.process_events(&events).await {
+        Err((index, FsmError::InvalidEvent(state, _))) => {
+            assert_eq!(index, 3);
+            assert_eq!(state, DeviceState::Off);
+        }
+        _ => panic!("Expected InvalidEvent at index 3"),
+    }
+
+    // No rollback, and the remaining events were not processed
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+
+    device.process_events(&events[..3]).await.unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+}
+
+#[tokio::test]
+async fn test_process_queue_leaves_remainder_queued() {
+    use std::collections::VecDeque;
+
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let mut queue = VecDeque::from([
+        DeviceEvent::PowerOn,
+        DeviceEvent::Activate,
+        DeviceEvent::PowerOff,
+        DeviceEvent::Deactivate, // Invalid while Off
+        DeviceEvent::PowerOn,
+        DeviceEvent::Activate,
+    ]);
+
+    let (index, err) = device.process_queue(&mut queue).await.unwrap_err();
+    assert_eq!(index, 3);
+    assert!(matches!(err, FsmError::InvalidEvent(DeviceState::Off, _)));
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+    assert_eq!(queue.len(), 2);
+    assert!(matches!(queue.front(), Some(DeviceEvent::PowerOn)));
+
+    // Resuming processes the remainder
+    assert_eq!(device.process_queue(&mut queue).await.unwrap(), 2);
+    assert!(queue.is_empty());
+    assert_eq!(device.current_state(), Some(DeviceState::Active));
+}
+
 #[tokio::test]
 async fn test_concurrent_operations() {
     use std::sync::Arc;
@@ -396,7 +550,7 @@ async fn test_stress() {
     let mut device = create_device_fsm();
     device.init(DeviceState::Off).await.unwrap();
 
-    let events = vec![
+    let events = [
         DeviceEvent::PowerOn,
         DeviceEvent::Activate,
         DeviceEvent::Deactivate,
@@ -420,3 +574,107 @@ async fn test_stress() {
     assert!(device.current_state().is_some());
     let _ = device.get_current_timeout().await;
 }
+
+#[tokio::test]
+async fn test_force_transition() {
+    let mut device = create_device_fsm();
+    let result = device.force_transition(DeviceState::Active).await;
+    assert!(matches!(result, Err(FsmError::StateMachineNotInitialized)));
+
+    device.init(DeviceState::Off).await.unwrap();
+    device.force_transition(DeviceState::Active).await.unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Active));
+    assert_eq!(device.context().power_level, 100);
+    assert!(
+        device
+            .transition_log()
+            .contains(&(DeviceState::Off, DeviceState::Active))
+    );
+}
+
+#[tokio::test]
+async fn test_global_handler() {
+    let mut device = StateMachineBuilder::new(DeviceContext::new())
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, StandbyState)
+        .state(DeviceState::Active, ActiveState)
+        .state(DeviceState::Error, ErrorState)
+        .global_handler(|event, _context| match event {
+            DeviceEvent::EmergencyStop => Some(Response::Transition(DeviceState::Off)),
+            _ => None,
+        })
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+
+    for start in [
+        DeviceState::Standby,
+        DeviceState::Active,
+        DeviceState::Error,
+    ] {
+        device.force_transition(start).await.unwrap();
+        device
+            .process_event(&DeviceEvent::EmergencyStop)
+            .await
+            .unwrap();
+        assert_eq!(device.current_state(), Some(DeviceState::Off));
+        assert_eq!(device.context().power_level, 0);
+    }
+
+    // Other events still reach the current state
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+}
+
+// Active state whose sensor reading fails on any event but `PowerOff`
+struct FaultyActiveState;
+
+#[async_trait]
+impl Stateful<DeviceState, DeviceContext, DeviceEvent> for FaultyActiveState {
+    async fn on_enter(&mut self, context: &mut DeviceContext) -> Response<DeviceState> {
+        context.power_level = 100;
+        Response::Handled
+    }
+
+    async fn on_event(
+        &mut self,
+        event: &DeviceEvent,
+        _context: &mut DeviceContext,
+    ) -> Response<DeviceState> {
+        match event {
+            DeviceEvent::PowerOff => Response::Transition(DeviceState::Off),
+            _ => Response::Error("sensor read failed".to_string()),
+        }
+    }
+
+    async fn on_exit(&mut self, _context: &mut DeviceContext) {}
+}
+
+#[tokio::test]
+async fn test_on_error_goto() {
+    let mut device = StateMachineBuilder::new(DeviceContext::new())
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, StandbyState)
+        .state(DeviceState::Active, FaultyActiveState)
+        .state(DeviceState::Error, ErrorState)
+        .on_error_goto(DeviceState::Active, DeviceState::Error)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+    device.process_event(&DeviceEvent::Activate).await.unwrap();
+
+    // The error is replaced by a transition to the recovery state
+    device
+        .process_event(&DeviceEvent::Deactivate)
+        .await
+        .unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Error));
+    assert_eq!(device.context().error_count, 1);
+
+    // States without a recovery target still report their errors
+    device.process_event(&DeviceEvent::PowerOff).await.unwrap();
+    let result = device.process_event(&DeviceEvent::Activate).await;
+    assert!(matches!(
+        result,
+        Err(FsmError::InvalidEvent(DeviceState::Off, _))
+    ));
+}
@@ -0,0 +1,137 @@
+use async_hierarchical_fsm::{
+    FsmError, ParallelStateMachine, Response, StateMachineBuilder, Stateful, async_trait,
+};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum State {
+    PowerOff,
+    PowerOn,
+    Disconnected,
+    Connected,
+}
+
+#[derive(Debug, Clone)]
+enum Input {
+    TogglePower,
+    Link,
+}
+
+#[derive(Default)]
+struct Device {
+    log: Vec<String>,
+}
+
+type Shared = Arc<Mutex<Device>>;
+
+// Moves to `next` on `trigger`, leaving every other event unhandled
+struct Toggle {
+    name: &'static str,
+    next: State,
+    trigger: fn(&Input) -> bool,
+}
+
+#[async_trait]
+impl Stateful<State, Shared, Input> for Toggle {
+    async fn on_enter(&mut self, context: &mut Shared) -> Response<State> {
+        context.lock().unwrap().log.push(self.name.to_string());
+        Response::Handled
+    }
+
+    async fn on_event(&mut self, event: &Input, _context: &mut Shared) -> Response<State> {
+        if (self.trigger)(event) {
+            Response::Transition(self.next.clone())
+        } else {
+            Response::Super
+        }
+    }
+
+    async fn on_exit(&mut self, _context: &mut Shared) {}
+}
+
+fn is_power(event: &Input) -> bool {
+    matches!(event, Input::TogglePower)
+}
+
+fn is_link(event: &Input) -> bool {
+    matches!(event, Input::Link)
+}
+
+#[tokio::test]
+async fn test_regions_react_to_disjoint_events() {
+    let mut parallel = ParallelStateMachine::new(Device::default());
+
+    let mut power = StateMachineBuilder::new(parallel.context())
+        .state(
+            State::PowerOff,
+            Toggle {
+                name: "PowerOff",
+                next: State::PowerOn,
+                trigger: is_power,
+            },
+        )
+        .state(
+            State::PowerOn,
+            Toggle {
+                name: "PowerOn",
+                next: State::PowerOff,
+                trigger: is_power,
+            },
+        )
+        .build();
+    power.init(State::PowerOff).await.unwrap();
+
+    let mut network = StateMachineBuilder::new(parallel.context())
+        .state(
+            State::Disconnected,
+            Toggle {
+                name: "Disconnected",
+                next: State::Connected,
+                trigger: is_link,
+            },
+        )
+        .state(
+            State::Connected,
+            Toggle {
+                name: "Connected",
+                next: State::Disconnected,
+                trigger: is_link,
+            },
+        )
+        .build();
+    network.init(State::Disconnected).await.unwrap();
+
+    let power = parallel.add_region(power);
+    let network = parallel.add_region(network);
+    assert_eq!(parallel.region_count(), 2);
+
+    let results = parallel.process_event(&Input::TogglePower).await;
+    assert!(results[power].1.is_ok());
+    assert!(matches!(
+        results[network].1,
+        Err(FsmError::InvalidEvent(State::Disconnected, _))
+    ));
+    assert_eq!(
+        parallel.current_states(),
+        vec![
+            (power, Some(State::PowerOn)),
+            (network, Some(State::Disconnected))
+        ]
+    );
+
+    let results = parallel.process_event(&Input::Link).await;
+    assert!(results[power].1.is_err());
+    assert!(results[network].1.is_ok());
+    assert_eq!(
+        parallel.current_states(),
+        vec![
+            (power, Some(State::PowerOn)),
+            (network, Some(State::Connected))
+        ]
+    );
+
+    assert_eq!(
+        parallel.context().lock().unwrap().log,
+        vec!["PowerOff", "Disconnected", "PowerOn", "Connected"]
+    );
+}
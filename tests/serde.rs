@@ -0,0 +1,128 @@
+#![cfg(feature = "serde")]
+
+use async_hierarchical_fsm::{
+    FsmError, FsmSnapshot, Response, StateMachine, StateMachineBuilder, Stateful, async_trait,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum State {
+    Idle,
+    Running,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+enum Event {
+    Start,
+}
+
+#[derive(Debug, Default)]
+struct Context {
+    entries: u32,
+}
+
+struct IdleState;
+
+#[async_trait]
+impl Stateful<State, Context, Event> for IdleState {
+    async fn on_enter(&mut self, context: &mut Context) -> Response<State> {
+        context.entries += 1;
+        Response::Handled
+    }
+
+    async fn on_event(&mut self, _event: &Event, _context: &mut Context) -> Response<State> {
+        Response::Transition(State::Running)
+    }
+
+    async fn on_exit(&mut self, _context: &mut Context) {}
+}
+
+struct RunningState;
+
+#[async_trait]
+impl Stateful<State, Context, Event> for RunningState {
+    async fn on_enter(&mut self, context: &mut Context) -> Response<State> {
+        context.entries += 1;
+        Response::Handled
+    }
+
+    async fn on_event(&mut self, _event: &Event, _context: &mut Context) -> Response<State> {
+        Response::Handled
+    }
+
+    async fn on_exit(&mut self, _context: &mut Context) {}
+}
+
+fn create_fsm() -> StateMachine<State, Context, Event> {
+    StateMachineBuilder::new(Context::default())
+        .state(State::Idle, IdleState)
+        .state(State::Running, RunningState)
+        .build()
+}
+
+#[tokio::test]
+async fn test_snapshot_json_round_trip() {
+    let mut fsm = create_fsm();
+    fsm.init(State::Idle).await.unwrap();
+    fsm.process_event(&Event::Start).await.unwrap();
+
+    let json = serde_json::to_string(&fsm.snapshot()).unwrap();
+    let snapshot: FsmSnapshot<State> = serde_json::from_str(&json).unwrap();
+    assert_eq!(snapshot, fsm.snapshot());
+
+    let mut restored = create_fsm();
+    restored.restore_snapshot(snapshot);
+
+    assert_eq!(restored.current_state(), Some(State::Running));
+    assert!(
+        restored
+            .transition_log()
+            .contains(&(State::Idle, State::Running))
+    );
+    // Restoring is a resume, so no on_enter ran
+    assert_eq!(restored.context().entries, 0);
+}
+
+#[tokio::test]
+async fn test_process_json_event() {
+    let mut fsm = create_fsm();
+    fsm.init(State::Idle).await.unwrap();
+
+    let err = fsm.process_json(r#"{"Stop": null}"#).await.unwrap_err();
+    assert!(matches!(err, FsmError::EventDecode(_)));
+    assert_eq!(fsm.current_state(), Some(State::Idle));
+
+    fsm.process_json(r#""Start""#).await.unwrap();
+    assert_eq!(fsm.current_state(), Some(State::Running));
+}
+
+#[tokio::test]
+async fn test_describe_json() {
+    let mut fsm = StateMachineBuilder::new(Context::default())
+        .state(State::Idle, IdleState)
+        .state(State::Running, RunningState)
+        .superstate_fn(|state| match state {
+            State::Running => Some(State::Idle),
+            State::Idle => None,
+        })
+        .build();
+    fsm.init(State::Idle).await.unwrap();
+    fsm.process_event(&Event::Start).await.unwrap();
+
+    let json = serde_json::to_value(fsm.describe()).unwrap();
+    assert_eq!(json["states"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        json["superstate_edges"],
+        serde_json::json!([["Running", "Idle"]])
+    );
+    assert_eq!(
+        json["transition_log"],
+        serde_json::json!([["Idle", "Running"]])
+    );
+    assert_eq!(json["current_state"], "Running");
+    assert_eq!(
+        json["active_states"],
+        serde_json::json!(["Running", "Idle"])
+    );
+    assert!(json["time_in_state"].is_object());
+}
@@ -0,0 +1,573 @@
+#![cfg(feature = "tokio-integration")]
+
+use async_hierarchical_fsm::tokio_utils::{
+    SharedContext, SharedStateMachine, block_on_event, run_with_timeouts, shared_context,
+    spawn_actor,
+};
+use async_hierarchical_fsm::{
+    Duration, FsmError, Response, StateMachine, StateMachineBuilder, Stateful, async_trait,
+};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DeviceState {
+    Off,
+    Standby,
+}
+
+#[derive(Debug, Clone)]
+enum DeviceEvent {
+    PowerOn,
+    Timeout,
+}
+
+#[derive(Debug)]
+struct DeviceContext {
+    timeouts: u32,
+}
+
+struct OffState;
+
+#[async_trait]
+impl Stateful<DeviceState, DeviceContext, DeviceEvent> for OffState {
+    async fn on_enter(&mut self, _context: &mut DeviceContext) -> Response<DeviceState> {
+        Response::Handled
+    }
+
+    async fn on_event(
+        &mut self,
+        event: &DeviceEvent,
+        _context: &mut DeviceContext,
+    ) -> Response<DeviceState> {
+        match event {
+            DeviceEvent::PowerOn => Response::Transition(DeviceState::Standby),
+            _ => Response::Error("Device is off".to_string()),
+        }
+    }
+
+    async fn on_exit(&mut self, _context: &mut DeviceContext) {}
+}
+
+struct StandbyState;
+
+#[async_trait]
+impl Stateful<DeviceState, DeviceContext, DeviceEvent> for StandbyState {
+    async fn on_enter(&mut self, _context: &mut DeviceContext) -> Response<DeviceState> {
+        Response::Handled
+    }
+
+    async fn on_event(
+        &mut self,
+        event: &DeviceEvent,
+        context: &mut DeviceContext,
+    ) -> Response<DeviceState> {
+        match event {
+            DeviceEvent::Timeout => {
+                context.timeouts += 1;
+                Response::Transition(DeviceState::Off)
+            }
+            _ => Response::Handled,
+        }
+    }
+
+    async fn on_exit(&mut self, _context: &mut DeviceContext) {}
+
+    async fn get_timeout(&self, _context: &DeviceContext) -> Option<Duration> {
+        Some(Duration::from_secs(60)) // Auto-shutdown after 1 minute
+    }
+}
+
+fn create_device_fsm() -> StateMachine<DeviceState, DeviceContext, DeviceEvent> {
+    StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, StandbyState)
+        .build()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_run_with_timeouts_fires_timeout_event() {
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let (event_tx, event_rx) = mpsc::channel(8);
+    let driver = tokio::spawn(async move {
+        let result = run_with_timeouts(&mut device, event_rx, || DeviceEvent::Timeout).await;
+        (device, result)
+    });
+
+    event_tx.send(DeviceEvent::PowerOn).await.unwrap();
+
+    // Let Standby's 60 second timeout elapse
+    tokio::time::sleep(Duration::from_secs(61)).await;
+    drop(event_tx);
+
+    let (device, result) = driver.await.unwrap();
+    result.unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+    assert_eq!(device.context().timeouts, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_elapsed_timeout() {
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+
+    // Within Standby's 60 second timeout, the event is handled where it is
+    tokio::time::advance(Duration::from_secs(59)).await;
+    device
+        .process_event_with_elapsed_timeout(&DeviceEvent::PowerOn, DeviceEvent::Timeout)
+        .await
+        .unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+    assert_eq!(device.context().timeouts, 0);
+
+    // Past it, the timeout event goes first, and Off handles PowerOn
+    tokio::time::advance(Duration::from_secs(2)).await;
+    device
+        .process_event_with_elapsed_timeout(&DeviceEvent::PowerOn, DeviceEvent::Timeout)
+        .await
+        .unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+    assert_eq!(device.context().timeouts, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_process_event_with_timeout_elapses() {
+    use async_hierarchical_fsm::tokio_utils::process_event_with_timeout;
+
+    struct SlowState;
+
+    #[async_trait]
+    impl Stateful<DeviceState, DeviceContext, DeviceEvent> for SlowState {
+        async fn on_enter(&mut self, _context: &mut DeviceContext) -> Response<DeviceState> {
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &DeviceEvent,
+            _context: &mut DeviceContext,
+        ) -> Response<DeviceState> {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Response::Handled
+        }
+
+        async fn on_exit(&mut self, _context: &mut DeviceContext) {}
+    }
+
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Standby, SlowState)
+        .build();
+    device.init(DeviceState::Standby).await.unwrap();
+
+    let result =
+        process_event_with_timeout(&mut device, &DeviceEvent::PowerOn, Duration::from_secs(1))
+            .await;
+    assert!(matches!(result, Err(FsmError::Timeout)));
+}
+
+#[test]
+fn test_block_on_event_from_sync_code() {
+    let mut device = create_device_fsm();
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(device.init(DeviceState::Off))
+        .unwrap();
+
+    block_on_event(&mut device, &DeviceEvent::PowerOn).unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+}
+
+#[tokio::test]
+async fn test_block_on_event_inside_runtime() {
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let result = block_on_event(&mut device, &DeviceEvent::PowerOn);
+    assert!(matches!(result, Err(FsmError::InsideAsyncRuntime)));
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+}
+
+#[tokio::test]
+async fn test_state_broadcast() {
+    let (builder, mut states) = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, StandbyState)
+        .state_broadcast(8);
+    let mut device = builder.build();
+
+    device.init(DeviceState::Off).await.unwrap();
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+    device.process_event(&DeviceEvent::Timeout).await.unwrap();
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+
+    let mut received = Vec::new();
+    while let Ok(state) = states.try_recv() {
+        received.push(state);
+    }
+    assert_eq!(
+        received,
+        vec![DeviceState::Standby, DeviceState::Off, DeviceState::Standby]
+    );
+}
+
+#[tokio::test]
+async fn test_spawn_actor() {
+    let mut device = create_device_fsm();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let handle = spawn_actor(device);
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+
+    let state = handle.send(DeviceEvent::PowerOn).await.unwrap();
+    assert_eq!(state, DeviceState::Standby);
+
+    // Handles can be cloned and used from other tasks
+    let other = handle.clone();
+    let state = tokio::spawn(async move { other.send(DeviceEvent::Timeout).await })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state, DeviceState::Off);
+
+    let result = handle.send(DeviceEvent::Timeout).await;
+    assert!(matches!(
+        result,
+        Err(FsmError::InvalidEvent(DeviceState::Off, _))
+    ));
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+}
+
+// Standby that powers itself off when its timeout elapses
+struct AutoOffStandbyState;
+
+#[async_trait]
+impl Stateful<DeviceState, DeviceContext, DeviceEvent> for AutoOffStandbyState {
+    async fn on_event(
+        &mut self,
+        event: &DeviceEvent,
+        context: &mut DeviceContext,
+    ) -> Response<DeviceState> {
+        match event {
+            DeviceEvent::Timeout => {
+                context.timeouts += 1;
+                Response::Transition(DeviceState::Off)
+            }
+            _ => Response::Handled,
+        }
+    }
+
+    async fn get_timeout(&self, _context: &DeviceContext) -> Option<Duration> {
+        Some(Duration::from_secs(60))
+    }
+
+    fn timeout_event(&self) -> Option<DeviceEvent> {
+        Some(DeviceEvent::Timeout)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_spawn_actor_injects_timeout_event() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, AutoOffStandbyState)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let handle = spawn_actor(device);
+    handle.send(DeviceEvent::PowerOn).await.unwrap();
+
+    tokio::time::sleep(Duration::from_secs(30)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Standby));
+
+    tokio::time::sleep(Duration::from_secs(31)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_spawn_actor_queries_do_not_postpone_timeout() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, AutoOffStandbyState)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let handle = spawn_actor(device);
+    handle.send(DeviceEvent::PowerOn).await.unwrap();
+
+    // Poll more often than the 60 second timeout
+    for _ in 0..5 {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(handle.current_state().await, Some(DeviceState::Standby));
+    }
+    tokio::time::sleep(Duration::from_secs(11)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+}
+
+// Standby that powers itself off in `on_timeout`, without a timeout event
+struct SelfTimingStandbyState;
+
+#[async_trait]
+impl Stateful<DeviceState, DeviceContext, DeviceEvent> for SelfTimingStandbyState {
+    async fn on_event(
+        &mut self,
+        _event: &DeviceEvent,
+        _context: &mut DeviceContext,
+    ) -> Response<DeviceState> {
+        Response::Handled
+    }
+
+    async fn get_timeout(&self, _context: &DeviceContext) -> Option<Duration> {
+        Some(Duration::from_secs(60))
+    }
+
+    async fn on_timeout(&mut self, context: &mut DeviceContext) -> Response<DeviceState> {
+        context.timeouts += 1;
+        Response::Transition(DeviceState::Off)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_spawn_actor_calls_on_timeout() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, SelfTimingStandbyState)
+        // Would fail in Off if it were injected after `on_timeout` moved there
+        .timeout_event(DeviceEvent::Timeout)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+
+    let handle = spawn_actor(device);
+    handle.send(DeviceEvent::PowerOn).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(30)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Standby));
+
+    tokio::time::sleep(Duration::from_secs(31)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+}
+
+#[tokio::test]
+async fn test_process_timeout() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, SelfTimingStandbyState)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+
+    // The default `on_timeout` does nothing
+    device.process_timeout().await.unwrap();
+    assert!(!device.last_transitioned());
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+    device.process_timeout().await.unwrap();
+    assert!(device.last_transitioned());
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+    assert_eq!(device.context().timeouts, 1);
+}
+
+// Standby whose `on_timeout` fails
+struct FailingTimeoutStandbyState;
+
+#[async_trait]
+impl Stateful<DeviceState, DeviceContext, DeviceEvent> for FailingTimeoutStandbyState {
+    async fn on_event(
+        &mut self,
+        _event: &DeviceEvent,
+        _context: &mut DeviceContext,
+    ) -> Response<DeviceState> {
+        Response::Transition(DeviceState::Off)
+    }
+
+    async fn get_timeout(&self, _context: &DeviceContext) -> Option<Duration> {
+        Some(Duration::from_secs(60))
+    }
+
+    async fn on_timeout(&mut self, context: &mut DeviceContext) -> Response<DeviceState> {
+        context.timeouts += 1;
+        Response::Error("sensor lost".to_string())
+    }
+}
+
+#[tokio::test]
+async fn test_process_timeout_recovers() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, FailingTimeoutStandbyState)
+        .collect_metrics()
+        .build();
+    device.init(DeviceState::Standby).await.unwrap();
+    assert!(matches!(
+        device.process_timeout().await,
+        Err(FsmError::InvalidEvent(DeviceState::Standby, _))
+    ));
+    assert_eq!(device.metrics().errors_in(&DeviceState::Standby), 1);
+
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, FailingTimeoutStandbyState)
+        .on_error_goto(DeviceState::Standby, DeviceState::Off)
+        .build();
+    device.init(DeviceState::Standby).await.unwrap();
+    device.process_timeout().await.unwrap();
+    assert!(device.last_transitioned());
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+    assert_eq!(device.context().timeouts, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_elapsed_timeout_uses_timeout_handling() {
+    struct TimingOutStandbyState;
+
+    #[async_trait]
+    impl Stateful<DeviceState, DeviceContext, DeviceEvent> for TimingOutStandbyState {
+        async fn get_timeout(&self, _context: &DeviceContext) -> Option<Duration> {
+            Some(Duration::from_secs(60))
+        }
+
+        async fn on_timeout(&mut self, context: &mut DeviceContext) -> Response<DeviceState> {
+            context.timeouts += 1;
+            Response::Transition(DeviceState::Off)
+        }
+    }
+
+    // on_timeout makes the transition, so no timeout event is processed
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, TimingOutStandbyState)
+        .build();
+    device.init(DeviceState::Standby).await.unwrap();
+    tokio::time::advance(Duration::from_secs(61)).await;
+    device
+        .process_event_with_elapsed_timeout(&DeviceEvent::PowerOn, DeviceEvent::Timeout)
+        .await
+        .unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+    assert_eq!(device.context().timeouts, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_elapsed_timeout_failure_keeps_event() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, FailingTimeoutStandbyState)
+        .build();
+    device.init(DeviceState::Standby).await.unwrap();
+    tokio::time::advance(Duration::from_secs(61)).await;
+
+    // The timeout fails, so PowerOn never reaches Standby
+    assert!(matches!(
+        device
+            .process_event_with_elapsed_timeout(&DeviceEvent::PowerOn, DeviceEvent::Timeout)
+            .await,
+        Err(FsmError::InvalidEvent(DeviceState::Standby, _))
+    ));
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+    assert_eq!(device.context().timeouts, 1);
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Off));
+
+    // Once recovered from, the event is handled where recovery led
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, FailingTimeoutStandbyState)
+        .on_error_goto(DeviceState::Standby, DeviceState::Off)
+        .build();
+    device.init(DeviceState::Standby).await.unwrap();
+    tokio::time::advance(Duration::from_secs(61)).await;
+    device
+        .process_event_with_elapsed_timeout(&DeviceEvent::PowerOn, DeviceEvent::Timeout)
+        .await
+        .unwrap();
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+    assert_eq!(device.context().timeouts, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_builder_timeout_event_drives_actor() {
+    let mut device = StateMachineBuilder::new(DeviceContext { timeouts: 0 })
+        .state(DeviceState::Off, OffState)
+        .state(DeviceState::Standby, StandbyState)
+        .timeout_event(DeviceEvent::Timeout)
+        .build();
+    device.init(DeviceState::Off).await.unwrap();
+
+    // Off has no timeout, so the event is never fired there
+    let handle = spawn_actor(device);
+    tokio::time::sleep(Duration::from_secs(120)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+
+    handle.send(DeviceEvent::PowerOn).await.unwrap();
+    tokio::time::sleep(Duration::from_secs(61)).await;
+    assert_eq!(handle.current_state().await, Some(DeviceState::Off));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shared_context_outlives_transition() {
+    // Standby that warms up in the background after it has been entered
+    struct WarmingStandbyState {
+        warmed: Option<tokio::sync::oneshot::Sender<()>>,
+    }
+
+    #[async_trait]
+    impl Stateful<DeviceState, SharedContext<DeviceContext>, DeviceEvent> for WarmingStandbyState {
+        async fn on_enter(
+            &mut self,
+            context: &mut SharedContext<DeviceContext>,
+        ) -> Response<DeviceState> {
+            let context = context.clone();
+            let warmed = self.warmed.take();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                context.lock().await.timeouts += 1;
+                if let Some(warmed) = warmed {
+                    let _ = warmed.send(());
+                }
+            });
+            Response::Handled
+        }
+
+        async fn on_event(
+            &mut self,
+            _event: &DeviceEvent,
+            _context: &mut SharedContext<DeviceContext>,
+        ) -> Response<DeviceState> {
+            Response::Handled
+        }
+    }
+
+    struct SharedOffState;
+
+    #[async_trait]
+    impl Stateful<DeviceState, SharedContext<DeviceContext>, DeviceEvent> for SharedOffState {
+        async fn on_event(
+            &mut self,
+            _event: &DeviceEvent,
+            _context: &mut SharedContext<DeviceContext>,
+        ) -> Response<DeviceState> {
+            Response::Transition(DeviceState::Standby)
+        }
+    }
+
+    let (warmed_tx, warmed_rx) = tokio::sync::oneshot::channel();
+    let mut device: SharedStateMachine<_, _, _> =
+        StateMachineBuilder::new(shared_context(DeviceContext { timeouts: 0 }))
+            .state(DeviceState::Off, SharedOffState)
+            .state(
+                DeviceState::Standby,
+                WarmingStandbyState {
+                    warmed: Some(warmed_tx),
+                },
+            )
+            .build();
+    device.init(DeviceState::Off).await.unwrap();
+    device.process_event(&DeviceEvent::PowerOn).await.unwrap();
+
+    // The transition has completed, but the spawned work has not
+    assert_eq!(device.current_state(), Some(DeviceState::Standby));
+    assert_eq!(device.context().lock().await.timeouts, 0);
+
+    warmed_rx.await.unwrap();
+    assert_eq!(device.context().lock().await.timeouts, 1);
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "tracing")]
+
+use async_hierarchical_fsm::{Response, StateMachineBuilder, Stateful, async_trait};
+use std::fmt::{Debug, Write};
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum State {
+    Off,
+    On,
+}
+
+#[derive(Debug, Clone)]
+enum Input {
+    Toggle,
+}
+
+struct ToggleState(State);
+
+#[async_trait]
+impl Stateful<State, (), Input> for ToggleState {
+    async fn on_enter(&mut self, _context: &mut ()) -> Response<State> {
+        Response::Handled
+    }
+
+    async fn on_event(&mut self, _event: &Input, _context: &mut ()) -> Response<State> {
+        Response::Transition(self.0.clone())
+    }
+
+    async fn on_exit(&mut self, _context: &mut ()) {}
+}
+
+// Layer that records every event's fields as `name=value` pairs
+struct Capture(Arc<Mutex<Vec<String>>>);
+
+struct FieldWriter(String);
+
+impl Visit for FieldWriter {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let _ = write!(self.0, "{}={:?} ", field.name(), value);
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Capture {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = FieldWriter(String::new());
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields.0.trim_end().to_string());
+    }
+}
+
+#[tokio::test]
+async fn test_transition_event_recorded() {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(Capture(Arc::clone(&records)));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut fsm = StateMachineBuilder::new(())
+        .state(State::Off, ToggleState(State::On))
+        .state(State::On, ToggleState(State::Off))
+        .build();
+    fsm.init(State::Off).await.unwrap();
+    fsm.process_event(&Input::Toggle).await.unwrap();
+
+    let records = records.lock().unwrap();
+    assert!(
+        records.contains(&"message=transition from=Off to=On".to_string()),
+        "{records:?}"
+    );
+}
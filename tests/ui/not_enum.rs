@@ -0,0 +1,8 @@
+use async_hierarchical_fsm::FsmState;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, FsmState)]
+struct State {
+    level: u8,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use async_hierarchical_fsm::FsmState;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, FsmState)]
+enum State {
+    Root,
+    Menu,
+    #[superstate(Root)]
+    #[superstate(Menu)]
+    Settings,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use async_hierarchical_fsm::FsmState;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, FsmState)]
+enum State {
+    Root,
+    #[superstate("Root")]
+    Menu,
+}
+
+fn main() {}